@@ -1,5 +1,5 @@
 //! scan
-use comport::prelude::*;
+use comport::{event::CancellationToken, prelude::*};
 use futures::StreamExt;
 use std::pin::pin;
 use tokio::task::JoinHandle;
@@ -24,22 +24,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Welcome message
     info!("Application service starting...");
 
+    // Create an abort signal
+    let abort = CancellationToken::new()?;
+
     // Channel to receive events
     let (tx, mut rx) = tokio::sync::mpsc::channel(128);
 
     // Create a stream to listen for events
-    let stream = comport::listen("COMPORT_DEMO").track(vec![("2FE3", "0100")])?;
+    let stream = comport::listen("COMPORT_DEMO")?.track_until(vec![("2FE3", "0100")], &abort)?;
     let jh: JoinHandle<Result<(), TrackingError>> = tokio::spawn(async move {
         let mut pinned = pin!(stream);
-        let mut count = 0usize;
         while let Some(msg) = pinned.next().await {
             if let Err(error) = tx.send(msg?).await {
                 error!(port = ?error, "failed to send port");
             }
-            count += 1;
-            if count == 8 {
-                break;
-            }
         }
         Ok(())
     });
@@ -54,9 +52,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     comport::rescan("COMPORT_DEMO")?;
     comport::rescan("COMPORT_DEMO")?;
     comport::rescan("COMPORT_DEMO")?;
+    let mut count = 0usize;
     while let Some(tracked) = rx.recv().await {
         info!(?tracked, "received scan");
+        count += 1;
+        if count == 8 {
+            break;
+        }
     }
+    abort.cancel();
 
     info!("demo over");
     jh.await??;
@@ -1,5 +1,5 @@
 //! track
-use comport::prelude::*;
+use comport::{event::CancellationToken, prelude::*};
 use futures::StreamExt;
 use std::pin::pin;
 use tokio::task::JoinHandle;
@@ -25,15 +25,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Application service starting...");
 
     // Create an abort signal
-    let (abort_set, abort) = tokio::sync::oneshot::channel();
+    let abort = CancellationToken::new()?;
 
     // Signal to receive a port
     let (tx, mut rx) = tokio::sync::mpsc::channel(128);
 
     // Create a stream to listen for events
-    let stream = comport::listen("comport demo")
-        .track(vec![("2FE3", "0100")])?
-        .take_until(abort);
+    let stream = comport::listen_until("comport demo", &abort)?.track(vec![("2FE3", "0100")]);
 
     // Spawn a task to listen for USB plug/unplug events
     let jh: JoinHandle<Result<(), TrackingError>> = tokio::spawn(async move {
@@ -53,14 +51,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut count = 0usize;
     while let Some(tracked) = rx.recv().await {
         info!(?tracked.port, "waiting for unplug event");
-        tracked.unplugged.await?;
+        tracked.unplugged.await.signaled()?;
         info!(?tracked.port, "received unplug event");
         count += 1;
         if count == 3 {
             break;
         }
     }
-    abort_set.send(()).unwrap();
+    abort.cancel();
     jh.await??;
     Ok(())
 }
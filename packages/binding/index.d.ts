@@ -6,8 +6,22 @@
 export interface PortMeta {
   vendor: string
   product: string
+  serial?: string
+  interface?: number
+  friendlyName?: string
+  description?: string
+  location?: string
+  locationPaths: Array<string>
+  kind: string
+  bluetoothAddress?: string
+  compositeParent?: string
+  compositeSerial?: string
+  inUse?: boolean
+  problem?: number
+  powerState?: string
 }
 export declare function scan(): Record<string, PortMeta>
+export declare function scanInUse(): Record<string, PortMeta>
 export declare function rescan(name: string): void
 export declare function listen(name: string, callback: (err:null | Error, event: any) => void): AbortHandle
 /**
@@ -15,6 +29,11 @@ export declare function listen(name: string, callback: (err:null | Error, event:
  *        emit a Track event which includes a Unplug promise
  */
 export declare function track(name: string, ids: Array<[string, string]>, callback: (err: null | Error, event: any) => void): AbortHandle
+/**
+ *      - Like track() but tracks the ports of the serial numbers, IE: the unit under test of a
+ *        rack of identical adapters
+ */
+export declare function trackSerials(name: string, serials: Array<string>, callback: (err: null | Error, event: any) => void): AbortHandle
 export class TrackedPort {
   port: string
   meta: PortMeta
@@ -310,11 +310,13 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { TrackedPort, AbortHandle, scan, rescan, listen, track } = nativeBinding
+const { TrackedPort, AbortHandle, scan, scanInUse, rescan, listen, track, trackSerials } = nativeBinding
 
 module.exports.TrackedPort = TrackedPort
 module.exports.AbortHandle = AbortHandle
 module.exports.scan = scan
+module.exports.scanInUse = scanInUse
 module.exports.rescan = rescan
 module.exports.listen = listen
 module.exports.track = track
+module.exports.trackSerials = trackSerials
@@ -2,14 +2,8 @@
 
 #[macro_use]
 extern crate napi_derive;
-use comport::{
-    event::{Receiver as Abort, Sender as AbortSet},
-    prelude::*,
-};
-use futures::{
-    future::{Either, Shared},
-    FutureExt, StreamExt,
-};
+use comport::{event::CancellationToken, prelude::*, InterfaceEvent, SessionEvent, VolumeEvent};
+use futures::{future::Either, Stream, StreamExt};
 use napi::{
     bindgen_prelude::ObjectFinalize,
     threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
@@ -22,8 +16,8 @@ use std::{collections::HashMap, pin::pin, thread::JoinHandle};
 pub struct TrackedPort {
     pub port: String,
     pub meta: PortMeta,
-    unplugged: Shared<Unplugged>,
-    abort: Shared<Abort>,
+    unplugged: Unplugged,
+    token: CancellationToken,
 }
 
 #[napi]
@@ -31,23 +25,26 @@ impl TrackedPort {
     #[napi]
     pub async fn unplugged(&self) -> Result<()> {
         let unplugged = self.unplugged.clone();
-        let abort = self.abort.clone();
-        match futures::future::select(unplugged, abort).await {
-            Either::Left((Ok(_), _)) => Ok(()),
-            Either::Left((Err(err), _)) => Err(Error::from_reason(err.to_string())),
-            Either::Right((Ok(_), _)) => Err(Error::from_reason("unplugged aborted".to_string())),
-            Either::Right((Err(err), _)) => Err(Error::from_reason(err.to_string())),
+        let cancelled = self
+            .token
+            .cancelled()
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+        match futures::future::select(unplugged, cancelled).await {
+            Either::Left((result, _)) => result
+                .signaled()
+                .map_err(|err| Error::from_reason(err.to_string())),
+            Either::Right(_) => Err(Error::from_reason("unplugged aborted")),
         }
     }
 }
 
 impl TrackedPort {
-    fn new(tracked: comport::prelude::TrackedPort, abort: Shared<Abort>) -> TrackedPort {
+    fn new(tracked: comport::prelude::TrackedPort, token: CancellationToken) -> TrackedPort {
         TrackedPort {
             port: tracked.port.to_str().unwrap_or("unknown").to_string(),
             meta: tracked.ids.into(),
-            unplugged: tracked.unplugged.shared(),
-            abort,
+            unplugged: tracked.unplugged,
+            token,
         }
     }
 }
@@ -55,8 +52,51 @@ impl TrackedPort {
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
 pub enum PlugEvent {
-    Plug { port: String, meta: PortMeta },
-    Unplug { port: String },
+    Plug {
+        port: String,
+        meta: PortMeta,
+    },
+    QueryRemove {
+        port: String,
+    },
+    QueryRemoveFailed {
+        port: String,
+    },
+    Unplug {
+        port: String,
+    },
+    InterfaceArrival {
+        path: String,
+        class: String,
+        kind: String,
+    },
+    InterfaceRemove {
+        path: String,
+        class: String,
+        kind: String,
+    },
+    VolumeArrival {
+        drives: Vec<String>,
+    },
+    VolumeRemove {
+        drives: Vec<String>,
+    },
+    SessionConnect {
+        session: u32,
+        remote: bool,
+    },
+    SessionDisconnect {
+        session: u32,
+        remote: bool,
+    },
+    Restarted,
+}
+
+fn drives(letters: Vec<char>) -> Vec<String> {
+    letters
+        .into_iter()
+        .map(|letter| format!("{letter}:"))
+        .collect()
 }
 
 impl From<comport::PlugEvent> for PlugEvent {
@@ -66,9 +106,44 @@ impl From<comport::PlugEvent> for PlugEvent {
                 port: port.to_str().unwrap_or("unknown").to_string(),
                 meta: meta.into(),
             },
+            comport::PlugEvent::QueryRemove(port) => PlugEvent::QueryRemove {
+                port: port.to_str().unwrap_or("unknown").to_string(),
+            },
+            comport::PlugEvent::QueryRemoveFailed(port) => PlugEvent::QueryRemoveFailed {
+                port: port.to_str().unwrap_or("unknown").to_string(),
+            },
             comport::PlugEvent::RemoveComplete(port) => PlugEvent::Unplug {
                 port: port.to_str().unwrap_or("unknown").to_string(),
             },
+            comport::PlugEvent::Interface(InterfaceEvent::Arrival(iface)) => {
+                PlugEvent::InterfaceArrival {
+                    path: iface.path.to_str().unwrap_or("unknown").to_string(),
+                    class: iface.class.to_string(),
+                    kind: format!("{:?}", iface.kind),
+                }
+            }
+            comport::PlugEvent::Interface(InterfaceEvent::RemoveComplete(iface)) => {
+                PlugEvent::InterfaceRemove {
+                    path: iface.path.to_str().unwrap_or("unknown").to_string(),
+                    class: iface.class.to_string(),
+                    kind: format!("{:?}", iface.kind),
+                }
+            }
+            comport::PlugEvent::Volume(VolumeEvent::Arrival(letters)) => PlugEvent::VolumeArrival {
+                drives: drives(letters),
+            },
+            comport::PlugEvent::Volume(VolumeEvent::RemoveComplete(letters)) => {
+                PlugEvent::VolumeRemove {
+                    drives: drives(letters),
+                }
+            }
+            comport::PlugEvent::Session(SessionEvent::Connect { session, remote }) => {
+                PlugEvent::SessionConnect { session, remote }
+            }
+            comport::PlugEvent::Session(SessionEvent::Disconnect { session, remote }) => {
+                PlugEvent::SessionDisconnect { session, remote }
+            }
+            comport::PlugEvent::Restarted => PlugEvent::Restarted,
         }
     }
 }
@@ -78,11 +153,37 @@ impl From<comport::PlugEvent> for PlugEvent {
 pub struct PortMeta {
     pub vendor: String,
     pub product: String,
+    pub serial: Option<String>,
+    pub interface: Option<u32>,
+    pub friendly_name: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub location_paths: Vec<String>,
+    pub kind: String,
+    pub bluetooth_address: Option<String>,
+    pub composite_parent: Option<String>,
+    pub composite_serial: Option<String>,
+    pub in_use: Option<bool>,
+    pub problem: Option<u32>,
+    pub power_state: Option<String>,
 }
 
 impl From<comport::PortMeta> for PortMeta {
     fn from(value: comport::PortMeta) -> Self {
         PortMeta {
+            serial: value.serial().map(str::to_string),
+            interface: value.interface().map(u32::from),
+            friendly_name: value.friendly_name().map(str::to_string),
+            description: value.description().map(str::to_string),
+            location: value.location().map(str::to_string),
+            location_paths: value.location_paths().to_vec(),
+            kind: format!("{:?}", value.kind()),
+            bluetooth_address: value.bluetooth_address().map(str::to_string),
+            composite_parent: value.composite_parent().map(str::to_string),
+            composite_serial: value.composite_serial(),
+            in_use: value.in_use(),
+            problem: value.problem(),
+            power_state: value.power_state().map(|state| format!("{state:?}")),
             vendor: value.vendor,
             product: value.product,
         }
@@ -91,7 +192,7 @@ impl From<comport::PortMeta> for PortMeta {
 
 #[napi(custom_finalize)]
 pub struct AbortHandle {
-    abort: Option<AbortSet>,
+    token: CancellationToken,
     join_handle: Option<JoinHandle<()>>,
 }
 
@@ -99,16 +200,12 @@ pub struct AbortHandle {
 impl AbortHandle {
     #[napi]
     pub fn abort(&mut self) -> Result<()> {
-        match self.abort.take() {
-            None => Ok(()),
-            Some(abort) => {
-                abort.set().map_err(|e| Error::from_reason(e.to_string()))?;
-                if let Some(jh) = self.join_handle.take() {
-                    let _result = jh.join();
-                }
-                Ok(())
-            }
+        // Cancelling more than once does nothing
+        self.token.cancel();
+        if let Some(jh) = self.join_handle.take() {
+            let _result = jh.join();
         }
+        Ok(())
     }
 }
 
@@ -118,8 +215,8 @@ impl ObjectFinalize for AbortHandle {
     }
 }
 
-fn abort_channel() -> Result<(AbortSet, Abort)> {
-    comport::event::oneshot().map_err(|e| Error::from_reason(e.to_string()))
+fn cancellation_token() -> Result<CancellationToken> {
+    CancellationToken::new().map_err(|e| Error::from_reason(e.to_string()))
 }
 
 #[napi]
@@ -132,6 +229,16 @@ pub fn scan() -> Result<HashMap<String, PortMeta>> {
     Ok(map)
 }
 
+#[napi]
+pub fn scan_in_use() -> Result<HashMap<String, PortMeta>> {
+    let map = comport::scan_in_use()
+        .map_err(|e| Error::from_reason(e.to_string()))?
+        .into_iter()
+        .filter_map(|(port, meta)| port.to_str().map(|s| (s.to_string(), PortMeta::from(meta))))
+        .collect();
+    Ok(map)
+}
+
 #[napi]
 pub fn rescan(name: String) -> Result<()> {
     comport::rescan(name).map_err(|e| Error::from_reason(e.to_string()))
@@ -146,11 +253,12 @@ pub fn listen(name: String, callback: JsFunction) -> Result<AbortHandle> {
             .map_err(|e| Error::from_reason(e.to_string()))
     })?;
 
-    // Get an abort handle to return to the caller
-    let (abort_set, abort) = abort_channel()?;
+    // Get a token for the abort handle returned to the caller
+    let token = cancellation_token()?;
 
     // Create an event stream
-    let stream = comport::listen(name).take_until(abort);
+    let stream =
+        comport::listen_until(name, &token).map_err(|e| Error::from_reason(e.to_string()))?;
 
     // Spawn a thread to listen for events
     let jh = std::thread::spawn(move || {
@@ -172,7 +280,7 @@ pub fn listen(name: String, callback: JsFunction) -> Result<AbortHandle> {
     });
     Ok(AbortHandle {
         join_handle: Some(jh),
-        abort: Some(abort_set),
+        token,
     })
 }
 
@@ -188,26 +296,59 @@ pub fn track(
     let tsfn: ThreadsafeFunction<TrackedPort> =
         callback.create_threadsafe_function(0, |cx| Ok(vec![cx.value]))?;
 
-    // Get an abort handle to return to the caller
-    // TODO trackedPort needs a clone of our abort signal
-    //      the future will race unplug vs abort
-    let (abort_set, abort) = abort_channel()?;
-    let abort = abort.shared();
+    // Get a token for the abort handle returned to the caller. Every tracked port races its
+    // unplug against the token
+    let token = cancellation_token()?;
 
     // Create an event stream
     let stream = comport::listen(name)
-        .take_until(abort.clone())
-        .track(ids)
+        .map_err(|e| Error::from_reason(e.to_string()))?
+        .track_until(ids, &token)
         .map_err(|e| Error::from_reason(e.to_string()))?;
+    Ok(spawn_tracking(stream, tsfn, token))
+}
 
-    // Spawn a thread to listen for events
+///      - Like track() but tracks the ports of the serial numbers, IE: the unit under test of a
+///        rack of identical adapters
+#[napi]
+pub fn track_serials(
+    name: String,
+    serials: Vec<String>,
+    #[napi(ts_arg_type = "(err: null | Error, event: any) => void")] callback: JsFunction,
+) -> Result<AbortHandle> {
+    // Create a callback to emit events into javascript land
+    let tsfn: ThreadsafeFunction<TrackedPort> =
+        callback.create_threadsafe_function(0, |cx| Ok(vec![cx.value]))?;
+
+    // Get a token for the abort handle returned to the caller
+    let token = cancellation_token()?;
+
+    // Create an event stream
+    let stream = comport::listen_until(name, &token)
+        .map_err(|e| Error::from_reason(e.to_string()))?
+        .track_serials(serials);
+    Ok(spawn_tracking(stream, tsfn, token))
+}
+
+/// Spawn a thread to emit the tracked ports of a stream into javascript land
+fn spawn_tracking<St>(
+    stream: St,
+    tsfn: ThreadsafeFunction<TrackedPort>,
+    token: CancellationToken,
+) -> AbortHandle
+where
+    St: Stream<Item = std::result::Result<comport::prelude::TrackedPort, TrackingError>>
+        + Send
+        + 'static,
+{
+    let theirs = token.clone();
     let jh = std::thread::spawn(move || {
         futures::executor::block_on(async {
             let mut pinned = pin!(stream);
             while let Some(ev) = pinned.next().await {
                 let _status = match ev {
                     Ok(ev) => tsfn.call(
-                        Ok(TrackedPort::new(ev, abort.clone())),
+                        Ok(TrackedPort::new(ev, theirs.clone())),
                         ThreadsafeFunctionCallMode::Blocking,
                     ),
                     Err(e) => tsfn.call(
@@ -218,8 +359,8 @@ pub fn track(
             }
         });
     });
-    Ok(AbortHandle {
+    AbortHandle {
         join_handle: Some(jh),
-        abort: Some(abort_set),
-    })
+        token,
+    }
 }
@@ -0,0 +1,144 @@
+//! cfgmgr
+//!
+//! Scan the connected COM ports with the Configuration Manager. Unlike the registry scan the
+//! Configuration Manager does not depend on the `COM Name Arbiter` and `SERIALCOMM` registry keys,
+//! which are laid out differently, or missing, in session 0 and on minimal installs such as
+//! Windows IoT.
+
+use crate::{
+    hkey::{Hkey, PortMeta, ScanResult},
+    wchar::{split_wide, to_wide},
+    wm::Registry,
+};
+use std::{collections::HashMap, ffi::OsString, io};
+use tracing::{trace, warn};
+use windows_sys::Win32::{
+    Devices::{
+        DeviceAndDriverInstallation::{
+            CM_Get_Device_Interface_ListW, CM_Get_Device_Interface_List_SizeW,
+            CM_Get_Device_Interface_PropertyW, CM_Locate_DevNodeW, CM_MapCrToWin32Err,
+            CM_Open_DevNode_Key, RegDisposition_OpenExisting, CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            CM_LOCATE_DEVNODE_NORMAL, CM_REGISTRY_HARDWARE, CONFIGRET, CR_BUFFER_SMALL, CR_SUCCESS,
+        },
+        Properties::{DEVPKEY_Device_InstanceId, DEVPROP_TYPE_STRING},
+    },
+    Foundation::ERROR_GEN_FAILURE,
+    System::Registry::KEY_READ,
+};
+
+/// Convert a Configuration Manager result into a result
+pub(crate) fn check(cr: CONFIGRET) -> io::Result<()> {
+    match cr {
+        CR_SUCCESS => Ok(()),
+        cr => {
+            let code = unsafe { CM_MapCrToWin32Err(cr, ERROR_GEN_FAILURE) };
+            Err(io::Error::from_raw_os_error(code as _))
+        }
+    }
+}
+
+/// The paths of the present COM port device interfaces
+fn interfaces() -> io::Result<Vec<String>> {
+    let class = Registry::COMPORT;
+    loop {
+        let mut len = 0;
+        // Safety: the size is read for the present interfaces of the class
+        check(unsafe {
+            CM_Get_Device_Interface_List_SizeW(
+                &mut len,
+                &class,
+                std::ptr::null(),
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            )
+        })?;
+        let mut buffer = vec![0u16; len as usize];
+        // Safety: the buffer holds len characters
+        let cr = unsafe {
+            CM_Get_Device_Interface_ListW(
+                &class,
+                std::ptr::null(),
+                buffer.as_mut_ptr(),
+                len,
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            )
+        };
+        match cr {
+            // An interface arrived between the calls
+            CR_BUFFER_SMALL => continue,
+            cr => check(cr)?,
+        }
+        break Ok(split_wide(&buffer).collect());
+    }
+}
+
+/// The device instance of a device interface
+fn instance(interface: &[u16]) -> io::Result<Vec<u16>> {
+    let mut ty = 0;
+    let mut buffer = vec![0u16; 256];
+    let mut len = (buffer.len() * 2) as u32;
+    // Safety: the buffer holds len bytes
+    check(unsafe {
+        CM_Get_Device_Interface_PropertyW(
+            interface.as_ptr(),
+            &DEVPKEY_Device_InstanceId,
+            &mut ty,
+            buffer.as_mut_ptr() as _,
+            &mut len,
+            0,
+        )
+    })?;
+    match ty {
+        DEVPROP_TYPE_STRING => Ok(buffer),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "device instance is not a string",
+        )),
+    }
+}
+
+/// The port name of a device interface. IE: COM3
+fn port_name(interface: &[u16]) -> ScanResult<OsString> {
+    let instance = instance(interface)?;
+    let mut devinst = 0;
+    // Safety: the instance is a nul terminated string
+    check(unsafe {
+        CM_Locate_DevNodeW(&mut devinst, instance.as_ptr(), CM_LOCATE_DEVNODE_NORMAL)
+    })?;
+    let mut key = 0;
+    // Safety: the key is owned by the Hkey once opened
+    check(unsafe {
+        CM_Open_DevNode_Key(
+            devinst,
+            KEY_READ,
+            0,
+            RegDisposition_OpenExisting,
+            &mut key,
+            CM_REGISTRY_HARDWARE,
+        )
+    })?;
+    let key = unsafe { Hkey::from_raw(key) };
+    Ok(key.get_value::<OsString>("PortName")?)
+}
+
+/// Scan the connected COM ports with the Configuration Manager
+pub fn scan() -> ScanResult<HashMap<OsString, PortMeta>> {
+    let mut ports = HashMap::new();
+    for interface in interfaces()? {
+        let wide = to_wide(&interface);
+        let port = match port_name(&wide) {
+            Ok(port) => port,
+            Err(error) => {
+                warn!(?error, interface, "unable to read port name");
+                continue;
+            }
+        };
+        match PortMeta::parse_registry(&interface.to_lowercase()) {
+            Some(meta) => {
+                trace!(?port, interface, "found com port");
+                ports.insert(port, meta.with_device_properties(&interface));
+            }
+            None => warn!(?port, interface, "unable to parse device interface"),
+        }
+    }
+    Ok(ports)
+}
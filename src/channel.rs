@@ -1,19 +1,34 @@
 //! channel
 
-use bytes::{Buf, BufMut, BytesMut};
-use crossbeam::queue::ArrayQueue;
-use futures::{AsyncRead, AsyncWrite, Stream};
+use crate::event::{Deadline, Event, EventError, EventInitialState, EventReset};
+use bytes::{Buf, Bytes, BytesMut};
+use crossbeam::queue::SegQueue;
+use futures::{AsyncBufRead, AsyncRead, AsyncWrite, Future, Stream};
 use parking_lot::Mutex;
 use pin_project_lite::pin_project;
 use std::{
-    io,
+    fmt, io,
     os::windows::io::{AsRawHandle, RawHandle},
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock, Weak,
+    },
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
+use tracing::warn;
 use windows_sys::Win32::{Foundation::FALSE, System::IO::CancelIoEx};
 
+/// Byte level events of the channel. Only compiled with the `trace-channel` feature, because the
+/// events are emitted for every buffer
+macro_rules! trace_channel {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace-channel")]
+        tracing::trace!($($arg)*);
+    };
+}
+
 pub trait WakeHandle: AsRawHandle {
     fn wake(&self) -> io::Result<()> {
         let result = unsafe { CancelIoEx(self.as_raw_handle() as _, std::ptr::null()) };
@@ -40,68 +55,439 @@ impl AsRawHandle for RawWakeHandle {
 
 impl WakeHandle for RawWakeHandle {}
 
+// Safety: the handle is only passed to CancelIoEx, which may be called from any thread
+unsafe impl Send for RawWakeHandle {}
+unsafe impl Sync for RawWakeHandle {}
+
+/// A channel which holds at most capacity buffers in each direction. See [`TaskQueue::grow`]
 pub fn bounded<W>(handle: W, capacity: usize) -> (TaskQueue<W>, ThreadQueue)
 where
-    W: WakeHandle,
+    W: WakeHandle + Send + Sync + 'static,
 {
-    let state = Arc::new(State {
-        task: ArrayQueue::new(capacity),
-        thread: ArrayQueue::new(capacity),
-        read_waker: Mutex::new(None),
-        write_waker: Mutex::new(None),
+    assert!(capacity > 0, "capacity must be non-zero");
+    let handle = Arc::new(handle);
+    let ready = Arc::new(OnceLock::new());
+    let rx = Arc::new(Rx {
+        queue: Queue::new(capacity),
+        waker: Mutex::new(None),
+        taps: Mutex::new(Vec::new()),
+        full: AtomicBool::new(false),
+        ready: Arc::clone(&ready),
+        thread: ThreadWake(Arc::clone(&handle) as _),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        bytes: AtomicU64::new(0),
+        overflows: AtomicU64::new(0),
     });
-    let task = TaskQueue { state, handle };
-    let thread = ThreadQueue(Arc::clone(&task.state));
+    let tx = Arc::new(Tx {
+        queue: Queue::new(capacity),
+        priority: Queue::new(capacity),
+        waker: Mutex::new(None),
+        ready,
+        error: Mutex::new(None),
+        failed: AtomicBool::new(false),
+        bytes: AtomicU64::new(0),
+        overflows: AtomicU64::new(0),
+    });
+    let thread = ThreadQueue {
+        rx: RxSender(Arc::clone(&rx)),
+        tx: TxReceiver(Arc::clone(&tx)),
+    };
+    let task = TaskQueue { rx, tx, handle };
     (task, thread)
 }
 
+/// A channel which never overflows. Memory grows for as long as a side is not consumed
+pub fn unbounded<W>(handle: W) -> (TaskQueue<W>, ThreadQueue)
+where
+    W: WakeHandle + Send + Sync + 'static,
+{
+    bounded(handle, usize::MAX)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum TaskError {
     #[error("io error => {0}")]
     Io(#[from] io::Error),
     #[error("failed to send data to thread, the queue is full")]
-    Overflow(BytesMut),
+    Overflow(Bytes),
 }
 
-/// Shared state between the task and the thread
+/// A queue holding at most capacity items. The capacity may grow while the queue is shared
 #[derive(Debug)]
-struct State {
+struct Queue<T> {
+    inner: SegQueue<T>,
+    capacity: AtomicUsize,
+    /// The most items the queue held at once
+    high_water: AtomicUsize,
+}
+
+impl<T> Queue<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            inner: SegQueue::new(),
+            capacity: AtomicUsize::new(capacity),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    /// Push an item, or return the item if the queue is full
+    fn push(&self, item: T) -> Result<(), T> {
+        if self.inner.len() >= self.capacity.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        self.inner.push(item);
+        self.high_water
+            .fetch_max(self.inner.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Push an item even if the queue is full. IE: to signal the queue is closed
+    fn force_push(&self, item: T) {
+        self.inner.push(item)
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.inner.pop()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Acquire)
+    }
+
+    fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// The queue may take another item
+    fn has_room(&self) -> bool {
+        self.inner.len() < self.capacity.load(Ordering::Acquire)
+    }
+
+    /// Raise the capacity. The capacity never shrinks
+    fn grow(&self, capacity: usize) {
+        self.capacity.fetch_max(capacity, Ordering::AcqRel);
+    }
+}
+
+/// Wakes the thread side of the channel with the [`WakeHandle`] of the queue
+#[derive(Clone)]
+struct ThreadWake(Arc<dyn WakeHandle + Send + Sync>);
+
+impl fmt::Debug for ThreadWake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ThreadWake").finish()
+    }
+}
+
+/// The receive direction. Bytes read by the thread are pushed to the task
+#[derive(Debug)]
+struct Rx {
     /// The queue consumed by the task
-    task: ArrayQueue<Option<io::Result<BytesMut>>>,
-    /// The queue consumed by the thread
-    thread: ArrayQueue<Option<BytesMut>>,
+    queue: Queue<Option<io::Result<Bytes>>>,
     /// Let the task know its time to read more bytes
-    read_waker: Mutex<Option<Waker>>,
+    waker: Mutex<Option<Waker>>,
+    /// Subscribers which observe the bytes pushed to the task. See [`TaskQueue::subscribe`]
+    taps: Mutex<Vec<Weak<Tap>>>,
+    /// A push was rejected because the queue was full. The thread holds on to the bytes until
+    /// the task makes room, see [`ThreadQueue::wait_room`]
+    full: AtomicBool,
+    /// Shared with the transmit direction, so a thread blocked in [`ThreadQueue::wait_room`]
+    /// sees writes as well as room
+    ready: Arc<OnceLock<Event>>,
+    /// Let a thread which does not block on the event know the task made room. IE: a reactor
+    thread: ThreadWake,
+    /// The live [`RxSender`]s. The task's reads end when the last sender is dropped
+    senders: AtomicUsize,
+    /// The end of the stream was pushed to the task
+    closed: AtomicBool,
+    /// Bytes pushed to the task, reported by [`Stats`]
+    bytes: AtomicU64,
+    /// Pushes rejected because the queue was full, reported by [`Stats`]
+    overflows: AtomicU64,
+}
+
+impl Rx {
+    /// Push an item to the task, or return the item if the queue is full
+    fn push(&self, item: io::Result<Bytes>) -> Result<(), io::Result<Bytes>> {
+        let len = item.as_ref().map_or(0, Bytes::len);
+        let item = match self.queue.push(Some(item)) {
+            Ok(_) => return Ok(self.pushed(len)),
+            Err(item) => item,
+        };
+        // Flag the queue as full before trying again, so a task which pops in between still
+        // lets the thread know there is room. Pairs with the fence in make_room
+        self.full.store(true, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        match self.queue.push(item) {
+            Ok(_) => Ok(self.pushed(len)),
+            Err(item) => {
+                trace_channel!(len, "task queue overflow");
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+                Err(item.unwrap())
+            }
+        }
+    }
+
+    /// Count the bytes pushed to the task and wake the task
+    fn pushed(&self, len: usize) {
+        trace_channel!(len, pending = self.queue.len(), "enqueued for task");
+        self.bytes.fetch_add(len as _, Ordering::Relaxed);
+        if let Some(waker) = self.waker.lock().as_ref() {
+            trace_channel!("waking reader");
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Let the thread know the task made room after a push was rejected
+    fn make_room(&self) {
+        // Pairs with the fence in push, so either the thread sees the room or we see the flag
+        atomic::fence(Ordering::SeqCst);
+        if self.full.swap(false, Ordering::SeqCst) {
+            trace_channel!("waking thread with room");
+            if let Some(Err(error)) = self.ready.get().map(Event::set) {
+                warn!(?error, "failed to notify channel thread");
+            }
+            if let Err(error) = self.thread.0.wake() {
+                warn!(?error, "failed to wake channel thread");
+            }
+        }
+    }
+
+    /// Copy an item pushed to the task to every subscriber. A slow subscriber loses buffers
+    /// rather than stalling the task
+    fn tee<F>(&self, item: F)
+    where
+        F: Fn() -> Option<io::Result<Bytes>>,
+    {
+        self.taps.lock().retain(|tap| match tap.upgrade() {
+            Some(tap) => {
+                tap.send(item());
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Let the task and every subscriber know no more bytes will arrive
+    fn close(&self) {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        trace_channel!("closing task queue");
+        self.queue.force_push(None);
+        self.tee(|| None);
+        if let Some(waker) = self.waker.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// The transmit direction. Bytes written by the task are pushed to the thread
+#[derive(Debug)]
+struct Tx {
+    /// The queue consumed by the thread
+    queue: Queue<Option<Bytes>>,
+    /// Control frames consumed by the thread ahead of the thread queue
+    priority: Queue<Bytes>,
     /// Let the task know its ok to write more bytes
-    write_waker: Mutex<Option<Waker>>,
-    // TODO need `event` to let the thread know its ok to send more bytes
+    waker: Mutex<Option<Waker>>,
+    /// Let the thread know there are bytes to send. Created when the thread first waits
+    ready: Arc<OnceLock<Event>>,
+    /// The error which failed the writes, until a writer reports it
+    error: Mutex<Option<io::Error>>,
+    /// The thread failed to write and will not consume the queue anymore
+    failed: AtomicBool,
+    /// Bytes pushed to the thread, reported by [`Stats`]
+    bytes: AtomicU64,
+    /// Pushes rejected because the queue was full, reported by [`Stats`]
+    overflows: AtomicU64,
+}
+
+impl Tx {
+    /// The error which failed the writes. The first writer to ask gets the error reported by the
+    /// thread, and every writer after gets [`io::ErrorKind::BrokenPipe`]
+    fn take_error(&self) -> Option<io::Error> {
+        match self.failed.load(Ordering::Acquire) {
+            false => None,
+            true => Some(
+                self.error
+                    .lock()
+                    .take()
+                    .unwrap_or_else(|| io::ErrorKind::BrokenPipe.into()),
+            ),
+        }
+    }
+
+    /// Buffers waiting for the thread in either lane
+    fn pending_writes(&self) -> usize {
+        self.priority.len() + self.queue.len()
+    }
+
+    /// Count the bytes pushed to the thread and signal the thread
+    fn written(&self, len: usize) {
+        trace_channel!(len, pending = self.pending_writes(), "enqueued for thread");
+        self.bytes.fetch_add(len as _, Ordering::Relaxed);
+        self.notify_thread();
+    }
+
+    fn overflow(&self) {
+        trace_channel!("thread queue overflow");
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Let the task know the thread consumed from the queue
+    fn wake_writer(&self) {
+        if let Some(waker) = self.waker.lock().as_ref() {
+            trace_channel!("waking writer");
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Register the task to be woken when the thread consumes from the queue
+    fn register_writer(&self, cx: &mut Context<'_>) {
+        let mut waker = self.waker.lock();
+        let new_waker = cx.waker();
+        *waker = match waker.take() {
+            None => Some(new_waker.clone()),
+            Some(old_waker) => match old_waker.will_wake(cx.waker()) {
+                false => Some(new_waker.clone()),
+                true => Some(old_waker),
+            },
+        };
+    }
+
+    /// Let the thread know no more bytes will be written
+    fn close(&self) {
+        trace_channel!("closing thread queue");
+        self.queue.force_push(None);
+        self.notify_thread();
+    }
+
+    /// Signal a thread blocked in [`TxReceiver::wait`] after pushing to the thread queue
+    fn notify_thread(&self) {
+        // Pairs with the fence in wait, so either the thread sees the item or we see the event
+        atomic::fence(Ordering::SeqCst);
+        trace_channel!(waiting = self.ready.get().is_some(), "notifying thread");
+        if let Some(Err(error)) = self.ready.get().map(Event::set) {
+            warn!(?error, "failed to notify channel thread");
+        }
+    }
+}
+
+/// A subscriber's copy of the items pushed to the task, read at the subscriber's own pace
+#[derive(Debug)]
+struct Tap {
+    queue: Queue<Option<io::Result<Bytes>>>,
+    waker: Mutex<Option<Waker>>,
+    /// Buffers dropped because the subscriber fell behind
+    lagged: AtomicU64,
+}
+
+impl Tap {
+    fn send(&self, item: Option<io::Result<Bytes>>) {
+        match item {
+            // The end of the stream must reach every subscriber
+            None => self.queue.force_push(None),
+            item => {
+                if self.queue.push(item).is_err() {
+                    self.lagged.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        if let Some(waker) = self.waker.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
 }
 
 /// TODO get rid of generic, use a mock wake handle or a real RawHandle impl to CancelIo
 /// TODO think about race condition via breaking a read loop. Read thread + Write thread? or Async?
 /// or some how manage read / write in same thread
 pub struct TaskQueue<W> {
-    state: Arc<State>,
-    handle: W,
+    rx: Arc<Rx>,
+    tx: Arc<Tx>,
+    /// Shared with writers so every write wakes the thread
+    handle: Arc<W>,
 }
 
 impl<W: WakeHandle> TaskQueue<W> {
-    /// Push data to the thread side of the queue
+    /// Push data to the thread side of the queue. Frozen [`Bytes`] are passed on without a copy
     /// TODO deprecate this infavor of AsyncWrite implementation (supports throttle w/ poll api)
-    pub fn push(&self, bytes: BytesMut) -> Result<(), TaskError> {
-        self.state
-            .thread
+    pub fn push<B: Into<Bytes>>(&self, bytes: B) -> Result<(), TaskError> {
+        let bytes = bytes.into();
+        let len = bytes.len();
+        self.tx
+            .queue
             .push(Some(bytes))
             .map_err(|bytes| match bytes {
-                Some(bytes) => TaskError::Overflow(bytes),
+                Some(bytes) => {
+                    self.tx.overflow();
+                    TaskError::Overflow(bytes)
+                }
                 _ => unreachable!(),
             })?;
+        self.tx.written(len);
+        self.handle.wake().map_err(TaskError::from)
+    }
+
+    /// Push a small control frame (IE: an abort or a keepalive) which the thread consumes ahead of
+    /// any bulk writes already in the queue
+    pub fn push_priority<B: Into<Bytes>>(&self, bytes: B) -> Result<(), TaskError> {
+        let bytes = bytes.into();
+        let len = bytes.len();
+        self.tx.priority.push(bytes).map_err(|bytes| {
+            self.tx.overflow();
+            TaskError::Overflow(bytes)
+        })?;
+        self.tx.written(len);
         self.handle.wake().map_err(TaskError::from)
     }
 
+    /// The most buffers each side of the queue holds
+    pub fn capacity(&self) -> usize {
+        self.tx.queue.capacity()
+    }
+
+    /// Raise the capacity of both sides of the queue, so a burst from a device or a writer does
+    /// not overflow while the consumer is stalled. The capacity never shrinks
+    pub fn grow(&self, capacity: usize) {
+        self.rx.queue.grow(capacity);
+        self.tx.queue.grow(capacity);
+        // Writers and a thread waiting for room may continue
+        self.tx.wake_writer();
+        self.rx.make_room();
+    }
+
+    /// A handle to the traffic statistics of the channel
+    pub fn stats(&self) -> Stats {
+        Stats {
+            rx: Arc::clone(&self.rx),
+            tx: Arc::clone(&self.tx),
+        }
+    }
+
     /// TODO deprecate (use AsyncRead)
     pub fn listen(&self) -> TaskStream {
-        TaskStream(Arc::clone(&self.state))
+        TaskStream(Source::Task(Arc::clone(&self.rx)))
+    }
+
+    /// Observe the bytes pushed to the task alongside the main consumer. IE: for a protocol
+    /// sniffer or logger. Each subscriber reads from its own cursor and only sees bytes pushed
+    /// after it subscribed. A subscriber which falls behind loses buffers, see
+    /// [`TaskStream::lagged`]
+    pub fn subscribe(&self) -> TaskStream {
+        let tap = Arc::new(Tap {
+            queue: Queue::new(self.rx.queue.capacity()),
+            waker: Mutex::new(None),
+            lagged: AtomicU64::new(0),
+        });
+        self.rx.taps.lock().push(Arc::downgrade(&tap));
+        TaskStream(Source::Tap(tap))
     }
 
     /// TODO deprecate, TaskQueue should implement AsyncRead and AsyncWrite
@@ -110,100 +496,352 @@ impl<W: WakeHandle> TaskQueue<W> {
     }
 
     /// TODO deprecate TaskQueue should implement AsyncRead and AsyncWrite
-    pub fn writer(&self) -> Writer {
-        Writer(Arc::clone(&self.state))
+    pub fn writer(&self) -> Writer
+    where
+        W: Send + Sync + 'static,
+    {
+        Writer {
+            tx: Arc::clone(&self.tx),
+            handle: Arc::clone(&self.handle) as _,
+            drain: None,
+            closed: false,
+        }
     }
 }
 
 impl<W> Drop for TaskQueue<W> {
     fn drop(&mut self) {
-        self.state.thread.force_push(None);
+        self.tx.close();
+    }
+}
+
+/// A handle to the traffic statistics of a channel, so backpressure can be detected in the field.
+/// Totals are kept for the life of the channel
+#[derive(Clone)]
+pub struct Stats {
+    rx: Arc<Rx>,
+    tx: Arc<Tx>,
+}
+
+impl Stats {
+    /// Bytes pushed from the thread to the task. IE: read from the device
+    pub fn bytes_read(&self) -> u64 {
+        self.rx.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes pushed from the task to the thread. IE: written to the device
+    pub fn bytes_written(&self) -> u64 {
+        self.tx.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The most buffers waiting for the task at once
+    pub fn task_high_water(&self) -> usize {
+        self.rx.queue.high_water()
+    }
+
+    /// The most buffers waiting for the thread at once
+    pub fn thread_high_water(&self) -> usize {
+        self.tx.queue.high_water()
+    }
+
+    /// Pushes rejected because a queue was full. A [`Writer`] waiting for room is not counted
+    pub fn overflows(&self) -> u64 {
+        self.rx.overflows.load(Ordering::Relaxed) + self.tx.overflows.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stats")
+            .field("bytes_read", &self.bytes_read())
+            .field("bytes_written", &self.bytes_written())
+            .field("task_high_water", &self.task_high_water())
+            .field("thread_high_water", &self.thread_high_water())
+            .field("overflows", &self.overflows())
+            .finish()
     }
 }
 
+/// The thread side of a channel. See [`ThreadQueue::split`] to close the directions separately
 #[derive(Clone)]
-pub struct ThreadQueue(Arc<State>);
+pub struct ThreadQueue {
+    rx: RxSender,
+    tx: TxReceiver,
+}
 
 impl ThreadQueue {
     /// Push data to the task side of the queue
-    pub fn push_ok(&self, bytes: BytesMut) -> Result<(), BytesMut> {
-        match self.0.task.push(Some(Ok(bytes))) {
-            Err(Some(Ok(bytes))) => Err(bytes),
+    pub fn push_ok<B: Into<Bytes>>(&self, bytes: B) -> Result<(), Bytes> {
+        self.rx.push_ok(bytes)
+    }
+
+    /// Push data to the task side of the queue
+    pub fn push_err(&self, err: io::Error) -> Result<(), io::Error> {
+        self.rx.push_err(err)
+    }
+
+    /// A handle to the traffic statistics of the channel
+    pub fn stats(&self) -> Stats {
+        Stats {
+            rx: Arc::clone(&self.rx.0),
+            tx: Arc::clone(&self.tx.0),
+        }
+    }
+
+    /// See [`TxReceiver::wait`]
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<(), EventError> {
+        self.tx.wait(timeout)
+    }
+
+    /// Block until the task makes room for a push which was rejected because the queue was full,
+    /// pushes data, or closes the queue. IE: so a thread stops reading the device while the task
+    /// falls behind, rather than dropping the bytes. Fails with [`EventError::Timeout`] if
+    /// neither happens within the timeout
+    pub fn wait_room(&self, timeout: Option<Duration>) -> Result<(), EventError> {
+        let ready = self.tx.ready()?;
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            // Pairs with the fences in notify_thread and make_room
+            atomic::fence(Ordering::SeqCst);
+            if self.rx.0.queue.has_room() || self.tx.0.pending_writes() > 0 {
+                break Ok(());
+            }
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            ready.wait(remaining)?;
+        }
+    }
+
+    /// See [`TxReceiver::pop`]
+    pub fn pop(&self) -> Option<Option<Bytes>> {
+        self.tx.pop()
+    }
+
+    /// See [`TxReceiver::pop_blocking`]
+    pub fn pop_blocking(&self, timeout: Option<Duration>) -> Result<Option<Bytes>, EventError> {
+        self.tx.pop_blocking(timeout)
+    }
+
+    /// See [`TxReceiver::collect`]
+    pub fn collect(&self) -> (Bytes, bool) {
+        self.tx.collect()
+    }
+
+    /// See [`TxReceiver::fail`]
+    pub fn fail(&self, error: io::Error) {
+        self.tx.fail(error)
+    }
+
+    /// Split into the receive and transmit directions, so they can be closed separately. IE:
+    /// drop every clone of the [`RxSender`] to end the task's reads while the [`TxReceiver`] keeps
+    /// flushing writes
+    pub fn split(self) -> (RxSender, TxReceiver) {
+        (self.rx, self.tx)
+    }
+}
+
+/// The thread side of the receive direction. Dropping the last clone of the sender ends the
+/// task's reads
+pub struct RxSender(Arc<Rx>);
+
+impl Clone for RxSender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
+        RxSender(Arc::clone(&self.0))
+    }
+}
+
+impl RxSender {
+    /// Push data to the task side of the queue
+    pub fn push_ok<B: Into<Bytes>>(&self, bytes: B) -> Result<(), Bytes> {
+        let bytes = bytes.into();
+        let copy = bytes.clone();
+        match self.0.push(Ok(bytes)) {
+            Err(Ok(bytes)) => Err(bytes),
             Err(_) => unreachable!(),
-            Ok(_) => match self.0.read_waker.lock().as_ref() {
-                None => Ok(()),
-                Some(waker) => {
-                    waker.wake_by_ref();
-                    Ok(())
-                }
-            },
+            Ok(_) => {
+                self.0.tee(|| Some(Ok(copy.clone())));
+                Ok(())
+            }
         }
     }
 
     /// Push data to the task side of the queue
     pub fn push_err(&self, err: io::Error) -> Result<(), io::Error> {
-        match self.0.task.push(Some(Err(err))) {
-            Err(Some(Err(e))) => Err(e),
+        // io errors can not be cloned, so subscribers get an error of the same kind
+        let (kind, message) = (err.kind(), err.to_string());
+        match self.0.push(Err(err)) {
+            Err(Err(e)) => Err(e),
             Err(_) => unreachable!(),
-            Ok(_) => match self.0.read_waker.lock().as_ref() {
-                None => Ok(()),
-                Some(waker) => {
-                    waker.wake_by_ref();
-                    Ok(())
-                }
-            },
+            Ok(_) => {
+                self.0
+                    .tee(|| Some(Err(io::Error::new(kind, message.clone()))));
+                Ok(())
+            }
         }
     }
+}
+
+impl Drop for RxSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.close();
+        }
+    }
+}
 
-    /// Thread side consumer
-    pub fn pop(&self) -> Option<Option<BytesMut>> {
-        if self.0.thread.len() > 0 {
-            if let Some(waker) = self.0.write_waker.lock().as_ref() {
-                waker.wake_by_ref();
+/// The thread side of the transmit direction
+#[derive(Clone)]
+pub struct TxReceiver(Arc<Tx>);
+
+impl TxReceiver {
+    /// Block until the task pushes data or closes the queue, instead of polling [`Self::pop`].
+    /// Fails with [`EventError::Timeout`] if nothing arrives within the timeout
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<(), EventError> {
+        let ready = self.ready()?;
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            // Pairs with the fence in notify_thread
+            atomic::fence(Ordering::SeqCst);
+            if self.0.pending_writes() > 0 {
+                break Ok(());
             }
-            self.0.thread.pop()
+            // The event may still be set by an item which was already popped, so loop around
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            ready.wait(remaining)?;
+        }
+    }
+
+    /// The event which signals the thread. Created when the thread first waits
+    fn ready(&self) -> Result<&Event, EventError> {
+        match self.0.ready.get() {
+            Some(ready) => Ok(ready),
+            None => {
+                let event = Event::anonymous(EventReset::Automatic, EventInitialState::Unset)?;
+                Ok(self.0.ready.get_or_init(|| event))
+            }
+        }
+    }
+
+    /// Thread side consumer. Control frames are consumed first, see [`TaskQueue::push_priority`]
+    pub fn pop(&self) -> Option<Option<Bytes>> {
+        if self.0.pending_writes() > 0 {
+            // Pop before waking so a writer waiting for the queue to drain sees the change
+            let item = match self.0.priority.pop() {
+                Some(bytes) => Some(Some(bytes)),
+                None => self.0.queue.pop(),
+            };
+            trace_channel!(
+                len = item
+                    .as_ref()
+                    .map(|item| item.as_ref().map_or(0, Bytes::len)),
+                "dequeued by thread"
+            );
+            self.0.wake_writer();
+            item
         } else {
             None
         }
     }
 
-    /// Collect all the bytes into a single buffer
-    pub fn collect(&self) -> (BytesMut, bool) {
-        let mut ret = BytesMut::new();
+    /// Report a failed write to the task. Pending and future writes fail with the error, so the
+    /// task does not wait on a queue which is never consumed. Only the first error is kept
+    pub fn fail(&self, error: io::Error) {
+        trace_channel!(?error, "thread failed to write");
+        {
+            let mut slot = self.0.error.lock();
+            if !self.0.failed.swap(true, Ordering::AcqRel) {
+                *slot = Some(error);
+            }
+        }
+        self.0.wake_writer();
+    }
+
+    /// Block until the task pushes data or closes the queue, then pop. Returns None when the task
+    /// closed the queue, and fails with [`EventError::Timeout`] if nothing arrives within the
+    /// timeout
+    pub fn pop_blocking(&self, timeout: Option<Duration>) -> Result<Option<Bytes>, EventError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            if let Some(item) = self.pop() {
+                break Ok(item);
+            }
+            // Another consumer may take the item we were woken for, so wait again
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            self.wait(remaining)?;
+        }
+    }
+
+    /// Collect all the bytes into a single buffer, control frames first. A lone buffer is
+    /// returned without a copy
+    pub fn collect(&self) -> (Bytes, bool) {
+        let mut chunks = Vec::new();
         let mut done = false;
-        while let Some(stream) = self.0.thread.pop() {
+        while let Some(bytes) = self.0.priority.pop() {
+            chunks.push(bytes);
+        }
+        while let Some(stream) = self.0.queue.pop() {
             match stream {
-                Some(bytes) => ret.put(bytes),
+                Some(bytes) => chunks.push(bytes),
                 None => {
                     done = true;
                     break;
                 }
             }
         }
+        let ret = match chunks.len() {
+            1 => chunks.pop().unwrap(),
+            _ => Bytes::from(chunks.concat()),
+        };
+        trace_channel!(len = ret.len(), done, "collected by thread");
         if ret.len() > 0 {
-            if let Some(waker) = self.0.write_waker.lock().as_ref() {
-                waker.wake_by_ref();
-            }
+            self.0.wake_writer();
         }
         (ret, done)
     }
 }
 
-impl Drop for ThreadQueue {
-    fn drop(&mut self) {
-        self.0.task.force_push(None);
-    }
+/// The queue a [`TaskStream`] reads from
+#[derive(Clone, Debug)]
+enum Source {
+    /// The queue consumed by the task
+    Task(Arc<Rx>),
+    /// A subscriber's copy of the queue consumed by the task
+    Tap(Arc<Tap>),
 }
 
 #[derive(Clone, Debug)]
-pub struct TaskStream(Arc<State>);
+pub struct TaskStream(Source);
+
+impl TaskStream {
+    /// Buffers dropped because a subscriber fell behind. The main stream never drops buffers
+    pub fn lagged(&self) -> u64 {
+        match &self.0 {
+            Source::Task(_) => 0,
+            Source::Tap(tap) => tap.lagged.load(Ordering::Relaxed),
+        }
+    }
+}
+
 impl Stream for TaskStream {
-    type Item = io::Result<BytesMut>;
+    type Item = io::Result<Bytes>;
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.0.task.pop() {
-            Some(item) => Poll::Ready(item),
+        let (queue, waker) = match &self.0 {
+            Source::Task(rx) => (&rx.queue, &rx.waker),
+            Source::Tap(tap) => (&tap.queue, &tap.waker),
+        };
+        match queue.pop() {
+            Some(item) => {
+                trace_channel!(end = item.is_none(), "dequeued by task");
+                if let Source::Task(rx) = &self.0 {
+                    rx.make_room();
+                }
+                Poll::Ready(item)
+            }
             None => {
-                let mut waker = self.0.read_waker.lock();
+                let mut waker = waker.lock();
                 let new_waker = cx.waker();
                 *waker = match waker.take() {
                     None => Some(new_waker.clone()),
@@ -227,7 +865,7 @@ pin_project! {
         Incomplete {
             #[pin]
             inner: TaskStream,
-            current: Option<io::Result<BytesMut>>,
+            current: Option<io::Result<Bytes>>,
         },
         Complete,
     }
@@ -242,6 +880,38 @@ impl From<TaskStream> for Reader {
     }
 }
 
+impl Reader {
+    /// Take the next buffer from the queue without copying it. Bytes left over from
+    /// [`AsyncRead`] or [`AsyncBufRead`] are returned first
+    pub fn poll_next_bytes(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Bytes>>> {
+        match self.as_mut().project() {
+            ReaderProj::Incomplete { inner, current } => {
+                match current.take() {
+                    Some(Ok(next)) if next.is_empty() => {}
+                    Some(next) => return Poll::Ready(Some(next)),
+                    None => {}
+                }
+                match futures::ready!(inner.poll_next(cx)) {
+                    None => {
+                        self.project_replace(Reader::Complete);
+                        Poll::Ready(None)
+                    }
+                    Some(next) => Poll::Ready(Some(next)),
+                }
+            }
+            ReaderProj::Complete => Poll::Ready(None),
+        }
+    }
+
+    /// Wait for the next buffer from the queue. See [`Self::poll_next_bytes`]
+    pub async fn next_bytes(&mut self) -> Option<io::Result<Bytes>> {
+        futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_next_bytes(cx)).await
+    }
+}
+
 impl AsyncRead for Reader {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -289,51 +959,461 @@ impl AsyncRead for Reader {
     }
 }
 
-/// TODO impl on TaskQueue which has access to WakeHandle
-pub struct Writer(Arc<State>);
+/// Lend the buffer at the front of the queue, so lines and delimited frames are read without
+/// copying through a [`futures::io::BufReader`]
+impl AsyncBufRead for Reader {
+    fn poll_fill_buf(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        loop {
+            match self.as_mut().project() {
+                ReaderProj::Incomplete { inner, current } => match current {
+                    Some(Ok(next)) if !next.is_empty() => break,
+                    Some(Ok(_)) => {
+                        current.take();
+                    }
+                    Some(Err(_)) => match current.take() {
+                        Some(Err(e)) => return Poll::Ready(Err(e)),
+                        _ => unreachable!(),
+                    },
+                    None => match futures::ready!(inner.poll_next(cx)) {
+                        None => {
+                            self.as_mut().project_replace(Reader::Complete);
+                        }
+                        Some(next) => *current = Some(next),
+                    },
+                },
+                ReaderProj::Complete => return Poll::Ready(Ok(&[])),
+            }
+        }
+        match self.project() {
+            ReaderProj::Incomplete {
+                current: Some(Ok(next)),
+                ..
+            } => Poll::Ready(Ok(&next[..])),
+            _ => unreachable!(),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        if let ReaderProj::Incomplete {
+            current: Some(Ok(next)),
+            ..
+        } = self.project()
+        {
+            next.advance(amt.min(next.len()));
+        }
+    }
+}
+
+/// Close waits for the thread to consume pending writes. See [`Writer::close_after_drain`]
+#[derive(Debug)]
+struct Drain {
+    timeout: Option<Duration>,
+    deadline: Option<Deadline>,
+}
+
+/// The task side writer. Every write wakes the thread with the [`WakeHandle`] of the queue
+pub struct Writer {
+    tx: Arc<Tx>,
+    handle: Arc<dyn WakeHandle + Send + Sync>,
+    drain: Option<Drain>,
+    /// The terminator was pushed to the thread
+    closed: bool,
+}
+
+impl Writer {
+    /// Have close wait until the thread has consumed every pending write before signaling EOF.
+    /// If the timeout elapses first the thread is signaled anyway and close fails with
+    /// [`io::ErrorKind::TimedOut`]
+    pub fn close_after_drain(mut self, timeout: Option<Duration>) -> Self {
+        self.drain = Some(Drain {
+            timeout,
+            deadline: None,
+        });
+        self
+    }
+
+    /// Register to be woken when the thread consumes from the queue
+    fn register(&self, cx: &mut Context<'_>) {
+        self.tx.register_writer(cx);
+    }
+
+    /// Resolves when the thread queue is empty, or with an error if the drain timed out or the
+    /// thread failed to write
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(drain) = self.drain.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        if let Some(error) = self.tx.take_error() {
+            return Poll::Ready(Err(error));
+        }
+        if self.tx.pending_writes() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(timeout) = drain.timeout {
+            let deadline = match drain.deadline.as_mut() {
+                Some(deadline) => deadline,
+                None => drain.deadline.insert(Deadline::new(timeout)?),
+            };
+            if Pin::new(deadline).poll(cx).is_ready() {
+                warn!(
+                    pending = self.tx.pending_writes(),
+                    "timed out draining writes"
+                );
+                return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+            }
+        }
+        self.register(cx);
+        // The thread may have drained the queue before we registered
+        match self.tx.pending_writes() == 0 {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        }
+    }
+}
+
 impl AsyncWrite for Writer {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        // TODO the writer needs the handle to call wake
-        match self.0.thread.push(Some(BytesMut::from(buf))) {
-            Ok(_) => Poll::Ready(Ok(buf.len())),
+        if let Some(error) = self.tx.take_error() {
+            return Poll::Ready(Err(error));
+        }
+        match self.tx.queue.push(Some(Bytes::copy_from_slice(buf))) {
+            Ok(_) => {
+                self.tx.written(buf.len());
+                Poll::Ready(self.handle.wake().map(|_| buf.len()))
+            }
             Err(_bytes) => {
-                let mut waker = self.0.write_waker.lock();
-                let new_waker = cx.waker();
-                *waker = match waker.take() {
-                    None => Some(new_waker.clone()),
-                    Some(old_waker) => match old_waker.will_wake(cx.waker()) {
-                        false => Some(new_waker.clone()),
-                        true => Some(old_waker),
-                    },
-                };
-                Poll::Pending
+                self.register(cx);
+                // The thread may have failed before we registered
+                match self.tx.take_error() {
+                    Some(error) => Poll::Ready(Err(error)),
+                    None => Poll::Pending,
+                }
             }
         }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        if self.0.thread.is_empty() {
+        if let Some(error) = self.tx.take_error() {
+            Poll::Ready(Err(error))
+        } else if self.tx.pending_writes() == 0 {
             Poll::Ready(Ok(()))
         } else {
-            let mut waker = self.0.write_waker.lock();
-            let new_waker = cx.waker();
-            *waker = match waker.take() {
-                None => Some(new_waker.clone()),
-                Some(old_waker) => match old_waker.will_wake(cx.waker()) {
-                    false => Some(new_waker.clone()),
-                    true => Some(old_waker),
-                },
-            };
-            Poll::Pending
+            self.register(cx);
+            // The thread may have failed before we registered
+            match self.tx.take_error() {
+                Some(error) => Poll::Ready(Err(error)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Ok(()));
         }
+        let result = futures::ready!(this.poll_drain(cx));
+        this.closed = true;
+        this.tx.close();
+        Poll::Ready(result.and(this.handle.wake()))
+    }
+}
+
+/// How a [`ThrottledWriter`] paces the bytes it passes on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pace {
+    /// Pass on at most this many bytes per second
+    BytesPerSecond(u32),
+    /// Pass on at most chunk bytes, then wait for the delay before passing on more
+    ChunkDelay { chunk: usize, delay: Duration },
+}
+
+/// Pace writes for devices which lose bytes when written faster than their UART drains
+///
+/// The pace is kept over the life of the writer, so a coarse timer resolution delays individual
+/// chunks but does not lower the average rate.
+#[derive(Debug)]
+pub struct ThrottledWriter<W = Writer> {
+    inner: W,
+    pace: Pace,
+    /// The earliest time the next chunk may be passed on
+    next: Option<Instant>,
+    delay: Option<Deadline>,
+}
+
+impl<W> ThrottledWriter<W> {
+    pub fn new(inner: W, pace: Pace) -> Self {
+        Self {
+            inner,
+            pace,
+            next: None,
+            delay: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.0.thread.force_push(None);
-        Poll::Ready(Ok(()))
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// The most bytes passed on at once
+    fn chunk(&self) -> usize {
+        match self.pace {
+            // A tenth of a second of bytes keeps the pace smooth
+            Pace::BytesPerSecond(rate) => (rate as usize / 10).max(1),
+            Pace::ChunkDelay { chunk, .. } => chunk.max(1),
+        }
+    }
+
+    /// Push back the time the next chunk may be passed on after passing on n bytes
+    fn advance(&mut self, n: usize) {
+        let now = Instant::now();
+        let (from, wait) = match self.pace {
+            Pace::BytesPerSecond(rate) => (
+                self.next.map_or(now, |next| next.max(now)),
+                Duration::from_secs_f64(n as f64 / rate.max(1) as f64),
+            ),
+            Pace::ChunkDelay { delay, .. } => (now, delay),
+        };
+        self.next = Some(from + wait);
+    }
+
+    /// Resolves when the next chunk may be passed on
+    fn poll_paced(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                futures::ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+            }
+            let now = Instant::now();
+            match self.next {
+                Some(next) if next > now => self.delay = Some(Deadline::new(next - now)?),
+                _ => break Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ThrottledWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_paced(cx))?;
+        let len = buf.len().min(this.chunk());
+        let n = futures::ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        this.advance(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// The mock device is serviced by a thread which blocks on [`ThreadQueue::wait`], so there is
+/// no io to cancel
+struct MockWake;
+
+impl AsRawHandle for MockWake {
+    fn as_raw_handle(&self) -> RawHandle {
+        std::ptr::null_mut()
+    }
+}
+
+impl WakeHandle for MockWake {
+    fn wake(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The behavior of a mock device, shared by the [`MockDevice`] and the device thread
+#[derive(Debug, Default)]
+struct Script {
+    /// Requests and the responses sent when the request is written
+    responses: Vec<(Bytes, Bytes)>,
+    /// How long the device takes to respond
+    latency: Duration,
+    /// Every byte written by the application
+    written: BytesMut,
+    /// Written bytes which have not matched a request yet
+    unmatched: BytesMut,
+}
+
+impl Script {
+    /// Consume the earliest request found in the unmatched bytes and return its response
+    fn next_response(&mut self) -> Option<Bytes> {
+        let (end, response) = self
+            .responses
+            .iter()
+            .filter_map(|(request, response)| {
+                self.unmatched
+                    .windows(request.len().max(1))
+                    .position(|window| window == &request[..])
+                    .map(|start| (start + request.len(), response))
+            })
+            .min_by_key(|(end, _)| *end)?;
+        let response = response.clone();
+        self.unmatched.advance(end);
+        Some(response)
+    }
+}
+
+/// Create a mock serial device for testing protocol logic without hardware. The [`MockPort`]
+/// reads and writes like a [`crate::ComPort`], and the [`MockDevice`] scripts what the device
+/// sends back. Dropping the device ends the port's reads, as if the device was unplugged
+pub fn mock() -> (MockPort, MockDevice) {
+    let (queue, thread) = unbounded(MockWake);
+    let script = Arc::new(Mutex::new(Script::default()));
+    let device = MockDevice {
+        queue: thread.clone(),
+        script: Arc::clone(&script),
+    };
+    std::thread::spawn(move || mock_dispatcher(&thread, &script));
+    let port = MockPort {
+        reader: queue.reader(),
+        writer: queue.writer(),
+        queue,
+    };
+    (port, device)
+}
+
+/// Service the device side of the mock until the application side is closed
+fn mock_dispatcher(queue: &ThreadQueue, script: &Mutex<Script>) {
+    loop {
+        let bytes = match queue.pop_blocking(None) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => break,
+            Err(error) => {
+                warn!(?error, "mock device failed to wait");
+                break;
+            }
+        };
+        let (responses, latency) = {
+            let mut script = script.lock();
+            script.written.extend_from_slice(&bytes);
+            script.unmatched.extend_from_slice(&bytes);
+            let responses = std::iter::from_fn(|| script.next_response()).collect::<Vec<_>>();
+            (responses, script.latency)
+        };
+        for response in responses {
+            std::thread::sleep(latency);
+            let _ = queue.push_ok(response);
+        }
+    }
+}
+
+/// The application side of a [`mock`] device
+pub struct MockPort {
+    reader: Reader,
+    writer: Writer,
+    queue: TaskQueue<MockWake>,
+}
+
+impl MockPort {
+    /// A handle to the traffic statistics of the mock
+    pub fn stats(&self) -> Stats {
+        self.queue.stats()
+    }
+}
+
+impl AsyncRead for MockPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for MockPort {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
+
+impl AsyncWrite for MockPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+/// The device side of a [`mock`] device
+pub struct MockDevice {
+    queue: ThreadQueue,
+    script: Arc<Mutex<Script>>,
+}
+
+impl MockDevice {
+    /// Send the response each time the application writes the request
+    pub fn respond<Req, Res>(&self, request: Req, response: Res)
+    where
+        Req: Into<Bytes>,
+        Res: Into<Bytes>,
+    {
+        let mut script = self.script.lock();
+        script.responses.push((request.into(), response.into()));
+    }
+
+    /// Delay every scripted response
+    pub fn set_latency(&self, latency: Duration) {
+        self.script.lock().latency = latency;
+    }
+
+    /// Send bytes the application did not ask for
+    pub fn send<B: Into<Bytes>>(&self, bytes: B) {
+        let _ = self.queue.push_ok(bytes);
+    }
+
+    /// Fail the application's next read. IE: to simulate a line error
+    pub fn inject_error(&self, error: io::Error) {
+        let _ = self.queue.push_err(error);
+    }
+
+    /// Every byte written by the application so far
+    pub fn written(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.script.lock().written)
+    }
+}
+
+impl Drop for MockDevice {
+    /// The device thread keeps its sender until the application closes the port, so the reads
+    /// are ended here as if the device was unplugged
+    fn drop(&mut self) {
+        self.queue.rx.0.close();
     }
 }
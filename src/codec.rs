@@ -0,0 +1,476 @@
+//! codec
+//!
+//! Frame the raw byte stream of a port into messages. A [`Framed`] adapter drives a codec over
+//! any [`AsyncRead`]/[`AsyncWrite`], such as a [`crate::ComPort`] or the [`crate::channel`]
+//! reader and writer, and exposes the messages as a [`Stream`] and a [`Sink`].
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{ready, AsyncRead, AsyncWrite, Sink, Stream};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The number of bytes read from the inner reader at a time
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Encoded bytes queued beyond this many are flushed before accepting another item
+const BACKPRESSURE_BOUNDARY: usize = 8192;
+
+/// SLIP frame delimiter
+const SLIP_END: u8 = 0xC0;
+
+/// SLIP escape byte
+const SLIP_ESC: u8 = 0xDB;
+
+/// Escaped [`SLIP_END`]
+const SLIP_ESC_END: u8 = 0xDC;
+
+/// Escaped [`SLIP_ESC`]
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Decode messages from a buffer of bytes read from a port
+pub trait Decoder {
+    type Item;
+    type Error: From<io::Error>;
+
+    /// Decode a message from the front of the buffer, consuming its bytes. Returns None when more
+    /// bytes are needed
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Decode a message once the reader has no more bytes. Defaults to [`Decoder::decode`]
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode(src)
+    }
+}
+
+/// Encode messages into a buffer of bytes to be written to a port
+pub trait Encoder<Item> {
+    type Error: From<io::Error>;
+
+    /// Append an encoded message to the buffer
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error>;
+}
+
+/// Errors of the built in codecs
+#[derive(thiserror::Error, Debug)]
+pub enum CodecError {
+    #[error("io error => {0}")]
+    Io(#[from] io::Error),
+    /// The frame was discarded because no delimiter arrived within the maximum length
+    #[error("frame exceeds maximum length of {0} bytes")]
+    MaxLengthExceeded(usize),
+    #[error("invalid utf8 => {0}")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+    /// The frame was discarded because it was not encoded correctly
+    #[error("invalid frame")]
+    InvalidFrame,
+}
+
+/// Split a frame terminated by a delimiter from the front of a buffer. The delimiter is consumed
+/// and not returned. When no delimiter arrives within max_length bytes the frame is reported and
+/// then discarded up to the next delimiter
+fn split_delimited(
+    src: &mut BytesMut,
+    delimiter: u8,
+    max_length: usize,
+    discarding: &mut bool,
+) -> Result<Option<BytesMut>, CodecError> {
+    loop {
+        let end = src.len().min(max_length.saturating_add(1));
+        match (src[..end].iter().position(|b| *b == delimiter), *discarding) {
+            (Some(idx), true) => {
+                src.advance(idx + 1);
+                *discarding = false;
+            }
+            (None, true) => {
+                src.advance(end);
+                if src.is_empty() {
+                    break Ok(None);
+                }
+            }
+            (Some(idx), false) => {
+                let mut frame = src.split_to(idx + 1);
+                frame.truncate(idx);
+                break Ok(Some(frame));
+            }
+            (None, false) if end > max_length => {
+                *discarding = true;
+                break Err(CodecError::MaxLengthExceeded(max_length));
+            }
+            (None, false) => break Ok(None),
+        }
+    }
+}
+
+/// Newline delimited UTF-8 lines. A trailing carriage return is stripped from each line
+#[derive(Clone, Debug)]
+pub struct LinesCodec {
+    max_length: usize,
+    discarding: bool,
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self {
+            max_length: usize::MAX,
+            discarding: false,
+        }
+    }
+}
+
+impl LinesCodec {
+    /// Lines of any length
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject lines longer than max_length bytes, not including the newline
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<String>, CodecError> {
+        match split_delimited(src, b'\n', self.max_length, &mut self.discarding)? {
+            None => Ok(None),
+            Some(mut line) => {
+                if line.last() == Some(&b'\r') {
+                    line.truncate(line.len() - 1);
+                }
+                Ok(Some(String::from_utf8(line.to_vec())?))
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<String>, CodecError> {
+        match self.decode(src)? {
+            Some(line) => Ok(Some(line)),
+            None if src.is_empty() || self.discarding => {
+                src.clear();
+                Ok(None)
+            }
+            // The final line is not terminated
+            None => {
+                let line = src.split();
+                Ok(Some(String::from_utf8(line.to_vec())?))
+            }
+        }
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for LinesCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let line = item.as_ref();
+        dst.reserve(line.len() + 1);
+        dst.put_slice(line.as_bytes());
+        dst.put_u8(b'\n');
+        Ok(())
+    }
+}
+
+/// Consistent overhead byte stuffing. Frames are delimited by a zero byte which never appears
+/// within an encoded frame
+#[derive(Clone, Debug)]
+pub struct CobsCodec {
+    max_length: usize,
+    discarding: bool,
+}
+
+impl Default for CobsCodec {
+    fn default() -> Self {
+        Self {
+            max_length: usize::MAX,
+            discarding: false,
+        }
+    }
+}
+
+impl CobsCodec {
+    /// Frames of any length
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject frames longer than max_length encoded bytes, not including the delimiter
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Decoder for CobsCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, CodecError> {
+        loop {
+            match split_delimited(src, 0, self.max_length, &mut self.discarding)? {
+                None => break Ok(None),
+                // Consecutive delimiters
+                Some(frame) if frame.is_empty() => continue,
+                Some(frame) => break cobs_decode(&frame).map(Some),
+            }
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for CobsCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let src = item.as_ref();
+        dst.reserve(src.len() + src.len() / 254 + 2);
+        let mut code_idx = dst.len();
+        let mut code = 1u8;
+        dst.put_u8(0);
+        for (idx, byte) in src.iter().enumerate() {
+            if *byte != 0 {
+                dst.put_u8(*byte);
+                code += 1;
+            }
+            // Close the block on a zero, or when the block is full and more data follows
+            if *byte == 0 || (code == 0xFF && idx + 1 < src.len()) {
+                dst[code_idx] = code;
+                code_idx = dst.len();
+                code = 1;
+                dst.put_u8(0);
+            }
+        }
+        dst[code_idx] = code;
+        dst.put_u8(0);
+        Ok(())
+    }
+}
+
+/// Decode a single COBS frame without its delimiter
+fn cobs_decode(src: &[u8]) -> Result<BytesMut, CodecError> {
+    let mut dst = BytesMut::with_capacity(src.len());
+    let mut idx = 0;
+    while idx < src.len() {
+        let code = src[idx] as usize;
+        let end = idx + code;
+        if code == 0 || end > src.len() {
+            return Err(CodecError::InvalidFrame);
+        }
+        dst.put_slice(&src[idx + 1..end]);
+        idx = end;
+        if code < 0xFF && idx < src.len() {
+            dst.put_u8(0);
+        }
+    }
+    Ok(dst)
+}
+
+/// Serial line internet protocol framing. [RFC 1055](https://www.rfc-editor.org/rfc/rfc1055)
+#[derive(Clone, Debug)]
+pub struct SlipCodec {
+    max_length: usize,
+    discarding: bool,
+}
+
+impl Default for SlipCodec {
+    fn default() -> Self {
+        Self {
+            max_length: usize::MAX,
+            discarding: false,
+        }
+    }
+}
+
+impl SlipCodec {
+    /// Frames of any length
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject frames longer than max_length encoded bytes, not including the delimiter
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Decoder for SlipCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, CodecError> {
+        loop {
+            match split_delimited(src, SLIP_END, self.max_length, &mut self.discarding)? {
+                None => break Ok(None),
+                // Line noise is flushed with an END before each frame
+                Some(frame) if frame.is_empty() => continue,
+                Some(frame) => break slip_decode(&frame).map(Some),
+            }
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for SlipCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let src = item.as_ref();
+        dst.reserve(src.len() + 2);
+        dst.put_u8(SLIP_END);
+        for byte in src {
+            match *byte {
+                SLIP_END => dst.put_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                SLIP_ESC => dst.put_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                byte => dst.put_u8(byte),
+            }
+        }
+        dst.put_u8(SLIP_END);
+        Ok(())
+    }
+}
+
+/// Decode a single SLIP frame without its delimiter
+fn slip_decode(src: &[u8]) -> Result<BytesMut, CodecError> {
+    let mut dst = BytesMut::with_capacity(src.len());
+    let mut iter = src.iter();
+    while let Some(byte) = iter.next() {
+        match *byte {
+            SLIP_ESC => match iter.next() {
+                Some(&SLIP_ESC_END) => dst.put_u8(SLIP_END),
+                Some(&SLIP_ESC_ESC) => dst.put_u8(SLIP_ESC),
+                _ => return Err(CodecError::InvalidFrame),
+            },
+            byte => dst.put_u8(byte),
+        }
+    }
+    Ok(dst)
+}
+
+/// A [`Stream`] of decoded messages and a [`Sink`] of messages to encode, over a byte stream
+#[derive(Debug)]
+pub struct Framed<T, C> {
+    inner: T,
+    codec: C,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    eof: bool,
+}
+
+impl<T, C> Framed<T, C> {
+    pub fn new(inner: T, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Bytes read but not yet decoded
+    pub fn read_buffer(&self) -> &BytesMut {
+        &self.read_buf
+    }
+
+    /// Release the inner byte stream. Buffered bytes are lost
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, C> Stream for Framed<T, C>
+where
+    T: AsyncRead + Unpin,
+    C: Decoder + Unpin,
+{
+    type Item = Result<C::Item, C::Error>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            let decoded = match this.eof {
+                false => this.codec.decode(&mut this.read_buf),
+                true => this.codec.decode_eof(&mut this.read_buf),
+            };
+            match decoded {
+                Err(error) => break Poll::Ready(Some(Err(error))),
+                Ok(Some(item)) => break Poll::Ready(Some(Ok(item))),
+                Ok(None) if this.eof => break Poll::Ready(None),
+                Ok(None) => {}
+            }
+            match ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk)) {
+                Err(error) => break Poll::Ready(Some(Err(error.into()))),
+                Ok(0) => this.eof = true,
+                Ok(n) => this.read_buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+}
+
+impl<T, C> Framed<T, C>
+where
+    T: AsyncWrite + Unpin,
+{
+    /// Write out the encoded bytes and flush the inner writer
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            match ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => self.write_buf.advance(n),
+            }
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+}
+
+impl<T, C, Item> Sink<Item> for Framed<T, C>
+where
+    T: AsyncWrite + Unpin,
+    C: Encoder<Item> + Unpin,
+{
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        match this.write_buf.len() < BACKPRESSURE_BOUNDARY {
+            true => Poll::Ready(Ok(())),
+            false => this.poll_flush_buf(cx).map_err(Into::into),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.codec.encode(item, &mut this.write_buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_buf(cx).map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx).map_err(Into::into)
+    }
+}
@@ -0,0 +1,128 @@
+//! comdb
+//!
+//! Ghost COM ports. Windows reserves a port number in the COM port database for every device
+//! which was ever installed, and the reservation remains after the device is removed. Machines
+//! used for device testing accumulate reservations, and new devices are given ever higher port
+//! numbers. Releasing a reservation requires an elevated process.
+
+use crate::{
+    hkey::{arbiter, connected, ScanResult, Win32Error},
+    name::ComPortName,
+    RegistryError,
+};
+use std::{collections::HashSet, ffi::OsString, io};
+use tracing::trace;
+use windows_sys::Win32::{
+    Devices::SerialCommunication::{
+        ComDBClose, ComDBGetCurrentPortUsage, ComDBOpen, ComDBReleasePort, CDB_REPORT_BYTES, HCOMDB,
+    },
+    Foundation::ERROR_SUCCESS,
+};
+
+/// Convert a COM port database status into a result
+fn check(status: i32) -> Result<(), Win32Error> {
+    match status as u32 {
+        ERROR_SUCCESS => Ok(()),
+        status => Err(Win32Error(status)),
+    }
+}
+
+/// A RAII guard for the COM port database
+struct ComDb(HCOMDB);
+
+impl ComDb {
+    fn open() -> Result<ComDb, Win32Error> {
+        let mut handle = 0;
+        check(unsafe { ComDBOpen(&mut handle) })?;
+        Ok(ComDb(handle))
+    }
+
+    /// The reserved port names
+    fn reserved(&self) -> Result<Vec<ComPortName>, Win32Error> {
+        let mut len = 0;
+        // Safety: the first call only reads the number of ports in the database
+        check(unsafe {
+            ComDBGetCurrentPortUsage(self.0, std::ptr::null_mut(), 0, CDB_REPORT_BYTES, &mut len)
+        })?;
+        let mut usage = vec![0u8; len as usize];
+        // Safety: the buffer holds a byte for every port
+        check(unsafe {
+            ComDBGetCurrentPortUsage(
+                self.0,
+                usage.as_mut_ptr(),
+                len,
+                CDB_REPORT_BYTES,
+                std::ptr::null_mut(),
+            )
+        })?;
+        Ok(reserved_ports(&usage))
+    }
+
+    fn release(&self, name: ComPortName) -> Result<(), Win32Error> {
+        check(unsafe { ComDBReleasePort(self.0, name.number()) })
+    }
+}
+
+impl Drop for ComDb {
+    fn drop(&mut self) {
+        let _ = unsafe { ComDBClose(self.0) };
+    }
+}
+
+/// The reserved port names of the usage of the database. The first byte is COM1, and a port is
+/// reserved when its byte is not zero
+pub(crate) fn reserved_ports(usage: &[u8]) -> Vec<ComPortName> {
+    usage
+        .iter()
+        .zip(1..)
+        .filter(|(byte, _)| **byte != 0)
+        .map(|(_, number)| ComPortName::new(number))
+        .collect()
+}
+
+/// A port reserved in the COM port database without a present device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GhostPort {
+    /// The name of the reserved port
+    pub name: ComPortName,
+    /// The device interface of the last device of the port in the COM Name Arbiter. None when
+    /// the arbiter does not know the port, IE: the port was reserved by a driver directly
+    pub interface: Option<OsString>,
+}
+
+/// The names of the present ports
+fn present() -> ScanResult<HashSet<ComPortName>> {
+    Ok(connected()?
+        .into_iter()
+        .filter_map(|port| ComPortName::try_from(port).ok())
+        .collect())
+}
+
+/// The ports which are reserved but absent, in port order. Unlike [`crate::scan`], which only
+/// lists present ports, a device picker can show these ports as "reserved"
+pub fn ghosts() -> ScanResult<Vec<GhostPort>> {
+    let present = present()?;
+    let interfaces = arbiter()?;
+    Ok(ComDb::open()?
+        .reserved()?
+        .into_iter()
+        .filter(|name| !present.contains(name))
+        .map(|name| GhostPort {
+            interface: interfaces.get(&OsString::from(name)).cloned(),
+            name,
+        })
+        .collect())
+}
+
+/// Release the reservation of an absent port, so that the number can be given to a new device.
+/// The port of a present device is not released. Requires an elevated process
+pub fn release(name: ComPortName) -> ScanResult<()> {
+    if present()?.contains(&name) {
+        return Err(RegistryError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} is present"),
+        )));
+    }
+    trace!(%name, "releasing ghost port");
+    Ok(ComDb::open()?.release(name)?)
+}
@@ -0,0 +1,566 @@
+//! comm
+//!
+//! Typed wrappers around the windows communications resource API
+
+use std::{error, fmt, io, os::windows::io::AsRawHandle, time::Duration};
+use tracing::warn;
+use windows_sys::Win32::{
+    Devices::Communication::{
+        ClearCommError, EscapeCommFunction, GetCommModemStatus, GetCommProperties, GetCommState,
+        GetCommTimeouts, PurgeComm, SetCommMask, SetCommState, SetCommTimeouts, SetupComm,
+        CE_BREAK, CE_FRAME, CE_OVERRUN, CE_RXOVER, CE_RXPARITY, CLRDTR, CLRRTS, COMMPROP,
+        COMMTIMEOUTS, DCB, EVENPARITY, EV_BREAK, EV_CTS, EV_DSR, EV_ERR, EV_RING, EV_RLSD,
+        MARKPARITY, MS_CTS_ON, MS_DSR_ON, MS_RING_ON, MS_RLSD_ON, NOPARITY, ODDPARITY,
+        ONE5STOPBITS, ONESTOPBIT, PARITY_EVEN, PARITY_MARK, PARITY_NONE, PARITY_ODD, PARITY_SPACE,
+        PURGE_RXABORT, PURGE_RXCLEAR, PURGE_TXABORT, PURGE_TXCLEAR, SETDTR, SETRTS, SPACEPARITY,
+        STOPBITS_10, STOPBITS_15, STOPBITS_20, TWOSTOPBITS,
+    },
+    Foundation::FALSE,
+    System::WindowsProgramming::{
+        BAUD_075, BAUD_110, BAUD_115200, BAUD_1200, BAUD_128K, BAUD_134_5, BAUD_14400, BAUD_150,
+        BAUD_1800, BAUD_19200, BAUD_2400, BAUD_300, BAUD_38400, BAUD_4800, BAUD_56K, BAUD_57600,
+        BAUD_600, BAUD_7200, BAUD_9600, BAUD_USER, PST_FAX, PST_LAT, PST_MODEM, PST_NETWORK_BRIDGE,
+        PST_PARALLELPORT, PST_RS232, PST_RS422, PST_RS423, PST_RS449, PST_SCANNER,
+        PST_TCPIP_TELNET, PST_UNSPECIFIED, PST_X25, SP_BAUD, SP_DATABITS, SP_HANDSHAKING,
+        SP_PARITY, SP_PARITY_CHECK, SP_RLSD, SP_STOPBITS,
+    },
+};
+
+/// [DCB](https://learn.microsoft.com/en-us/windows/win32/api/winbase/ns-winbase-dcb) bitfield
+/// flags we manage. The remaining flags are left as reported by the driver
+pub(crate) mod dcb {
+    /// Binary mode. Windows does not support non binary mode transfers so this must be set
+    pub const BINARY: u32 = 1 << 0;
+    /// Parity checking is enabled
+    pub const PARITY: u32 = 1 << 1;
+}
+
+/// The parity scheme to be used
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Parity {
+    #[default]
+    None = NOPARITY,
+    Odd = ODDPARITY,
+    Even = EVENPARITY,
+    Mark = MARKPARITY,
+    Space = SPACEPARITY,
+}
+
+/// The number of bits in the bytes transmitted and received
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DataBits {
+    Five = 5,
+    Six = 6,
+    Seven = 7,
+    #[default]
+    Eight = 8,
+}
+
+/// The number of stop bits to be used
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StopBits {
+    #[default]
+    One = ONESTOPBIT,
+    OnePointFive = ONE5STOPBITS,
+    Two = TWOSTOPBITS,
+}
+
+/// Read the current control settings of a communications device
+///
+/// [GetCommState](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommstate)
+pub(crate) fn get_state<H: AsRawHandle>(handle: &H) -> io::Result<DCB> {
+    // Safety: DCB is a plain C struct and is valid when zeroed
+    let mut state: DCB = unsafe { std::mem::zeroed() };
+    state.DCBlength = std::mem::size_of::<DCB>() as _;
+    match unsafe { GetCommState(handle.as_raw_handle() as _, &mut state) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(state),
+    }
+}
+
+/// Configure a communications device with the control settings in a DCB
+///
+/// [SetCommState](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-setcommstate)
+pub(crate) fn set_state<H: AsRawHandle>(handle: &H, state: &DCB) -> io::Result<()> {
+    match unsafe { SetCommState(handle.as_raw_handle() as _, state) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Extended functions which may be performed by a communications device
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Escape {
+    /// Sends the DTR (data-terminal-ready) signal
+    SetDtr = SETDTR,
+    /// Clears the DTR (data-terminal-ready) signal
+    ClrDtr = CLRDTR,
+    /// Sends the RTS (request-to-send) signal
+    SetRts = SETRTS,
+    /// Clears the RTS (request-to-send) signal
+    ClrRts = CLRRTS,
+}
+
+/// Direct a communications device to perform an extended function. This call does not
+/// interfere with overlapped I/O in flight on the same handle
+///
+/// [EscapeCommFunction](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-escapecommfunction)
+pub(crate) fn escape<H: AsRawHandle>(handle: &H, func: Escape) -> io::Result<()> {
+    match unsafe { EscapeCommFunction(handle.as_raw_handle() as _, func as _) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+bitflags::bitflags! {
+    /// The state of the modem control-register lines
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct ModemStatus: u32 {
+        /// The CTS (clear-to-send) signal is on
+        const CTS = MS_CTS_ON;
+        /// The DSR (data-set-ready) signal is on
+        const DSR = MS_DSR_ON;
+        /// The RI (ring indicator) signal is on
+        const RI = MS_RING_ON;
+        /// The CD (carrier detect) signal is on. Also known as RLSD (receive-line-signal-detect)
+        const CD = MS_RLSD_ON;
+    }
+}
+
+/// Read the modem control-register values
+///
+/// [GetCommModemStatus](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommmodemstatus)
+pub(crate) fn modem_status<H: AsRawHandle>(handle: &H) -> io::Result<ModemStatus> {
+    let mut status = 0;
+    match unsafe { GetCommModemStatus(handle.as_raw_handle() as _, &mut status) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(ModemStatus::from_bits_truncate(status)),
+    }
+}
+
+bitflags::bitflags! {
+    /// Line events which may be monitored on a communications device
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct LineEvent: u32 {
+        /// The CTS (clear-to-send) signal changed state
+        const CTS = EV_CTS;
+        /// The DSR (data-set-ready) signal changed state
+        const DSR = EV_DSR;
+        /// A ring indicator was detected
+        const RING = EV_RING;
+        /// The RLSD (receive-line-signal-detect) signal changed state. IE: carrier detect
+        const RLSD = EV_RLSD;
+        /// A break was detected on input
+        const BREAK = EV_BREAK;
+        /// A line-status error occurred (frame, overrun or parity)
+        const ERR = EV_ERR;
+    }
+}
+
+/// Specify the set of events to be monitored for a communications device
+///
+/// [SetCommMask](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-setcommmask)
+pub(crate) fn set_mask<H: AsRawHandle>(handle: &H, mask: LineEvent) -> io::Result<()> {
+    match unsafe { SetCommMask(handle.as_raw_handle() as _, mask.bits()) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Time-out parameters for a communications device. All durations have millisecond resolution.
+///
+/// The default times out a read after 100ms when no bytes arrive, and otherwise returns as soon as
+/// any bytes are available. Writes never time out.
+///
+/// [COMMTIMEOUTS](https://learn.microsoft.com/en-us/windows/win32/api/winbase/ns-winbase-commtimeouts)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// The maximum time allowed to elapse before the arrival of the next byte. [`Timeouts::MAX`]
+    /// combined with a zero read total returns immediately with the bytes already received
+    pub read_interval: Duration,
+    /// Multiplied by the requested number of bytes to calculate the total read time-out
+    pub read_total_multiplier: Duration,
+    /// Added to the product of the read multiplier and requested bytes
+    pub read_total_constant: Duration,
+    /// Multiplied by the number of bytes to be written to calculate the total write time-out
+    pub write_total_multiplier: Duration,
+    /// Added to the product of the write multiplier and number of bytes to be written
+    pub write_total_constant: Duration,
+}
+
+impl Timeouts {
+    /// The MAXDWORD time-out value, which has special meaning for the read parameters
+    pub const MAX: Duration = Duration::from_millis(u32::MAX as _);
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            read_interval: Timeouts::MAX,
+            read_total_multiplier: Timeouts::MAX,
+            read_total_constant: Duration::from_millis(100),
+            write_total_multiplier: Duration::ZERO,
+            write_total_constant: Duration::ZERO,
+        }
+    }
+}
+
+impl From<COMMTIMEOUTS> for Timeouts {
+    fn from(value: COMMTIMEOUTS) -> Self {
+        Self {
+            read_interval: Duration::from_millis(value.ReadIntervalTimeout as _),
+            read_total_multiplier: Duration::from_millis(value.ReadTotalTimeoutMultiplier as _),
+            read_total_constant: Duration::from_millis(value.ReadTotalTimeoutConstant as _),
+            write_total_multiplier: Duration::from_millis(value.WriteTotalTimeoutMultiplier as _),
+            write_total_constant: Duration::from_millis(value.WriteTotalTimeoutConstant as _),
+        }
+    }
+}
+
+impl From<Timeouts> for COMMTIMEOUTS {
+    fn from(value: Timeouts) -> Self {
+        let ms = |d: Duration| d.as_millis().min(u32::MAX as _) as u32;
+        Self {
+            ReadIntervalTimeout: ms(value.read_interval),
+            ReadTotalTimeoutMultiplier: ms(value.read_total_multiplier),
+            ReadTotalTimeoutConstant: ms(value.read_total_constant),
+            WriteTotalTimeoutMultiplier: ms(value.write_total_multiplier),
+            WriteTotalTimeoutConstant: ms(value.write_total_constant),
+        }
+    }
+}
+
+/// Read the time-out parameters of a communications device
+///
+/// [GetCommTimeouts](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommtimeouts)
+pub(crate) fn get_timeouts<H: AsRawHandle>(handle: &H) -> io::Result<Timeouts> {
+    // Safety: COMMTIMEOUTS is a plain C struct and is valid when zeroed
+    let mut timeouts: COMMTIMEOUTS = unsafe { std::mem::zeroed() };
+    match unsafe { GetCommTimeouts(handle.as_raw_handle() as _, &mut timeouts) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(timeouts.into()),
+    }
+}
+
+/// Set the time-out parameters of a communications device
+///
+/// [SetCommTimeouts](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-setcommtimeouts)
+pub(crate) fn set_timeouts<H: AsRawHandle>(handle: &H, timeouts: Timeouts) -> io::Result<()> {
+    let timeouts = COMMTIMEOUTS::from(timeouts);
+    match unsafe { SetCommTimeouts(handle.as_raw_handle() as _, &timeouts) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+bitflags::bitflags! {
+    /// Buffers and operations discarded by [`purge`]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub(crate) struct Purge: u32 {
+        /// Terminate all outstanding overlapped reads
+        const RX_ABORT = PURGE_RXABORT;
+        /// Clear the input buffer of the driver
+        const RX_CLEAR = PURGE_RXCLEAR;
+        /// Terminate all outstanding overlapped writes
+        const TX_ABORT = PURGE_TXABORT;
+        /// Clear the output buffer of the driver
+        const TX_CLEAR = PURGE_TXCLEAR;
+    }
+}
+
+/// Discard characters from the buffers of a communications device, or terminate outstanding
+/// overlapped operations
+///
+/// [PurgeComm](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-purgecomm)
+pub(crate) fn purge<H: AsRawHandle>(handle: &H, flags: Purge) -> io::Result<()> {
+    match unsafe { PurgeComm(handle.as_raw_handle() as _, flags.bits()) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+bitflags::bitflags! {
+    /// Communications errors reported by the driver
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct LineError: u32 {
+        /// The hardware detected a framing error
+        const FRAME = CE_FRAME;
+        /// The hardware detected a parity error
+        const PARITY = CE_RXPARITY;
+        /// A character-buffer overrun has occurred. The next character is lost
+        const OVERRUN = CE_OVERRUN;
+        /// An input buffer overflow has occurred
+        const RX_OVER = CE_RXOVER;
+        /// The hardware detected a break condition
+        const BREAK = CE_BREAK;
+    }
+}
+
+impl error::Error for LineError {}
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self
+            .iter_names()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(" | ");
+        write!(f, "line error => {names}")
+    }
+}
+
+/// A line error is reported as [`io::ErrorKind::InvalidData`]. The [`LineError`] can be recovered
+/// with [`io::Error::get_ref`]
+impl From<LineError> for io::Error {
+    fn from(value: LineError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
+
+/// Read and clear the communications errors of a device so that I/O may continue
+///
+/// [ClearCommError](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-clearcommerror)
+pub(crate) fn clear_errors<H: AsRawHandle>(handle: &H) -> io::Result<LineError> {
+    let mut errors = 0;
+    let result = unsafe {
+        ClearCommError(
+            handle.as_raw_handle() as _,
+            &mut errors,
+            std::ptr::null_mut(),
+        )
+    };
+    match result {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(LineError::from_bits_truncate(errors)),
+    }
+}
+
+/// Take the pending communications errors of a device, if any
+pub(crate) fn take_line_error<H: AsRawHandle>(handle: &H) -> Option<LineError> {
+    match clear_errors(handle) {
+        Ok(errors) if !errors.is_empty() => Some(errors),
+        Ok(_) => None,
+        Err(error) => {
+            warn!(?error, "failed to clear line errors");
+            None
+        }
+    }
+}
+
+/// Recommend the size of the internal input and output buffers of the driver. The driver is free
+/// to use buffers of a different size
+///
+/// [SetupComm](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-setupcomm)
+pub(crate) fn setup<H: AsRawHandle>(handle: &H, rx: u32, tx: u32) -> io::Result<()> {
+    match unsafe { SetupComm(handle.as_raw_handle() as _, rx, tx) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Rates reported in [`COMMPROP`] as a bitmask, and their value in bits per second
+const BAUD_RATES: [(u32, u32); 19] = [
+    (BAUD_075, 75),
+    (BAUD_110, 110),
+    (BAUD_134_5, 134),
+    (BAUD_150, 150),
+    (BAUD_300, 300),
+    (BAUD_600, 600),
+    (BAUD_1200, 1200),
+    (BAUD_1800, 1800),
+    (BAUD_2400, 2400),
+    (BAUD_4800, 4800),
+    (BAUD_7200, 7200),
+    (BAUD_9600, 9600),
+    (BAUD_14400, 14400),
+    (BAUD_19200, 19200),
+    (BAUD_38400, 38400),
+    (BAUD_56K, 56000),
+    (BAUD_57600, 57600),
+    (BAUD_115200, 115200),
+    (BAUD_128K, 128000),
+];
+
+/// The type of communications provider
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProviderSubtype {
+    Unspecified,
+    Rs232,
+    ParallelPort,
+    Rs422,
+    Rs423,
+    Rs449,
+    Modem,
+    Fax,
+    Scanner,
+    NetworkBridge,
+    Lat,
+    TcpIpTelnet,
+    X25,
+    /// A provider subtype not known to this crate
+    Other(u32),
+}
+
+impl From<u32> for ProviderSubtype {
+    fn from(value: u32) -> Self {
+        match value {
+            PST_UNSPECIFIED => Self::Unspecified,
+            PST_RS232 => Self::Rs232,
+            PST_PARALLELPORT => Self::ParallelPort,
+            PST_RS422 => Self::Rs422,
+            PST_RS423 => Self::Rs423,
+            PST_RS449 => Self::Rs449,
+            PST_MODEM => Self::Modem,
+            PST_FAX => Self::Fax,
+            PST_SCANNER => Self::Scanner,
+            PST_NETWORK_BRIDGE => Self::NetworkBridge,
+            PST_LAT => Self::Lat,
+            PST_TCPIP_TELNET => Self::TcpIpTelnet,
+            PST_X25 => Self::X25,
+            other => Self::Other(other),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// The communications parameters which may be changed
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct SettableParams: u32 {
+        const PARITY = SP_PARITY;
+        const BAUD = SP_BAUD;
+        const DATA_BITS = SP_DATABITS;
+        const STOP_BITS = SP_STOPBITS;
+        const HANDSHAKING = SP_HANDSHAKING;
+        const PARITY_CHECK = SP_PARITY_CHECK;
+        const RLSD = SP_RLSD;
+    }
+
+    /// The data bits which may be set
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct SettableData: u16 {
+        const FIVE = 0x0001;
+        const SIX = 0x0002;
+        const SEVEN = 0x0004;
+        const EIGHT = 0x0008;
+        const SIXTEEN = 0x0010;
+        /// Special wide path through serial hardware lines
+        const SIXTEEN_X = 0x0020;
+    }
+
+    /// The stop bits and parity which may be set
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct SettableStopParity: u16 {
+        const STOP_ONE = STOPBITS_10;
+        const STOP_ONE_POINT_FIVE = STOPBITS_15;
+        const STOP_TWO = STOPBITS_20;
+        const PARITY_NONE = PARITY_NONE;
+        const PARITY_ODD = PARITY_ODD;
+        const PARITY_EVEN = PARITY_EVEN;
+        const PARITY_MARK = PARITY_MARK;
+        const PARITY_SPACE = PARITY_SPACE;
+    }
+}
+
+/// The capabilities of a communications device
+///
+/// [COMMPROP](https://learn.microsoft.com/en-us/windows/win32/api/winbase/ns-winbase-commprop)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The maximum size of the driver's internal output buffer in bytes. 0 means no maximum
+    pub max_tx_queue: u32,
+    /// The maximum size of the driver's internal input buffer in bytes. 0 means no maximum
+    pub max_rx_queue: u32,
+    /// The maximum baud rate in bits per second. None when the rate is programmable
+    pub max_baud: Option<u32>,
+    /// The type of communications provider
+    pub provider_subtype: ProviderSubtype,
+    /// The communications parameters which may be changed
+    pub settable_params: SettableParams,
+    /// The standard baud rates which may be set, as a BAUD_* bitmask
+    pub settable_baud: u32,
+    /// The data bits which may be set
+    pub settable_data: SettableData,
+    /// The stop bits and parity which may be set
+    pub settable_stop_parity: SettableStopParity,
+    /// The size of the driver's internal output buffer in bytes. 0 when unavailable
+    pub current_tx_queue: u32,
+    /// The size of the driver's internal input buffer in bytes. 0 when unavailable
+    pub current_rx_queue: u32,
+}
+
+impl Capabilities {
+    /// The device accepts the baud rate
+    pub fn supports_baud_rate(&self, baud_rate: u32) -> bool {
+        let within_max = self.max_baud.map_or(true, |max| baud_rate <= max);
+        let standard = BAUD_RATES
+            .iter()
+            .any(|(flag, rate)| *rate == baud_rate && self.settable_baud & flag != 0);
+        within_max && (standard || self.settable_baud & BAUD_USER != 0)
+    }
+
+    /// The device accepts the data bits
+    pub fn supports_data_bits(&self, data_bits: DataBits) -> bool {
+        self.settable_data.contains(match data_bits {
+            DataBits::Five => SettableData::FIVE,
+            DataBits::Six => SettableData::SIX,
+            DataBits::Seven => SettableData::SEVEN,
+            DataBits::Eight => SettableData::EIGHT,
+        })
+    }
+
+    /// The device accepts the parity scheme
+    pub fn supports_parity(&self, parity: Parity) -> bool {
+        self.settable_stop_parity.contains(match parity {
+            Parity::None => SettableStopParity::PARITY_NONE,
+            Parity::Odd => SettableStopParity::PARITY_ODD,
+            Parity::Even => SettableStopParity::PARITY_EVEN,
+            Parity::Mark => SettableStopParity::PARITY_MARK,
+            Parity::Space => SettableStopParity::PARITY_SPACE,
+        })
+    }
+
+    /// The device accepts the stop bits
+    pub fn supports_stop_bits(&self, stop_bits: StopBits) -> bool {
+        self.settable_stop_parity.contains(match stop_bits {
+            StopBits::One => SettableStopParity::STOP_ONE,
+            StopBits::OnePointFive => SettableStopParity::STOP_ONE_POINT_FIVE,
+            StopBits::Two => SettableStopParity::STOP_TWO,
+        })
+    }
+}
+
+impl From<COMMPROP> for Capabilities {
+    fn from(value: COMMPROP) -> Self {
+        Self {
+            max_tx_queue: value.dwMaxTxQueue,
+            max_rx_queue: value.dwMaxRxQueue,
+            max_baud: BAUD_RATES
+                .iter()
+                .find(|(flag, _)| *flag == value.dwMaxBaud)
+                .map(|(_, rate)| *rate),
+            provider_subtype: value.dwProvSubType.into(),
+            settable_params: SettableParams::from_bits_truncate(value.dwSettableParams),
+            settable_baud: value.dwSettableBaud,
+            settable_data: SettableData::from_bits_truncate(value.wSettableData),
+            settable_stop_parity: SettableStopParity::from_bits_truncate(value.wSettableStopParity),
+            current_tx_queue: value.dwCurrentTxQueue,
+            current_rx_queue: value.dwCurrentRxQueue,
+        }
+    }
+}
+
+/// Read the capabilities of a communications device
+///
+/// [GetCommProperties](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommproperties)
+pub(crate) fn properties<H: AsRawHandle>(handle: &H) -> io::Result<Capabilities> {
+    // Safety: COMMPROP is a plain C struct and is valid when zeroed
+    let mut prop: COMMPROP = unsafe { std::mem::zeroed() };
+    prop.wPacketLength = std::mem::size_of::<COMMPROP>() as _;
+    match unsafe { GetCommProperties(handle.as_raw_handle() as _, &mut prop) } {
+        FALSE => Err(io::Error::last_os_error()),
+        _ => Ok(prop.into()),
+    }
+}
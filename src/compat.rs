@@ -0,0 +1,110 @@
+//! compat
+//!
+//! Implement the tokio io traits alongside the futures io traits, so that ports can be used with
+//! tokio without wrapping them in compat shims
+
+use crate::{
+    channel::{Reader, Writer},
+    hkey::ScanResult,
+    port::{ComPort, OwnedReadHalf, OwnedWriteHalf},
+    reopen::ReopeningPort,
+    wm::PlugEvent,
+};
+use futures::{ready, Stream};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Read into the unfilled part of a tokio buffer with a futures reader
+fn poll_read_buf<R>(
+    reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+) -> Poll<io::Result<()>>
+where
+    R: futures::AsyncRead + ?Sized,
+{
+    let n = ready!(reader.poll_read(cx, buf.initialize_unfilled()))?;
+    buf.advance(n);
+    Poll::Ready(Ok(()))
+}
+
+macro_rules! impl_async_read {
+    ($($ty:ty),*) => {
+        $(
+            impl AsyncRead for $ty {
+                fn poll_read(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    buf: &mut ReadBuf<'_>,
+                ) -> Poll<io::Result<()>> {
+                    poll_read_buf(self, cx, buf)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_async_write {
+    ($($ty:ty),*) => {
+        $(
+            impl AsyncWrite for $ty {
+                fn poll_write(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    buf: &[u8],
+                ) -> Poll<io::Result<usize>> {
+                    futures::AsyncWrite::poll_write(self, cx, buf)
+                }
+
+                fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                    futures::AsyncWrite::poll_flush(self, cx)
+                }
+
+                fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                    futures::AsyncWrite::poll_close(self, cx)
+                }
+            }
+        )*
+    };
+}
+
+impl_async_read!(ComPort, OwnedReadHalf, Reader);
+impl_async_write!(ComPort, OwnedWriteHalf, Writer);
+
+impl<St> AsyncRead for ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        poll_read_buf(self, cx, buf)
+    }
+}
+
+impl<St> AsyncWrite for ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        futures::AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::AsyncWrite::poll_close(self, cx)
+    }
+}
@@ -0,0 +1,111 @@
+//! debounce
+//!
+//! Flaky cables and devices which re-enumerate produce an arrival and a removal of the same port
+//! within milliseconds. A [`Debounce`] stream holds every arrival and removal for a window, and
+//! drops the pair when the port toggles back within the window, so only steady state changes are
+//! reported.
+
+use crate::{event::Deadline, hkey::ScanResult, wm::PlugEvent};
+use futures::{Future, Stream};
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tracing::trace;
+
+/// An arrival or removal waiting for its window to close
+#[derive(Debug)]
+struct Held {
+    event: PlugEvent,
+    window: Deadline,
+}
+
+pin_project! {
+    /// A stream which collapses transient plug and unplug toggles. See
+    /// [`crate::prelude::DeviceStreamExt::debounce`]
+    #[derive(Debug)]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Debounce<St> {
+        #[pin]
+        inner: St,
+        window: Duration,
+        // Events in the order their windows close
+        pending: VecDeque<Held>,
+        finished: bool,
+    }
+}
+
+impl<St> Debounce<St> {
+    pub(crate) fn new(inner: St, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+/// The port of an arrival or removal, and true for an arrival
+fn toggle(event: &PlugEvent) -> Option<(&OsString, bool)> {
+    match event {
+        PlugEvent::Arrival(port, _) => Some((port, true)),
+        PlugEvent::RemoveComplete(port) => Some((port, false)),
+        _ => None,
+    }
+}
+
+impl<St> Stream for Debounce<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>>,
+{
+    type Item = ScanResult<PlugEvent>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            // Events whose window closed are reported as they are
+            if let Some(held) = this.pending.front_mut() {
+                if *this.finished || Pin::new(&mut held.window).poll(cx).is_ready() {
+                    let held = this.pending.pop_front().unwrap();
+                    return Poll::Ready(Some(Ok(held.event)));
+                }
+            }
+            if *this.finished {
+                return Poll::Ready(None);
+            }
+            let event = match this.inner.as_mut().poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => {
+                    *this.finished = true;
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(event))) => event,
+            };
+            let (port, arrival) = match toggle(&event) {
+                Some(toggle) => toggle,
+                None => return Poll::Ready(Some(Ok(event))),
+            };
+            match this
+                .pending
+                .iter()
+                .position(|held| toggle(&held.event).map_or(false, |(held, _)| held == port))
+            {
+                Some(n) if toggle(&this.pending[n].event).unwrap().1 != arrival => {
+                    trace!(?port, "debounced toggle");
+                    this.pending.remove(n);
+                }
+                // The port is already held in this state. IE: a rescan
+                Some(_) => trace!(?port, "debounced duplicate"),
+                None => {
+                    let window = Deadline::new(*this.window)?;
+                    this.pending.push_back(Held { event, window });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! dedupe
+//!
+//! A rescan re-emits an arrival for every connected device, including devices which were already
+//! reported. A [`Dedupe`] stream tracks the connected ports and only reports genuine transitions.
+
+use crate::{
+    hkey::{PortMeta, ScanResult},
+    wm::PlugEvent,
+};
+use futures::Stream;
+use pin_project_lite::pin_project;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tracing::trace;
+
+pin_project! {
+    /// A stream which suppresses duplicate arrivals and removals. See
+    /// [`crate::prelude::DeviceStreamExt::dedupe`]
+    #[derive(Debug)]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Dedupe<St> {
+        #[pin]
+        inner: St,
+        // The connected ports and the ids they arrived with
+        connected: HashMap<OsString, PortMeta>,
+    }
+}
+
+impl<St> Dedupe<St> {
+    pub(crate) fn new(inner: St) -> Self {
+        Self {
+            inner,
+            connected: HashMap::new(),
+        }
+    }
+
+    /// The ports which are currently connected
+    pub fn connected(&self) -> &HashMap<OsString, PortMeta> {
+        &self.connected
+    }
+}
+
+impl<St> Stream for Dedupe<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>>,
+{
+    type Item = ScanResult<PlugEvent>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(PlugEvent::Arrival(port, ids)))) => {
+                    // A port which arrives again with other ids is a transition
+                    match this.connected.insert(port.clone(), ids.clone()) {
+                        Some(old) if old == ids => trace!(?port, "duplicate arrival"),
+                        _ => return Poll::Ready(Some(Ok(PlugEvent::Arrival(port, ids)))),
+                    }
+                }
+                Poll::Ready(Some(Ok(PlugEvent::RemoveComplete(port)))) => {
+                    match this.connected.remove(&port) {
+                        None => trace!(?port, "duplicate removal"),
+                        Some(_) => return Poll::Ready(Some(Ok(PlugEvent::RemoveComplete(port)))),
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
@@ -5,26 +5,70 @@ use std::{
     ffi::{c_void, OsString},
     future::Future,
     io,
+    marker::PhantomData,
     os::windows::{
         io::{AsRawHandle, HandleOrNull, OwnedHandle, RawHandle},
         prelude::*,
     },
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
     task::{Context, Poll, Waker},
     time::Duration,
 };
+use tracing::warn;
 use windows_sys::Win32::{
-    Foundation::{FALSE, FILETIME, TRUE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT},
+    Foundation::{
+        FALSE, FILETIME, HANDLE, TRUE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT,
+    },
     System::Threading::{
-        CloseThreadpoolWait, CreateEventW, CreateThreadpoolWait, ResetEvent, SetEvent,
-        SetThreadpoolWait, WaitForSingleObject, WaitForThreadpoolWaitCallbacks, INFINITE,
-        PTP_CALLBACK_INSTANCE, PTP_WAIT,
+        CancelWaitableTimer, CloseThreadpoolWait, CreateEventW, CreateThreadpoolWait,
+        CreateWaitableTimerW, ResetEvent, SetEvent, SetThreadpoolWait, SetWaitableTimer,
+        WaitForSingleObject, WaitForThreadpoolWaitCallbacks, INFINITE, PTP_CALLBACK_INSTANCE,
+        PTP_WAIT,
     },
 };
 
-/// Waiting on a waitable object will resolve with Ok or a WaitError
-pub type WaitResult = Result<(), WaitError>;
+/// How a wait on a waitable object resolved. A timeout is an outcome of the wait rather than an
+/// error, so pollers can tell it apart from a signal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaitResult {
+    /// The waitable object was signaled
+    Signaled,
+    /// The waitable object was not signaled before the timeout passed to the wait
+    TimedOut,
+    /// A caller signaled they are no longer interested in waiting. See [`EventListener::cancel`]
+    Cancelled,
+    /// The waitable object is a mutex which was released by a thread which exited while owning it
+    Abandoned,
+    /// The wait infrastructure failed. Carries the status the kernel reported for the wait
+    Failed(u32),
+}
+
+impl WaitResult {
+    /// The outcome of a threadpool wait callback
+    fn from_status(status: u32) -> WaitResult {
+        match status {
+            WAIT_OBJECT_0 => WaitResult::Signaled,
+            WAIT_TIMEOUT => WaitResult::TimedOut,
+            WAIT_ABANDONED => WaitResult::Abandoned,
+            status => WaitResult::Failed(status),
+        }
+    }
+
+    /// Treat anything but a signal as a [`WaitError`]
+    pub fn signaled(self) -> Result<(), WaitError> {
+        match self {
+            WaitResult::Signaled => Ok(()),
+            WaitResult::TimedOut => Err(WaitError::Timeout),
+            WaitResult::Cancelled => Err(WaitError::Cancelled),
+            WaitResult::Abandoned => Err(WaitError::Abandoned),
+            WaitResult::Failed(status) => Err(WaitError::Failed(status)),
+        }
+    }
+}
 
 /// When waiting on a waitable object. The wait may resolve with a wait error.
 #[derive(thiserror::Error, Copy, Clone, Debug, PartialEq)]
@@ -39,6 +83,12 @@ pub enum WaitError {
     /// Already waiting for the waitable object
     #[error("wait already in progress")]
     InProgress,
+    /// The waitable object is a mutex which was abandoned by its owner
+    #[error("wait abandoned")]
+    Abandoned = WAIT_ABANDONED,
+    /// The wait infrastructure failed with the status
+    #[error("wait failed with status {0:#x}")]
+    Failed(u32),
 }
 
 /// Waitable object as per windows
@@ -81,15 +131,14 @@ impl WaitPool {
     ///
     /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpoolwait
     pub fn start<W: Waitable>(&self, waitable: &W, timeout: Option<Duration>) {
-        let ft = timeout
-            .map(|to| {
-                let ms = to.as_millis();
-                &FILETIME {
-                    dwHighDateTime: (ms >> 32) as u32,
-                    dwLowDateTime: (ms & 0xFFFFFFFF) as u32,
-                } as *const _
-            })
-            .unwrap_or_else(std::ptr::null);
+        let ft = timeout.map(|to| {
+            let ticks = relative_ticks(to);
+            FILETIME {
+                dwHighDateTime: (ticks >> 32) as u32,
+                dwLowDateTime: (ticks & 0xFFFFFFFF) as u32,
+            }
+        });
+        let ft = ft.as_ref().map_or_else(std::ptr::null, |ft| ft as *const _);
         unsafe { SetThreadpoolWait(self.0, waitable.as_raw_handle() as _, ft) };
     }
 
@@ -114,6 +163,11 @@ impl WaitPool {
     }
 }
 
+/// A negative time-out is relative to now, in 100 nanosecond intervals
+fn relative_ticks(duration: Duration) -> i64 {
+    -((duration.as_nanos() / 100).min(i64::MAX as u128) as i64)
+}
+
 #[derive(Default, Debug)]
 struct WaitState {
     waker: Option<Waker>,
@@ -160,11 +214,7 @@ unsafe extern "system" fn wait_callback(
 ) {
     let state = &*(context as *const Mutex<WaitState>);
     let mut shared = state.lock();
-    shared.result = match waitresult {
-        WAIT_OBJECT_0 => Some(Ok(())),
-        WAIT_TIMEOUT => Some(Err(WaitError::Timeout)),
-        _ => panic!("Unsupported kernel argument passed to wait callback!"),
-    };
+    shared.result = Some(WaitResult::from_status(waitresult));
     if let Some(waker) = shared.waker.as_ref() {
         waker.wake_by_ref()
     }
@@ -233,6 +283,11 @@ impl Event {
         .map(Self)
     }
 
+    /// Duplicate the handle of the event. Both handles refer to the same kernel event
+    pub fn try_clone(&self) -> io::Result<Event> {
+        self.0.try_clone().map(Event)
+    }
+
     pub fn set(&self) -> io::Result<()> {
         match unsafe { SetEvent(self.as_raw_handle() as _) } {
             FALSE => Err(io::Error::last_os_error()),
@@ -303,6 +358,8 @@ impl EventListener {
         WaitPool::new(Arc::as_ptr(&state) as _, wait_callback).map(|pool| Self { pool, state })
     }
 
+    /// Wait for the waitable object to be signaled. If a timeout is given and passes first, the
+    /// future resolves with [`WaitResult::TimedOut`]
     pub fn start<W>(&self, waitable: &W, timeout: Option<Duration>) -> Waiting
     where
         W: Waitable,
@@ -316,6 +373,7 @@ impl EventListener {
         }
     }
 
+    /// Wait again once the previous wait resolved. See [`Self::start`]
     pub fn restart<W>(&self, waitable: &W, timeout: Option<Duration>) -> Result<Waiting, WaitError>
     where
         W: Waitable,
@@ -326,10 +384,24 @@ impl EventListener {
         Ok(Waiting(Arc::clone(&self.state)))
     }
 
+    /// Stop the current wait, discarding its result, and wait again. Unlike [`Self::restart`]
+    /// the current wait does not need to resolve first
+    fn rearm<W>(&self, waitable: &W, timeout: Option<Duration>) -> Waiting
+    where
+        W: Waitable,
+    {
+        self.pool.stop();
+        // A callback of the current wait must not resolve the next wait
+        self.pool.wait(WaitPending::Cancel);
+        self.state.lock().result = None;
+        self.pool.start(waitable, timeout);
+        Waiting(Arc::clone(&self.state))
+    }
+
     pub fn cancel(&self) -> &Self {
         self.pool.stop();
         let mut state = self.state.lock();
-        match state.result.replace(Err(WaitError::Cancelled)) {
+        match state.result.replace(WaitResult::Cancelled) {
             Some(prev) => state.result = Some(prev),
             None => match state.waker.take() {
                 Some(waker) => waker.wake(),
@@ -340,28 +412,341 @@ impl EventListener {
     }
 }
 
+/// The most events kept for reuse by [`oneshot`]. Events beyond the limit are closed
+const EVENT_POOL_CAPACITY: usize = 64;
+
+/// Manual-reset events of finished oneshots. Tracking creates a oneshot per arrival, so test racks
+/// which plug devices all day would otherwise churn through kernel handles
+static EVENT_POOL: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+/// An event of the [`EVENT_POOL`], which is reset and returned to the pool when dropped
 #[derive(Debug)]
-pub struct Receiver {
-    #[allow(unused)]
+struct PooledEvent(Option<Event>);
+
+impl PooledEvent {
+    fn take() -> io::Result<PooledEvent> {
+        let event = match EVENT_POOL.lock().pop() {
+            Some(event) => event,
+            None => Event::anonymous(EventReset::Manual, EventInitialState::Unset)?,
+        };
+        Ok(PooledEvent(Some(event)))
+    }
+
+    fn event(&self) -> &Event {
+        self.0.as_ref().expect("pooled event")
+    }
+}
+
+impl Drop for PooledEvent {
+    fn drop(&mut self) {
+        let Some(event) = self.0.take() else {
+            return;
+        };
+        // An event which can not be reset is closed rather than handed out set
+        if event.reset().is_ok() {
+            let mut pool = EVENT_POOL.lock();
+            if pool.len() < EVENT_POOL_CAPACITY {
+                pool.push(event);
+            }
+        }
+    }
+}
+
+/// The state shared between a oneshot wait callback and the clones of its [`Receiver`]
+#[derive(Debug)]
+struct OneshotState {
+    wait: Mutex<OneshotWait>,
+    event: Arc<PooledEvent>,
+}
+
+impl OneshotState {
+    fn new(event: Arc<PooledEvent>) -> Arc<OneshotState> {
+        Arc::new(OneshotState {
+            wait: Mutex::new(OneshotWait::default()),
+            event,
+        })
+    }
+}
+
+/// How the wait of a oneshot resolved, and a waker for every clone of the receiver waiting on it
+#[derive(Debug, Default)]
+struct OneshotWait {
+    result: Option<WaitResult>,
+    wakers: Vec<Waker>,
+}
+
+/// The threadpool wait shared by the clones of a kernel [`Receiver`]
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The pool must stop waiting before the
+/// event is returned to the pool
+#[derive(Debug)]
+struct KernelWait {
     pool: WaitPool,
-    state: Arc<(Mutex<WaitState>, Event)>,
+    state: Arc<OneshotState>,
+}
+
+/// The state shared by the sides of a [`oneshot_local`]
+#[derive(Debug, Default)]
+struct LocalState {
+    set: AtomicBool,
+    /// A waker for every receiver waiting on the signal
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl LocalState {
+    fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        for waker in std::mem::take(&mut *self.wakers.lock()) {
+            waker.wake();
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<WaitResult> {
+        if self.is_set() {
+            return Poll::Ready(WaitResult::Signaled);
+        }
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        // The sender may have set the signal before we registered
+        match self.is_set() {
+            true => Poll::Ready(WaitResult::Signaled),
+            false => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ReceiverKind {
+    Kernel(Arc<KernelWait>),
+    Local(Arc<LocalState>),
+}
+
+/// Resolves once the [`Sender`] is set. Clones share the wait on the same signal, so one signal
+/// can be shared by many waiters and cloning can not fail
+#[derive(Debug, Clone)]
+pub struct Receiver(ReceiverKind);
+
+impl Receiver {
+    /// Wait on the event of the shared state
+    fn listen(state: Arc<OneshotState>) -> io::Result<Receiver> {
+        let pool = WaitPool::new(Arc::as_ptr(&state) as _, oneshot_callback)?;
+        pool.start(state.event.event(), None);
+        Ok(Receiver(ReceiverKind::Kernel(Arc::new(KernelWait {
+            pool,
+            state,
+        }))))
+    }
+
+    /// Wait for the signal, but give up with [`WaitError::Timeout`] once the duration passes. IE:
+    /// wait for an unplug for at most 30s
+    pub fn with_deadline(self, duration: Duration) -> io::Result<WithDeadline> {
+        Ok(WithDeadline {
+            receiver: self,
+            deadline: Deadline::new(duration)?,
+        })
+    }
+
+    /// True once the sender is set. Does not block, and does not need a task context
+    pub fn is_set(&self) -> bool {
+        match &self.0 {
+            ReceiverKind::Kernel(wait) => is_set(wait.state.event.event()),
+            ReceiverKind::Local(state) => state.is_set(),
+        }
+    }
 }
 
 impl Future for Receiver {
     type Output = WaitResult;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.state.0.lock();
-        let new_waker = cx.waker();
-
+        let wait = match &self.0 {
+            ReceiverKind::Kernel(wait) => wait,
+            ReceiverKind::Local(state) => return state.poll(cx),
+        };
+        let mut state = wait.state.wait.lock();
         match state.result {
-            Some(result) => {
-                if let Some(waker) = state.waker.take() {
-                    waker.wake()
+            Some(result) => Poll::Ready(result),
+            None => {
+                // Every clone polled from another task needs its own waker
+                if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
                 }
-                Poll::Ready(result)
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A [`Receiver`] which gives up at a deadline. See [`Receiver::with_deadline`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WithDeadline {
+    receiver: Receiver,
+    deadline: Deadline,
+}
+
+impl Future for WithDeadline {
+    type Output = Result<(), WaitError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(result) = Pin::new(&mut self.receiver).poll(cx) {
+            return Poll::Ready(result.signaled());
+        }
+        Pin::new(&mut self.deadline)
+            .poll(cx)
+            .map(|_| Err(WaitError::Timeout))
+    }
+}
+
+#[derive(Debug)]
+enum SenderKind {
+    Kernel(Arc<PooledEvent>),
+    Local(Arc<LocalState>),
+}
+
+#[derive(Debug)]
+pub struct Sender(SenderKind);
+
+impl Sender {
+    pub fn set(self) -> io::Result<()> {
+        match &self.0 {
+            SenderKind::Kernel(event) => event.event().set(),
+            SenderKind::Local(state) => {
+                state.set();
+                Ok(())
             }
+        }
+    }
+
+    /// True once the signal is set. See [`Receiver::is_set`]
+    pub fn is_set(&self) -> bool {
+        match &self.0 {
+            SenderKind::Kernel(event) => is_set(event.event()),
+            SenderKind::Local(state) => state.is_set(),
+        }
+    }
+}
+
+/// Check the manual-reset event of a oneshot without waiting. Checking an auto-reset event this
+/// way would reset it
+fn is_set(event: &Event) -> bool {
+    event.wait(Some(Duration::ZERO)).is_ok()
+}
+
+/// A signal from one [`Sender`] to a [`Receiver`] and its clones. Events are reused once every
+/// side of a oneshot is dropped
+pub fn oneshot() -> io::Result<(Sender, Receiver)> {
+    let event = Arc::new(PooledEvent::take()?);
+    let receiver = Receiver::listen(OneshotState::new(Arc::clone(&event)))?;
+    Ok((Sender(SenderKind::Kernel(event)), receiver))
+}
+
+/// A [`oneshot`] which signals in userspace with an atomic flag and wakers instead of a kernel
+/// event. IE: so tests can drive an [`crate::prelude::Unplugged`] without creating OS handles.
+/// The receiver can not be waited on by a thread, but it can not fail either
+pub fn oneshot_local() -> (Sender, Receiver) {
+    let state = Arc::new(LocalState::default());
+    let receiver = Receiver(ReceiverKind::Local(Arc::clone(&state)));
+    (Sender(SenderKind::Local(state)), receiver)
+}
+
+unsafe extern "system" fn oneshot_callback(
+    _instance: PTP_CALLBACK_INSTANCE,
+    context: *mut c_void,
+    _wait: PTP_WAIT,
+    waitresult: u32,
+) {
+    let state = &*(context as *const OneshotState);
+    let mut shared = state.wait.lock();
+    shared.result = Some(WaitResult::from_status(waitresult));
+    for waker in shared.wakers.drain(..) {
+        waker.wake()
+    }
+}
+
+#[derive(Default, Debug)]
+struct SelectState {
+    waker: Option<Waker>,
+    /// The index of the first event to resolve, and how its wait resolved
+    result: Option<(usize, WaitResult)>,
+}
+
+/// The context of one threadpool wait of a [`SelectAll`]
+#[derive(Debug)]
+struct SelectSlot {
+    index: usize,
+    handle: HANDLE,
+    state: Arc<Mutex<SelectState>>,
+}
+
+/// A future which resolves with the index of the first signaled event, or with the error of the
+/// first wait which failed. See [`select_all`]
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The pools must finish their callbacks before
+/// the slots are dropped
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SelectAll<'a> {
+    #[allow(unused)]
+    pools: Vec<WaitPool>,
+    #[allow(unused)]
+    slots: Vec<Box<SelectSlot>>,
+    state: Arc<Mutex<SelectState>>,
+    /// The events must outlive the waits
+    events: PhantomData<&'a [Event]>,
+}
+
+/// Race events against each other. IE: an abort event against a data ready event. Each event is
+/// waited on by the threadpool, so no thread is blocked per wait.
+///
+/// Waiting on an auto-reset event resets it. An event which is signaled after the first is set
+/// again, so only the signal of the reported event is consumed
+pub fn select_all(events: &[Event]) -> io::Result<SelectAll<'_>> {
+    if events.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "select_all requires at least one event",
+        ));
+    }
+    let state = Arc::new(Mutex::new(SelectState::default()));
+    let slots = events
+        .iter()
+        .enumerate()
+        .map(|(index, event)| {
+            Box::new(SelectSlot {
+                index,
+                handle: event.as_raw_handle() as _,
+                state: Arc::clone(&state),
+            })
+        })
+        .collect::<Vec<_>>();
+    let pools = slots
+        .iter()
+        .map(|slot| WaitPool::new(&**slot as *const SelectSlot as _, select_callback))
+        .collect::<io::Result<Vec<_>>>()?;
+    for (pool, event) in pools.iter().zip(events) {
+        pool.start(event, None);
+    }
+    Ok(SelectAll {
+        pools,
+        slots,
+        state,
+        events: PhantomData,
+    })
+}
+
+impl Future for SelectAll<'_> {
+    type Output = Result<usize, WaitError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        match state.result {
+            Some((index, result)) => Poll::Ready(result.signaled().map(|_| index)),
             None => {
-                // Update our waker
+                let new_waker = cx.waker();
                 state.waker = match state.waker.take() {
                     None => Some(new_waker.clone()),
                     Some(old_waker) => match old_waker.will_wake(new_waker) {
@@ -375,45 +760,236 @@ impl Future for Receiver {
     }
 }
 
+unsafe extern "system" fn select_callback(
+    _instance: PTP_CALLBACK_INSTANCE,
+    context: *mut c_void,
+    _wait: PTP_WAIT,
+    waitresult: u32,
+) {
+    let slot = &*(context as *const SelectSlot);
+    let result = WaitResult::from_status(waitresult);
+    let mut state = slot.state.lock();
+    match state.result {
+        // Give the signal back, in case the wait consumed it
+        Some(_) if result == WaitResult::Signaled => {
+            SetEvent(slot.handle);
+        }
+        Some(_) => {}
+        None => {
+            state.result = Some((slot.index, result));
+            if let Some(waker) = state.waker.as_ref() {
+                waker.wake_by_ref()
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct Sender {
+struct TokenState {
+    /// Set once the token is cancelled, so threads can block on the token
+    event: Event,
+    cancelled: AtomicBool,
+    children: Mutex<Vec<Weak<TokenState>>>,
+}
+
+impl TokenState {
+    fn new() -> io::Result<TokenState> {
+        Ok(TokenState {
+            event: Event::anonymous(EventReset::Manual, EventInitialState::Unset)?,
+            cancelled: AtomicBool::new(false),
+            children: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Err(error) = self.event.set() {
+            warn!(?error, "failed to signal cancellation");
+        }
+        let children = std::mem::take(&mut *self.children.lock());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// A signal to stop work, shared by every clone of the token. Cancelling a token cancels its
+/// children, but cancelling a child leaves its parent alone. IE: one token for the application
+/// and a child token per port
+#[derive(Debug, Clone)]
+pub struct CancellationToken(Arc<TokenState>);
+
+impl CancellationToken {
+    pub fn new() -> io::Result<CancellationToken> {
+        TokenState::new().map(Arc::new).map(CancellationToken)
+    }
+
+    /// A token which is cancelled with this token, or on its own
+    pub fn child(&self) -> io::Result<CancellationToken> {
+        let child = Arc::new(TokenState::new()?);
+        {
+            let mut children = self.0.children.lock();
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child));
+        }
+        // The parent may have been cancelled before the child was registered
+        if self.is_cancelled() {
+            child.cancel();
+        }
+        Ok(CancellationToken(child))
+    }
+
+    /// Cancel the token and all of its children. Cancelling more than once does nothing
+    pub fn cancel(&self) {
+        self.0.cancel()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// A future which resolves once the token is cancelled
+    pub fn cancelled(&self) -> io::Result<Cancelled> {
+        let listener = EventListener::new()?;
+        let waiting = listener.start(self, None);
+        Ok(Cancelled {
+            waiting,
+            listener,
+            token: self.clone(),
+        })
+    }
+}
+
+impl Waitable for CancellationToken {}
+
+impl AsRawHandle for CancellationToken {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.event.as_raw_handle()
+    }
+}
+
+/// A future which resolves once a [`CancellationToken`] is cancelled
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The listener must stop waiting before the
+/// event of the token is closed
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Cancelled {
+    waiting: Waiting,
+    #[allow(unused)]
+    listener: EventListener,
     #[allow(unused)]
-    state: Arc<(Mutex<WaitState>, Event)>,
+    token: CancellationToken,
 }
 
-impl Sender {
-    pub fn set(self) -> io::Result<()> {
-        self.state.1.set()
+impl Future for Cancelled {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.waiting).poll(cx).map(|_| ())
     }
 }
 
-pub fn oneshot() -> io::Result<(Sender, Receiver)> {
-    let event = Event::anonymous(EventReset::Manual, EventInitialState::Unset)?;
-    let state = Arc::new((Mutex::new(WaitState::default()), event));
-    let pool = WaitPool::new(Arc::as_ptr(&state) as _, oneshot_callback)?;
-    pool.start(&state.1, None);
-    let sender = Sender { state };
-    let receiver = Receiver {
-        state: Arc::clone(&sender.state),
-        pool,
-    };
-    Ok((sender, receiver))
+/// A kernel waitable timer. The timer stays signaled from its due time until it is set again
+#[derive(Debug)]
+pub struct WaitableTimer(OwnedHandle);
+
+impl WaitableTimer {
+    /// Create a manual-reset timer which is not set
+    ///
+    /// [CreateWaitableTimerW](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createwaitabletimerw)
+    pub fn new() -> io::Result<WaitableTimer> {
+        unsafe {
+            let raw = CreateWaitableTimerW(std::ptr::null(), TRUE, std::ptr::null());
+            let handle = HandleOrNull::from_raw_handle(raw as _);
+            OwnedHandle::try_from(handle).map_err(|_| io::Error::last_os_error())
+        }
+        .map(Self)
+    }
+
+    /// Signal the timer once the duration elapses. Setting an active timer moves its due time
+    ///
+    /// [SetWaitableTimer](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-setwaitabletimer)
+    pub fn set(&self, due: Duration) -> io::Result<()> {
+        let ticks = relative_ticks(due);
+        let result = unsafe {
+            SetWaitableTimer(
+                self.as_raw_handle() as _,
+                &ticks,
+                0,
+                None,
+                std::ptr::null(),
+                FALSE,
+            )
+        };
+        match result {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deactivate the timer. The signaled state of the timer is left as is
+    ///
+    /// [CancelWaitableTimer](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-cancelwaitabletimer)
+    pub fn cancel(&self) -> io::Result<()> {
+        match unsafe { CancelWaitableTimer(self.as_raw_handle() as _) } {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
 }
 
-unsafe extern "system" fn oneshot_callback(
-    _instance: PTP_CALLBACK_INSTANCE,
-    context: *mut c_void,
-    _wait: PTP_WAIT,
-    waitresult: u32,
-) {
-    let state = &*(context as *const (Mutex<WaitState>, Event));
-    let mut shared = state.0.lock();
-    shared.result = match waitresult {
-        WAIT_OBJECT_0 => Some(Ok(())),
-        WAIT_TIMEOUT => Some(Err(WaitError::Timeout)),
-        _ => panic!("Unsupported kernel argument passed to wait callback!"),
-    };
-    if let Some(waker) = shared.waker.as_ref() {
-        waker.wake_by_ref()
+impl Waitable for WaitableTimer {}
+
+impl AsRawHandle for WaitableTimer {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+/// A future which resolves once a [`WaitableTimer`] is due. The deadline may be moved while it is
+/// pending, IE: to debounce or to extend a read deadline
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The listener must stop waiting before the
+/// timer is closed
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Deadline {
+    waiting: Waiting,
+    listener: EventListener,
+    timer: WaitableTimer,
+}
+
+impl Deadline {
+    pub fn new(duration: Duration) -> io::Result<Deadline> {
+        let timer = WaitableTimer::new()?;
+        timer.set(duration)?;
+        let listener = EventListener::new()?;
+        let waiting = listener.start(&timer, None);
+        Ok(Deadline {
+            waiting,
+            listener,
+            timer,
+        })
     }
+
+    /// Move the deadline to the duration from now, even if the deadline already passed
+    pub fn reset(&mut self, duration: Duration) -> io::Result<()> {
+        self.timer.set(duration)?;
+        self.waiting = self.listener.rearm(&self.timer, None);
+        Ok(())
+    }
+}
+
+impl Future for Deadline {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.waiting).poll(cx).map(|_| ())
+    }
+}
+
+/// Resolve once the duration elapses, without a runtime. See [`Deadline`]
+pub fn sleep(duration: Duration) -> io::Result<Deadline> {
+    Deadline::new(duration)
 }
@@ -0,0 +1,135 @@
+//! ftdi
+//!
+//! Tune FTDI virtual COM ports. The FTDI driver reads its latency timer and USB transfer sizes
+//! from the `Device Parameters` registry key of each device. The default 16ms latency timer
+//! delays every short response, which ruins request/response protocols.
+
+use crate::hkey::{self, Hkey, PredefinedHkey, RegistryError, Win32Error};
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    time::Duration,
+};
+use tracing::trace;
+use windows_sys::Win32::{
+    Foundation::ERROR_FILE_NOT_FOUND,
+    System::Registry::{KEY_READ, KEY_SET_VALUE},
+};
+
+/// Devices enumerated by the FTDI bus driver. IE: FTDIBUS\VID_0403+PID_6001+A12345A\0000
+const FTDIBUS: &str = "SYSTEM\\CurrentControlSet\\Enum\\FTDIBUS";
+
+const PORT_NAME: &str = "PortName";
+const LATENCY_TIMER: &str = "LatencyTimer";
+const IN_TRANSFER_SIZE: &str = "InTransferSize";
+const OUT_TRANSFER_SIZE: &str = "OutTransferSize";
+
+/// Driver parameters of an FTDI device. Parameters which are None are left as they are
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FtdiParams {
+    /// How long the device holds a short packet before sending it. 1ms to 255ms
+    pub latency_timer: Option<Duration>,
+    /// The USB request size in bytes for received data. A multiple of 64 from 64 to 65536
+    pub in_transfer_size: Option<u32>,
+    /// The USB request size in bytes for transmitted data. A multiple of 64 from 64 to 65536
+    pub out_transfer_size: Option<u32>,
+}
+
+impl FtdiParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_latency_timer(mut self, latency_timer: Duration) -> Self {
+        self.latency_timer = Some(latency_timer);
+        self
+    }
+
+    pub fn with_transfer_sizes(mut self, rx: u32, tx: u32) -> Self {
+        self.in_transfer_size = Some(rx);
+        self.out_transfer_size = Some(tx);
+        self
+    }
+
+    /// Make sure the driver will accept the parameters
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        let latency_ok = self
+            .latency_timer
+            .map_or(true, |latency| (1..=255).contains(&latency.as_millis()));
+        let size_ok = |size: Option<u32>| {
+            size.map_or(true, |size| (64..=65536).contains(&size) && size % 64 == 0)
+        };
+        match latency_ok && size_ok(self.in_transfer_size) && size_ok(self.out_transfer_size) {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ftdi parameters out of range",
+            )),
+        }
+    }
+}
+
+/// Read the driver parameters of an FTDI device. IE: "COM4"
+pub fn params<P: AsRef<OsStr>>(port: P) -> Result<FtdiParams, RegistryError> {
+    let key = device_parameters(port.as_ref(), KEY_READ)?;
+    Ok(FtdiParams {
+        latency_timer: read_u32(&key, LATENCY_TIMER)?.map(|ms| Duration::from_millis(ms as _)),
+        in_transfer_size: read_u32(&key, IN_TRANSFER_SIZE)?,
+        out_transfer_size: read_u32(&key, OUT_TRANSFER_SIZE)?,
+    })
+}
+
+/// Write the driver parameters of an FTDI device. IE: "COM4"
+///
+/// NOTE writing to the device's registry key requires administrator rights, and the driver only
+/// reads the parameters when the device is started. Unplug and replug the device for the
+/// parameters to take effect
+pub fn set_params<P: AsRef<OsStr>>(port: P, params: &FtdiParams) -> Result<(), RegistryError> {
+    params.validate()?;
+    let key = device_parameters(port.as_ref(), KEY_READ | KEY_SET_VALUE)?;
+    if let Some(latency) = params.latency_timer {
+        key.set_u32(LATENCY_TIMER, latency.as_millis() as _)?;
+    }
+    if let Some(size) = params.in_transfer_size {
+        key.set_u32(IN_TRANSFER_SIZE, size)?;
+    }
+    if let Some(size) = params.out_transfer_size {
+        key.set_u32(OUT_TRANSFER_SIZE, size)?;
+    }
+    trace!(port = ?port.as_ref(), ?params, "updated ftdi parameters");
+    Ok(())
+}
+
+/// Read a DWORD value which may not exist
+fn read_u32(key: &Hkey, name: &str) -> Result<Option<u32>, RegistryError> {
+    match key.get_value::<u32>(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(RegistryError::Win32(Win32Error(ERROR_FILE_NOT_FOUND))) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Find the `Device Parameters` key of the FTDI device which was assigned the port name
+fn device_parameters(port: &OsStr, access: u32) -> Result<Hkey, RegistryError> {
+    let bus = hkey::open(PredefinedHkey::LOCAL_MACHINE, FTDIBUS)?;
+    for device in bus.subkeys()? {
+        let device = bus.open_subkey(device, KEY_READ)?;
+        for instance in device.subkeys()? {
+            let mut path = instance;
+            path.push("\\Device Parameters");
+            // Devices which were never started have no parameters
+            let Ok(key) = device.open_subkey(path, access) else {
+                continue;
+            };
+            let Ok(name) = key.get_value::<OsString>(PORT_NAME) else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case(port) {
+                return Ok(key);
+            }
+        }
+    }
+    Err(RegistryError::ComPortMissingFromRegistry(OsString::from(
+        port,
+    )))
+}
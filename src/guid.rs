@@ -14,6 +14,7 @@ impl fmt::Display for InvalidUuidString {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Guid(pub windows_sys::core::GUID);
 impl Guid {
     /// Create a new Guid from an OsString. Will return an encoded wide version of the OsString on
@@ -46,6 +47,36 @@ impl PartialEq for Guid {
     }
 }
 
+impl Eq for Guid {}
+
+/// Formats the canonical registry form. IE: {4D36E978-E325-11CE-BFC1-08002BE10318}
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = &self.0.data4;
+        write!(
+            f,
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            self.0.data1,
+            self.0.data2,
+            self.0.data3,
+            d[0],
+            d[1],
+            d[2],
+            d[3],
+            d[4],
+            d[5],
+            d[6],
+            d[7]
+        )
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 impl From<windows_sys::core::GUID> for Guid {
     fn from(value: windows_sys::core::GUID) -> Self {
         Self(value)
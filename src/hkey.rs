@@ -1,9 +1,31 @@
 //! hkey
-use super::wchar::from_wide;
+use super::wchar::from_wide_len;
+use crate::{
+    event::{Event, EventError, EventInitialState, EventListener, EventReset, Waiting},
+    name::ComPortName,
+};
+use futures::{ready, FutureExt, Stream};
+use parking_lot::Mutex;
 use regex::Regex;
-use std::{borrow::Cow, collections::HashMap, error, ffi::OsString, fmt, io};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error,
+    ffi::{OsStr, OsString},
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    os::windows::io::AsRawHandle,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
 use tracing::{trace, warn};
-use windows_sys::Win32::{Foundation::ERROR_SUCCESS, System::Registry::*};
+use windows_sys::Win32::{
+    Foundation::{ERROR_SUCCESS, TRUE},
+    System::Registry::*,
+};
 
 #[derive(Debug)]
 pub struct UnexpectedRegistryData {
@@ -42,6 +64,33 @@ impl fmt::Display for UnexpectedRegistryData {
     }
 }
 
+/// A system error code returned by a registry function. Registry functions return the code rather
+/// than setting the last error of the thread, so [`io::Error::last_os_error`] does not apply
+///
+/// https://learn.microsoft.com/en-us/windows/win32/debug/system-error-codes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Win32Error(pub u32);
+
+impl Win32Error {
+    /// The system error code. IE: ERROR_FILE_NOT_FOUND
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+}
+
+impl error::Error for Win32Error {}
+impl fmt::Display for Win32Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&io::Error::from(*self), f)
+    }
+}
+
+impl From<Win32Error> for io::Error {
+    fn from(value: Win32Error) -> io::Error {
+        io::Error::from_raw_os_error(value.0 as _)
+    }
+}
+
 impl From<UnexpectedRegistryData> for io::Error {
     fn from(value: UnexpectedRegistryData) -> io::Error {
         io::Error::new(io::ErrorKind::Other, value.to_string())
@@ -61,11 +110,16 @@ impl RegistryData {
         Self { data, ty }
     }
 
+    /// The data as a wide string, bounded by the data length. The data of a string value is not
+    /// guaranteed to be null terminated
+    fn wide(&self) -> OsString {
+        // Safety: from_wide_len reads no further than the data length, unaligned
+        unsafe { from_wide_len(self.data.as_ptr() as _, self.data.len() / 2) }
+    }
+
     pub fn try_into_expanded_os_string(self) -> Result<OsString, UnexpectedRegistryData> {
         match self.ty {
-            // Safety: NOTE this is unsound, as the data might not be null terminated.
-            //         TODO - make a from_nwide which excepts a len param and use this instead
-            REG_SZ => unsafe { Ok(from_wide(self.data.as_ptr() as _)) },
+            REG_SZ => Ok(self.wide()),
             REG_EXPAND_SZ => todo!("expand the inner string"),
             val => Err(UnexpectedRegistryData {
                 expect: REG_EXPAND_SZ,
@@ -77,9 +131,7 @@ impl RegistryData {
 
     pub fn try_into_os_string(self) -> Result<OsString, UnexpectedRegistryData> {
         match self.ty {
-            // Safety: NOTE this is unsound, as the data might not be null terminated.
-            //         TODO - make a from_nwide which excepts a len param and use this instead
-            REG_EXPAND_SZ | REG_SZ => unsafe { Ok(from_wide(self.data.as_ptr() as _)) },
+            REG_EXPAND_SZ | REG_SZ => Ok(self.wide()),
             val => Err(UnexpectedRegistryData {
                 expect: REG_SZ,
                 actual: val,
@@ -110,14 +162,11 @@ impl RegistryData {
     pub fn try_into_u64(self) -> Result<u64, UnexpectedRegistryData> {
         let mut bytes: [u8; 8] = [0; 8];
         match self.ty {
-            REG_DWORD if self.data.len() == 8 => {
+            // NOTE there is no big endian QWORD
+            REG_QWORD if self.data.len() == 8 => {
                 bytes.copy_from_slice(self.data.as_slice());
                 Ok(u64::from_le_bytes(bytes))
             }
-            REG_DWORD_BIG_ENDIAN if self.data.len() == 8 => {
-                bytes.copy_from_slice(self.data.as_slice());
-                Ok(u64::from_be_bytes(bytes))
-            }
             actual => Err(UnexpectedRegistryData {
                 expect: REG_QWORD,
                 actual,
@@ -125,11 +174,112 @@ impl RegistryData {
             }),
         }
     }
+
+    pub fn try_into_bytes(self) -> Result<Vec<u8>, UnexpectedRegistryData> {
+        match self.ty {
+            REG_BINARY => Ok(self.data),
+            actual => Err(UnexpectedRegistryData {
+                expect: REG_BINARY,
+                actual,
+                data: self.data,
+            }),
+        }
+    }
+
+    /// Convert the data into any type which can be read from the registry. IE:
+    ///
+    /// ```ignore
+    /// let port = data.try_into::<OsString>()?;
+    /// ```
+    pub fn try_into<T: FromRegistryData>(self) -> Result<T, UnexpectedRegistryData> {
+        T::from_registry_data(self)
+    }
+}
+
+impl From<u32> for RegistryData {
+    fn from(value: u32) -> Self {
+        Self::from_data(REG_DWORD, value.to_le_bytes().to_vec())
+    }
+}
+
+impl From<u64> for RegistryData {
+    fn from(value: u64) -> Self {
+        Self::from_data(REG_QWORD, value.to_le_bytes().to_vec())
+    }
+}
+
+impl From<&OsStr> for RegistryData {
+    fn from(value: &OsStr) -> Self {
+        // The data of a string includes the terminating null
+        let data = crate::wchar::to_wide(value)
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        Self::from_data(REG_SZ, data)
+    }
+}
+
+impl From<&str> for RegistryData {
+    fn from(value: &str) -> Self {
+        Self::from(OsStr::new(value))
+    }
+}
+
+impl From<Vec<u8>> for RegistryData {
+    fn from(value: Vec<u8>) -> Self {
+        Self::from_data(REG_BINARY, value)
+    }
+}
+
+/// A type which can be converted from [`RegistryData`]. See [`RegistryData::try_into`]
+pub trait FromRegistryData: Sized {
+    /// The value types [`Hkey::get_value`] is restricted to. IE: RRF_RT_REG_DWORD
+    const RESTRICT: u32 = RRF_RT_ANY;
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData>;
 }
 
+impl FromRegistryData for u32 {
+    const RESTRICT: u32 = RRF_RT_REG_DWORD;
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData> {
+        data.try_into_u32()
+    }
+}
+
+impl FromRegistryData for u64 {
+    const RESTRICT: u32 = RRF_RT_REG_QWORD;
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData> {
+        data.try_into_u64()
+    }
+}
+
+impl FromRegistryData for OsString {
+    // REG_EXPAND_SZ values are expanded into REG_SZ values
+    const RESTRICT: u32 = RRF_RT_REG_SZ;
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData> {
+        data.try_into_os_string()
+    }
+}
+
+impl FromRegistryData for Vec<u8> {
+    const RESTRICT: u32 = RRF_RT_REG_BINARY;
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData> {
+        data.try_into_bytes()
+    }
+}
+
+impl FromRegistryData for RegistryData {
+    fn from_registry_data(data: RegistryData) -> Result<Self, UnexpectedRegistryData> {
+        Ok(data)
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct PredefinedHkey(HKEY);
 impl PredefinedHkey {
     pub const LOCAL_MACHINE: PredefinedHkey = Self(HKEY_LOCAL_MACHINE);
+    pub const CURRENT_USER: PredefinedHkey = Self(HKEY_CURRENT_USER);
+    pub const USERS: PredefinedHkey = Self(HKEY_USERS);
+    pub const CLASSES_ROOT: PredefinedHkey = Self(HKEY_CLASSES_ROOT);
 }
 impl From<PredefinedHkey> for HKEY {
     fn from(value: PredefinedHkey) -> Self {
@@ -156,11 +306,18 @@ pub struct HkeyInfo {
 pub struct Hkey(isize);
 
 impl Hkey {
+    /// Take ownership of an open key. The key is closed when dropped
+    ///
+    /// Safety: the key must be open, and not closed by anyone else
+    pub(crate) unsafe fn from_raw(key: HKEY) -> Hkey {
+        Hkey(key)
+    }
+
     /// Query the key and populate a [`crate::hkey::HkeyInfo`] struct
     ///
     /// [See also]
     /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regqueryinfokeyw)
-    pub fn info(&self) -> io::Result<HkeyInfo> {
+    pub fn info(&self) -> Result<HkeyInfo, Win32Error> {
         let mut num_subkeys = 0;
         let mut num_values = 0;
         let mut max_subkey_name_len = 0;
@@ -190,7 +347,209 @@ impl Hkey {
                 max_value_name_len: max_value_name_len as _,
                 max_value_len: max_value_len as _,
             }),
-            _ => Err(io::Error::last_os_error()),
+            status => Err(Win32Error(status)),
+        }
+    }
+
+    /// Open a subkey of this key with the requested access rights. IE: KEY_READ | KEY_SET_VALUE
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regopenkeyexw)
+    pub fn open_subkey<K: Into<OsString>>(
+        &self,
+        subkey: K,
+        access: u32,
+    ) -> Result<Hkey, Win32Error> {
+        let name = crate::wchar::to_wide(subkey);
+        let mut key: HKEY = 0;
+        match unsafe { RegOpenKeyExW(self.0, name.as_ptr(), 0, access, &mut key) } {
+            ERROR_SUCCESS => Ok(Hkey(key)),
+            status => Err(Win32Error(status)),
+        }
+    }
+
+    /// Return the names of the subkeys of this registry key
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regenumkeyexw)
+    pub fn subkeys(&self) -> Result<Vec<OsString>, Win32Error> {
+        let info = self.info()?;
+        (0..info.num_subkeys)
+            .map(|index| {
+                // Lengths exclude the terminating null
+                let mut name_len = info.max_subkey_name_len as u32 + 1;
+                let mut name = vec![0u16; name_len as _];
+                let status = unsafe {
+                    RegEnumKeyExW(
+                        self.0,
+                        index as _,
+                        name.as_mut_ptr(),
+                        &mut name_len,
+                        std::ptr::null(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    )
+                };
+                match status {
+                    // Safety: RegEnumKeyExW wrote name_len chars, excluding the null
+                    ERROR_SUCCESS => Ok(unsafe { from_wide_len(name.as_ptr(), name_len as _) }),
+                    status => Err(Win32Error(status)),
+                }
+            })
+            .collect()
+    }
+
+    /// Read a single value of this key
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regqueryvalueexw)
+    pub fn value<N: Into<OsString>>(&self, name: N) -> Result<RegistryData, Win32Error> {
+        let name = crate::wchar::to_wide(name);
+        let mut ty = 0;
+        let mut data_len = 0u32;
+        let status = unsafe {
+            RegQueryValueExW(
+                self.0,
+                name.as_ptr(),
+                std::ptr::null(),
+                &mut ty,
+                std::ptr::null_mut(),
+                &mut data_len,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return Err(Win32Error(status));
+        }
+        let mut data = vec![0u8; data_len as _];
+        let status = unsafe {
+            RegQueryValueExW(
+                self.0,
+                name.as_ptr(),
+                std::ptr::null(),
+                &mut ty,
+                data.as_mut_ptr(),
+                &mut data_len,
+            )
+        };
+        match status {
+            ERROR_SUCCESS => {
+                data.truncate(data_len as _);
+                Ok(RegistryData::from_data(ty, data))
+            }
+            status => Err(Win32Error(status)),
+        }
+    }
+
+    /// Read a single value of this key, restricted to the value types of T. IE: read a DWORD
+    ///
+    /// ```ignore
+    /// let latency = key.get_value::<u32>("LatencyTimer")?;
+    /// ```
+    ///
+    /// Environment variables of REG_EXPAND_SZ strings are expanded
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-reggetvaluew)
+    pub fn get_value<T: FromRegistryData>(&self, name: impl Into<OsString>) -> ScanResult<T> {
+        let name = crate::wchar::to_wide(name);
+        let mut ty = 0;
+        let mut data_len = 0u32;
+        let status = unsafe {
+            RegGetValueW(
+                self.0,
+                std::ptr::null(),
+                name.as_ptr(),
+                T::RESTRICT,
+                &mut ty,
+                std::ptr::null_mut(),
+                &mut data_len,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return Err(Win32Error(status).into());
+        }
+        let mut data = vec![0u8; data_len as _];
+        let status = unsafe {
+            RegGetValueW(
+                self.0,
+                std::ptr::null(),
+                name.as_ptr(),
+                T::RESTRICT,
+                &mut ty,
+                data.as_mut_ptr() as _,
+                &mut data_len,
+            )
+        };
+        match status {
+            ERROR_SUCCESS => {
+                data.truncate(data_len as _);
+                Ok(RegistryData::from_data(ty, data).try_into()?)
+            }
+            status => Err(Win32Error(status).into()),
+        }
+    }
+
+    /// Write a DWORD value to this key. The key must be opened with KEY_SET_VALUE
+    pub fn set_u32<N: Into<OsString>>(&self, name: N, value: u32) -> Result<(), Win32Error> {
+        self.set_value(name, RegistryData::from(value))
+    }
+
+    /// Write a value to this key, replacing the value of the same name. The key must be opened
+    /// with KEY_SET_VALUE. IE: to override a port name
+    ///
+    /// ```ignore
+    /// key.set_value("PortName", RegistryData::from("COM42"))?;
+    /// ```
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regsetvalueexw)
+    pub fn set_value<N: Into<OsString>>(
+        &self,
+        name: N,
+        value: RegistryData,
+    ) -> Result<(), Win32Error> {
+        let name = crate::wchar::to_wide(name);
+        let status = unsafe {
+            RegSetValueExW(
+                self.0,
+                name.as_ptr(),
+                0,
+                value.ty,
+                value.data.as_ptr(),
+                value.data.len() as _,
+            )
+        };
+        match status {
+            ERROR_SUCCESS => Ok(()),
+            status => Err(Win32Error(status)),
+        }
+    }
+
+    /// Create a subkey of this key, or open the subkey when it exists. The subkey is opened with
+    /// KEY_WRITE. This key must be opened with KEY_CREATE_SUB_KEY
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regcreatekeyexw)
+    pub fn create_subkey<K: Into<OsString>>(&self, subkey: K) -> Result<Hkey, Win32Error> {
+        let name = crate::wchar::to_wide(subkey);
+        let mut key: HKEY = 0;
+        let status = unsafe {
+            RegCreateKeyExW(
+                self.0,
+                name.as_ptr(),
+                0,
+                std::ptr::null(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_WRITE,
+                std::ptr::null(),
+                &mut key,
+                std::ptr::null_mut(),
+            )
+        };
+        match status {
+            ERROR_SUCCESS => Ok(Hkey(key)),
+            status => Err(Win32Error(status)),
         }
     }
 
@@ -198,7 +557,7 @@ impl Hkey {
     ///
     /// [See also]
     /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regenumvaluew)
-    pub fn into_values(self) -> io::Result<HkeyValueIter> {
+    pub fn into_values(self) -> Result<HkeyValueIter, Win32Error> {
         let info = self.info()?;
         Ok(HkeyValueIter {
             hkey: self,
@@ -206,6 +565,110 @@ impl Hkey {
             index: 0,
         })
     }
+
+    /// Watch this key and its subkeys for changes. The stream yields each time the key changed.
+    /// IE: to observe COM ports when device broadcasts are unreliable
+    ///
+    /// ```ignore
+    /// let mut changes = open(PredefinedHkey::LOCAL_MACHINE, "HARDWARE\\DEVICEMAP\\SERIALCOMM")?
+    ///     .watch()?;
+    /// while changes.try_next().await?.is_some() {
+    ///     let ports = scan()?;
+    /// }
+    /// ```
+    ///
+    /// NOTE a change does not say what changed, and changes made while the stream is not polled
+    /// are reported once. Read the key again after each change.
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regnotifychangekeyvalue)
+    pub fn watch(self) -> io::Result<HkeyChanges> {
+        Ok(HkeyChanges {
+            waiting: None,
+            listener: EventListener::new()?,
+            key: self,
+            event: Event::anonymous(EventReset::Automatic, EventInitialState::Unset)?,
+            started: false,
+        })
+    }
+}
+
+/// The changes reported by [`Hkey::watch`]. A value being added, removed or written, or a subkey
+/// being added or removed
+const WATCH_FILTER: u32 =
+    REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET | REG_NOTIFY_THREAD_AGNOSTIC;
+
+/// Ask the kernel to signal the event on the next change of the key, see [`WATCH_FILTER`]
+fn notify_change(key: &Hkey, event: &Event) -> Result<(), Win32Error> {
+    let event = event.as_raw_handle() as _;
+    match unsafe { RegNotifyChangeKeyValue(key.0, TRUE, WATCH_FILTER, event, TRUE) } {
+        ERROR_SUCCESS => Ok(()),
+        status => Err(Win32Error(status)),
+    }
+}
+
+/// A stream of changes to a registry key created by [`Hkey::watch`]
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The listener must stop waiting on the event
+/// before the event is closed. Closing the key completes the pending notification, so the event
+/// is closed last
+pub struct HkeyChanges {
+    waiting: Option<Waiting>,
+    listener: EventListener,
+    key: Hkey,
+    event: Event,
+    started: bool,
+}
+
+impl HkeyChanges {
+    /// Ask the kernel to signal our event on the next change, and wait for the event. A
+    /// notification only reports a single change
+    fn notify(&mut self) -> io::Result<Waiting> {
+        notify_change(&self.key, &self.event)?;
+        match self.started {
+            false => {
+                self.started = true;
+                Ok(self.listener.start(&self.event, None))
+            }
+            true => self
+                .listener
+                .restart(&self.event, None)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl fmt::Debug for HkeyChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HkeyChanges")
+            .field("waiting", &self.waiting.is_some())
+            .finish()
+    }
+}
+
+impl Stream for HkeyChanges {
+    type Item = io::Result<()>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match this.waiting.as_mut() {
+                None => match this.notify() {
+                    Ok(waiting) => this.waiting = Some(waiting),
+                    Err(e) => break Poll::Ready(Some(Err(e))),
+                },
+                Some(waiting) => {
+                    let result = ready!(waiting.poll_unpin(cx));
+                    this.waiting = None;
+                    trace!(?result, "registry key changed");
+                    break Poll::Ready(Some(
+                        result
+                            .signaled()
+                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+                    ));
+                }
+            }
+        }
+    }
 }
 
 impl From<Hkey> for HKEY {
@@ -226,11 +689,8 @@ pub struct HkeyValueIter {
     index: usize,
 }
 
-/// NOTE this is unsound it returns an io::Error but is really a "System error"
-///
-/// https://learn.microsoft.com/en-us/windows/win32/debug/system-error-codes
 impl Iterator for HkeyValueIter {
-    type Item = io::Result<(OsString, RegistryData)>;
+    type Item = Result<(OsString, RegistryData), Win32Error>;
     fn next(&mut self) -> Option<Self::Item> {
         // Early return when we are empty
         if self.index == self.info.num_values {
@@ -262,55 +722,549 @@ impl Iterator for HkeyValueIter {
                     // Safety: We allocated worst case buffers and the kernel has initialized
                     // the data pointed to these buffers up to the data length.
                     //
-                    // Safety: value_name has been initialized with a wide char string when
-                    // RegEnumValueW returns success
+                    // Safety: value_name has been initialized with value_name_len wide chars,
+                    // excluding the null, when RegEnumValueW returns success
                     data.set_len(data_len as _);
                     Some(Ok((
-                        from_wide(value_name.as_ptr()),
+                        from_wide_len(value_name.as_ptr(), value_name_len as _),
                         RegistryData::from_data(ty, data),
                     )))
                 }
             }
-            _ => Some(Err(io::Error::last_os_error())),
+            status => Some(Err(Win32Error(status))),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
-#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+/// The vendor and product of a port. Ports compare equal by their vendor and product ignoring
+/// case, which is how [`crate::prelude::DeviceStreamExt::track`] matches ids. Identical adapters
+/// are told apart by their serial number instead, see [`Self::serial`] and
+/// [`crate::prelude::DeviceStreamExt::track_serials`]. Ports without ids, IE: Bluetooth ports,
+/// compare equal by their kind and Bluetooth address
+///
+/// Build a port with [`PortMeta::new`] and [`PortMeta::with_serial`]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "PortMetaFields"))]
 pub struct PortMeta {
     pub vendor: String,
     pub product: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    serial: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    interface: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    friendly_name: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    description: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    location: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    location_paths: Vec<String>,
+    kind: PortKind,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    bluetooth_address: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    composite_parent: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    in_use: Option<bool>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    problem: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    power_state: Option<PowerState>,
+}
+
+/// The transport and driver of a port, derived from the enumerator and the bound driver of the
+/// device. IE: to open a port differently per transport
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PortKind {
+    /// A USB device of the Communications Device Class, bound to the inbox usbser driver
+    #[default]
+    UsbCdcAcm,
+    /// A USB device bound to the driver of its vendor
+    UsbVendor(UsbVendor),
+    /// A virtual port of the Bluetooth Serial Port Profile. The port has no vendor or product id,
+    /// see [`PortMeta::bluetooth_address`]
+    Bluetooth,
+    /// A port of a software driver. IE: com0com. The port has no vendor or product id
+    Virtual,
+    /// A UART of the machine enumerated by ACPI or PCI. The port has no vendor or product id
+    Onboard,
+}
+
+/// The vendor driver of a [`PortKind::UsbVendor`] port
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UsbVendor {
+    /// FTDI, enumerated by the FTDIBUS driver
+    Ftdi,
+    /// Silicon Labs CP210x
+    Cp210x,
+    /// WCH CH340 and CH341
+    Ch34x,
+    /// Any other vendor driver. IE: Prolific
+    Other,
+}
+
+impl PortKind {
+    /// Classify a port by the enumerator of its device. IE: USB. The service of the bound driver
+    /// is preferred for USB devices, and the vendor id is used when the service is unknown
+    pub(crate) fn classify(enumerator: &str, service: Option<&str>, vid: Option<u16>) -> PortKind {
+        let service = service.map(str::to_ascii_lowercase);
+        match enumerator.to_ascii_uppercase().as_str() {
+            "BTHENUM" => PortKind::Bluetooth,
+            "FTDIBUS" => PortKind::UsbVendor(UsbVendor::Ftdi),
+            "ACPI" | "PCI" => PortKind::Onboard,
+            "USB" => match (service.as_deref(), vid) {
+                (Some("usbser"), _) => PortKind::UsbCdcAcm,
+                (Some("ftdibus" | "ftser2k"), _) | (None, Some(0x0403)) => {
+                    PortKind::UsbVendor(UsbVendor::Ftdi)
+                }
+                (Some("silabser"), _) | (None, Some(0x10c4)) => {
+                    PortKind::UsbVendor(UsbVendor::Cp210x)
+                }
+                (Some(service), _) if service.starts_with("ch341ser") => {
+                    PortKind::UsbVendor(UsbVendor::Ch34x)
+                }
+                (None, Some(0x1a86)) => PortKind::UsbVendor(UsbVendor::Ch34x),
+                (Some(_), _) => PortKind::UsbVendor(UsbVendor::Other),
+                (None, _) => PortKind::UsbCdcAcm,
+            },
+            _ => PortKind::Virtual,
+        }
+    }
+}
+
+/// The most recent power state of the device of a port. IE: a selectively suspended USB device is
+/// in D2
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PowerState {
+    /// Working
+    D0,
+    D1,
+    D2,
+    /// Off
+    D3,
+    /// The driver did not report a state
+    Unspecified,
+}
+
+impl PowerState {
+    /// The power state of a DEVICE_POWER_STATE
+    pub(crate) fn from_raw(state: i32) -> PowerState {
+        match state {
+            1 => PowerState::D0,
+            2 => PowerState::D1,
+            3 => PowerState::D2,
+            4 => PowerState::D3,
+            _ => PowerState::Unspecified,
+        }
+    }
+}
+
+/// The enumerator of a device interface. IE: USB for `\\?\usb#vid_2fe3&pid_0100#123#{...}`
+fn enumerator(interface: &str) -> Option<&str> {
+    interface
+        .trim_start_matches("\\\\?\\")
+        .split(['#', '\\'])
+        .next()
+        .filter(|enumerator| !enumerator.is_empty())
+}
+
+impl PartialEq for PortMeta {
+    fn eq(&self, other: &Self) -> bool {
+        self.vendor.eq_ignore_ascii_case(&other.vendor)
+            && self.product.eq_ignore_ascii_case(&other.product)
+            && (!self.without_ids()
+                || (self.kind == other.kind && self.bluetooth_address == other.bluetooth_address))
+    }
+}
+
+impl Eq for PortMeta {}
+
+impl Hash for PortMeta {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal ports must hash the same, so the case is normalized like in eq
+        self.vendor.to_ascii_lowercase().hash(state);
+        self.product.to_ascii_lowercase().hash(state);
+        if self.without_ids() {
+            self.kind.hash(state);
+            self.bluetooth_address.hash(state);
+        }
+    }
+}
+
+/// The serialized fields of a [`PortMeta`]. The vendor and product are normalized like
+/// [`PortMeta::new`] when deserialized
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct PortMetaFields {
+    vendor: String,
+    product: String,
+    serial: Option<String>,
+    interface: Option<u8>,
+    friendly_name: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    #[serde(default)]
+    location_paths: Vec<String>,
+    #[serde(default)]
+    kind: PortKind,
+    bluetooth_address: Option<String>,
+    composite_parent: Option<String>,
+    in_use: Option<bool>,
+    problem: Option<u32>,
+    power_state: Option<PowerState>,
+}
+
+#[cfg(feature = "serde")]
+impl From<PortMetaFields> for PortMeta {
+    fn from(fields: PortMetaFields) -> Self {
+        PortMeta {
+            serial: fields.serial,
+            interface: fields.interface,
+            friendly_name: fields.friendly_name,
+            description: fields.description,
+            location: fields.location,
+            location_paths: fields.location_paths,
+            kind: fields.kind,
+            bluetooth_address: fields.bluetooth_address,
+            composite_parent: fields.composite_parent,
+            in_use: fields.in_use,
+            problem: fields.problem,
+            power_state: fields.power_state,
+            ..PortMeta::from((fields.vendor, fields.product))
+        }
+    }
+}
+
+/// The vendor and product as uppercase hex digits. IE: "2FE3:0100"
+impl fmt::Display for PortMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vendor = self.vendor.to_uppercase();
+        let product = self.product.to_uppercase();
+        write!(f, "{vendor}:{product}")
+    }
+}
+
+/// The string is not a "vid:pid" pair of hex ids. See [`PortMeta`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("expected a vid:pid pair of hex ids, found {0:?}")]
+pub struct ParsePortMetaError(String);
+
+/// Parse a vendor and product from "vid:pid". IE: "2fe3:0100". The ids are 4 hex digits
+impl FromStr for PortMeta {
+    type Err = ParsePortMetaError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = |id: &str| match id.len() == 4 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => u16::from_str_radix(id, 16).ok(),
+            false => None,
+        };
+        s.trim()
+            .split_once(':')
+            .and_then(|(vid, pid)| Some(PortMeta::from((id(vid)?, id(pid)?))))
+            .ok_or_else(|| ParsePortMetaError(s.to_string()))
+    }
 }
 
 impl PortMeta {
+    /// A port of a vendor and product id, as lowercase hex digits
+    fn with_ids(vendor: String, product: String) -> PortMeta {
+        PortMeta {
+            vendor,
+            product,
+            serial: None,
+            interface: None,
+            friendly_name: None,
+            description: None,
+            location: None,
+            location_paths: Vec::new(),
+            kind: PortKind::UsbCdcAcm,
+            bluetooth_address: None,
+            composite_parent: None,
+            in_use: None,
+            problem: None,
+            power_state: None,
+        }
+    }
+
+    /// Parse the device interface of a port. Ports without a vendor and product id are
+    /// recognized by their enumerator, see [`PortKind`]
     pub fn parse_registry(s: &str) -> Option<PortMeta> {
         let re = Regex::new("(vid_|pid_).{4}").unwrap();
         let mut caps: Vec<String> = re
             .find_iter(s)
             .map(|m| m.as_str()[4..].to_string())
             .collect();
+        let enumerator = enumerator(s)?;
+        let (Some(product), Some(vendor)) = (caps.pop(), caps.pop()) else {
+            return match PortKind::classify(enumerator, None, None) {
+                PortKind::Bluetooth => PortMeta::parse_bluetooth(s),
+                kind @ (PortKind::Virtual | PortKind::Onboard) => Some(PortMeta {
+                    kind,
+                    ..PortMeta::with_ids(String::new(), String::new())
+                }),
+                // A USB device must have ids
+                _ => None,
+            };
+        };
+        let meta = PortMeta::with_ids(vendor, product);
+        Some(PortMeta {
+            serial: parse_serial(s),
+            interface: parse_interface(s),
+            kind: PortKind::classify(enumerator, None, meta.vid_u16()),
+            ..meta
+        })
+    }
+
+    /// A Bluetooth port has no vendor or product id, and the device instance ends with the
+    /// address of the remote device. IE:
+    /// `\\?\bthenum#{00001101-...}_localmfg&0002#7&2a0a3e8f&0&001122334455_c00000000#{...}`
+    fn parse_bluetooth(s: &str) -> Option<PortMeta> {
+        let re = Regex::new(r"(?i)bthenum[#\\][^#\\]*[#\\][^#\\]*&([0-9a-f]{12})_").unwrap();
+        let address = re.captures(s)?.get(1)?.as_str();
+        // Incoming ports wait for any remote device
+        let bluetooth_address = match address.bytes().all(|b| b == b'0') {
+            true => None,
+            false => Some(
+                (0..address.len())
+                    .step_by(2)
+                    .map(|n| address[n..n + 2].to_uppercase())
+                    .collect::<Vec<_>>()
+                    .join(":"),
+            ),
+        };
         Some(PortMeta {
-            product: caps.pop()?,
-            vendor: caps.pop()?,
+            kind: PortKind::Bluetooth,
+            bluetooth_address,
+            ..PortMeta::with_ids(String::new(), String::new())
         })
     }
 
+    /// A Bluetooth, virtual or onboard port has no vendor or product id
+    fn without_ids(&self) -> bool {
+        self.vendor.is_empty() && self.product.is_empty()
+    }
+
+    /// The transport of the port
+    pub fn kind(&self) -> PortKind {
+        self.kind
+    }
+
+    /// The address of the remote device of a Bluetooth port. IE: "00:11:22:33:44:55". None when
+    /// the port is not a Bluetooth port, or is an incoming port which accepts any remote device
+    pub fn bluetooth_address(&self) -> Option<&str> {
+        self.bluetooth_address.as_deref()
+    }
+
+    /// The vendor id as a number. None when the vendor is not a hex number
+    pub fn vid_u16(&self) -> Option<u16> {
+        u16::from_str_radix(&self.vendor, 16).ok()
+    }
+
+    /// The product id as a number. None when the product is not a hex number
+    pub fn pid_u16(&self) -> Option<u16> {
+        u16::from_str_radix(&self.product, 16).ok()
+    }
+
+    /// The serial number of the USB device. Two identical adapters differ only by their serial
+    /// number. None when the device does not report a serial number
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// A port of a vendor and product id. IE: `PortMeta::new("2FE3", "0100")`
+    pub fn new<V: UsbId, P: UsbId>(vendor: V, product: P) -> PortMeta {
+        PortMeta::from((vendor, product))
+    }
+
+    /// Set the serial number of the port. IE: to describe a device which is not connected
+    pub fn with_serial<S: Into<String>>(mut self, serial: S) -> Self {
+        self.serial = Some(serial.into());
+        self
+    }
+
+    /// The interface number of a function of a composite USB device. IE: 2 for `MI_02`. None when
+    /// the device is not composite
+    pub fn interface(&self) -> Option<u8> {
+        self.interface
+    }
+
+    /// The name Windows shows for the device. IE: "USB Serial Device (COM7)"
+    pub fn friendly_name(&self) -> Option<&str> {
+        self.friendly_name.as_deref()
+    }
+
+    /// The description the device reported to the bus. IE: "STM32 Virtual ComPort"
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// The hub and port the device is plugged into. IE: "Port_#0002.Hub_#0004"
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// The paths from the root of the system to the device. IE:
+    /// "PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(2)#USB(4)". The path of a port does not change while
+    /// the device stays plugged into the same hub port, so identical adapters can be told apart
+    pub fn location_paths(&self) -> &[String] {
+        &self.location_paths
+    }
+
+    /// The device instance of the composite USB device a function belongs to. IE:
+    /// `USB\VID_2FE3&PID_0002\A12345` for the `MI_02` function. The other functions of the same
+    /// physical device, IE: a DFU interface, are the children of the parent. See
+    /// [`crate::pnp::children`]. None when the device is not composite
+    pub fn composite_parent(&self) -> Option<&str> {
+        self.composite_parent.as_deref()
+    }
+
+    /// The serial number of the composite USB device a function belongs to. The instance of a
+    /// function is generated by Windows, so the serial number is only found on the parent
+    pub fn composite_serial(&self) -> Option<String> {
+        self.composite_parent.as_deref().and_then(parse_serial)
+    }
+
+    /// Whether another handle held the port open when it was probed. None when the port was not
+    /// probed, see [`crate::scan_in_use`]
+    pub fn in_use(&self) -> Option<bool> {
+        self.in_use
+    }
+
+    /// The problem code of the device, IE: 43 when the driver stopped the device because it
+    /// reported a failure. The port is present but does not work. None when the device has no
+    /// problem
+    pub fn problem(&self) -> Option<u32> {
+        self.problem
+    }
+
+    /// The most recent power state of the device. None when the state could not be read
+    pub fn power_state(&self) -> Option<PowerState> {
+        self.power_state
+    }
+
+    /// Probe whether another handle holds the port open, see [`crate::port::in_use`]
+    pub(crate) fn with_in_use(mut self, port: &OsStr) -> Self {
+        match crate::port::in_use(port) {
+            Ok(in_use) => self.in_use = Some(in_use),
+            Err(error) => warn!(?error, ?port, "unable to probe port"),
+        }
+        self
+    }
+
+    /// Describe the device with names which were already read. IE: from WMI
+    pub(crate) fn with_names(
+        mut self,
+        friendly_name: Option<String>,
+        description: Option<String>,
+    ) -> Self {
+        self.friendly_name = friendly_name;
+        self.description = description;
+        self
+    }
+
+    /// Read the properties of the device of a device interface which describe it to a user
+    pub(crate) fn with_device_properties(mut self, interface: &str) -> Self {
+        let described =
+            device_instance(interface).map(|instance| crate::pnp::describe(OsStr::new(&instance)));
+        match described {
+            Some(Ok(description)) => {
+                self.friendly_name = description.friendly_name;
+                self.description = description.description;
+                self.location = description.location;
+                self.location_paths = description.location_paths;
+                self.problem = description.problem;
+                self.power_state = description.power_state;
+                if self.interface.is_some() {
+                    self.composite_parent = description.parent;
+                }
+                if let (Some(enumerator), Some(service)) =
+                    (enumerator(interface), description.service)
+                {
+                    self.kind = PortKind::classify(enumerator, Some(&service), self.vid_u16());
+                }
+            }
+            Some(Err(error)) => warn!(?error, interface, "unable to describe device"),
+            None => {}
+        }
+        self
+    }
+
     pub fn matches(&self, vid: &str, pid: &str) -> bool {
         vid == self.vendor.to_lowercase() && pid == self.product.to_lowercase()
     }
 }
 
-impl<'v, 'p, V, P> From<(V, P)> for PortMeta
-where
-    V: Into<Cow<'v, str>>,
-    P: Into<Cow<'p, str>>,
-{
+/// A vendor or product id. IE: "0403", or 0x0403
+pub trait UsbId {
+    /// The id as lowercase hex digits
+    fn into_hex(self) -> String;
+}
+
+impl UsbId for u16 {
+    fn into_hex(self) -> String {
+        format!("{self:04x}")
+    }
+}
+
+impl UsbId for &str {
+    fn into_hex(self) -> String {
+        self.to_lowercase()
+    }
+}
+
+impl UsbId for String {
+    fn into_hex(self) -> String {
+        self.to_lowercase()
+    }
+}
+
+impl UsbId for &String {
+    fn into_hex(self) -> String {
+        self.to_lowercase()
+    }
+}
+
+impl UsbId for Cow<'_, str> {
+    fn into_hex(self) -> String {
+        self.to_lowercase()
+    }
+}
+
+impl<V: UsbId, P: UsbId> From<(V, P)> for PortMeta {
     fn from((vid, pid): (V, P)) -> Self {
-        PortMeta {
-            vendor: vid.into().to_string().to_lowercase(),
-            product: pid.into().to_string().to_lowercase(),
-        }
+        PortMeta::with_ids(vid.into_hex(), pid.into_hex())
+    }
+}
+
+/// The interface number of a composite device in the device interface of a port. IE: the `mi_02`
+/// of `\\?\usb#vid_2fe3&pid_0002&mi_02#7&123456&0&0002#{...}`
+pub(crate) fn parse_interface(interface: &str) -> Option<u8> {
+    let re = Regex::new("(?i)mi_([0-9a-f]{2})").unwrap();
+    let number = re.captures(interface)?.get(1)?.as_str();
+    u8::from_str_radix(number, 16).ok()
+}
+
+/// The serial number in the device interface of a port. IE: the instance `A12345` of
+/// `\\?\USB#VID_0403&PID_6001#A12345#{...}`, or the FTDI bus id `A12345A` of
+/// `\\?\FTDIBUS#VID_0403+PID_6001+A12345A#0000#{...}`. Windows generates the instance of a device
+/// without a serial number, IE: `7&123456&0&1`, which is not a serial number. A device instance,
+/// IE: `USB\VID_0403&PID_6001\A12345`, is parsed the same
+pub(crate) fn parse_serial(interface: &str) -> Option<String> {
+    let mut parts = interface.trim_start_matches("\\\\?\\").split(['#', '\\']);
+    let bus = parts.next()?;
+    let hardware = parts.next()?;
+    let instance = parts.next()?;
+    let serial = match bus.eq_ignore_ascii_case("FTDIBUS") {
+        true => hardware.split('+').nth(2)?,
+        false => instance,
+    };
+    match serial.is_empty() || serial.contains('&') {
+        true => None,
+        false => Some(serial.to_string()),
     }
 }
 
@@ -320,33 +1274,253 @@ pub enum RegistryError {
     UnexpectedRegistryData(#[from] UnexpectedRegistryData),
     #[error("io error => {0}")]
     Io(#[from] io::Error),
+    #[error("registry error => {0}")]
+    Win32(#[from] Win32Error),
     #[error("unable to parse registry data {0:?}")]
     UnableToParseRegistryData(OsString),
     #[error("com port {0:?} missing from registry")]
     ComPortMissingFromRegistry(OsString),
+    #[cfg(feature = "wmi")]
+    #[error("wmi error => {0}")]
+    Wmi(#[from] wmi::WMIError),
+}
+
+/// Which view of the registry a 32-bit process on 64-bit Windows opens. The view is ignored by
+/// 64-bit Windows for keys which are shared by both views
+///
+/// https://learn.microsoft.com/en-us/windows/win32/winprog64/accessing-an-alternate-registry-view
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RegistryView {
+    /// The view of the process. IE: 32-bit processes are redirected to WOW6432Node
+    #[default]
+    Native = 0,
+    /// The 64-bit registry
+    Registry64 = KEY_WOW64_64KEY,
+    /// The 32-bit registry
+    Registry32 = KEY_WOW64_32KEY,
+}
+
+/// How to open a registry key with [`open_with`]. Keys are opened with KEY_READ in the native
+/// view by default
+#[derive(Copy, Clone, Debug)]
+pub struct OpenOptions {
+    access: u32,
+    view: RegistryView,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            access: KEY_READ,
+            view: RegistryView::Native,
+        }
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The access rights of the key. IE: KEY_READ | KEY_SET_VALUE
+    pub fn with_access(mut self, access: u32) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Open the key in a view of the registry. See [`RegistryView`]
+    pub fn with_view(mut self, view: RegistryView) -> Self {
+        self.view = view;
+        self
+    }
+
+    /// The access mask including the view, IE: to pass to [`Hkey::open_subkey`]
+    pub fn access(&self) -> u32 {
+        self.access | self.view as u32
+    }
 }
 
 /// Open a subkey associated with a given parent key
-pub fn open<K: Into<OsString>>(parent: PredefinedHkey, subkey: K) -> io::Result<Hkey> {
+pub fn open<K: Into<OsString>>(parent: PredefinedHkey, subkey: K) -> Result<Hkey, Win32Error> {
+    open_with(parent, subkey, OpenOptions::default())
+}
+
+/// Open a subkey associated with a given parent key with the options. IE: for a 32-bit process
+/// to read the 64-bit registry
+///
+/// ```ignore
+/// let options = OpenOptions::new().with_view(RegistryView::Registry64);
+/// let key = open_with(PredefinedHkey::LOCAL_MACHINE, "SOFTWARE\\FTDI", options)?;
+/// ```
+pub fn open_with<K: Into<OsString>>(
+    parent: PredefinedHkey,
+    subkey: K,
+    options: OpenOptions,
+) -> Result<Hkey, Win32Error> {
     let name = crate::wchar::to_wide(subkey);
     unsafe {
         let mut key: HKEY = 0;
-        match RegOpenKeyExW(
-            parent.into(),
-            name.as_ptr(),
-            0 as _,
-            KEY_READ as _,
-            &mut key,
-        ) {
+        match RegOpenKeyExW(parent.into(), name.as_ptr(), 0, options.access(), &mut key) {
             ERROR_SUCCESS => Ok(Hkey(key)),
-            _ => Err(io::Error::last_os_error()),
+            status => Err(Win32Error(status)),
+        }
+    }
+}
+
+/// A value found by [`walk`]
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// The path of the key holding the value, including the path the walk started from
+    pub path: OsString,
+    /// The name of the value
+    pub name: OsString,
+    /// The data of the value
+    pub data: RegistryData,
+}
+
+/// An iterator of registry values, see [`walk`]
+pub struct Walk<F> {
+    root: PredefinedHkey,
+    predicate: F,
+    // Paths of keys waiting to be visited, the next key to visit is last
+    pending: Vec<OsString>,
+    // The values of the key being visited
+    values: Option<(OsString, HkeyValueIter)>,
+}
+
+impl<F> Iterator for Walk<F>
+where
+    F: FnMut(&WalkEntry) -> bool,
+{
+    type Item = Result<WalkEntry, Win32Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((path, values)) = &mut self.values {
+                match values.next() {
+                    Some(Ok((name, data))) => {
+                        let path = path.clone();
+                        let entry = WalkEntry { path, name, data };
+                        if (self.predicate)(&entry) {
+                            return Some(Ok(entry));
+                        }
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        self.values = None;
+                        return Some(Err(e));
+                    }
+                    None => self.values = None,
+                }
+            }
+            let path = self.pending.pop()?;
+            trace!(?path, "walking registry key");
+            let key = match open(self.root, &path) {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            match key.subkeys() {
+                // Reversed so that the first subkey is visited first
+                Ok(subkeys) => self.pending.extend(subkeys.into_iter().rev().map(|subkey| {
+                    let mut child = path.clone();
+                    if !child.is_empty() {
+                        child.push("\\");
+                    }
+                    child.push(subkey);
+                    child
+                })),
+                Err(e) => return Some(Err(e)),
+            }
+            match key.into_values() {
+                Ok(values) => self.values = Some((path, values)),
+                Err(e) => return Some(Err(e)),
+            }
         }
     }
 }
 
+/// Walk a registry key and all of its subkeys depth first, and return the values accepted by the
+/// predicate. IE: to find the COM port of every USB device
+///
+/// ```ignore
+/// let usb = "SYSTEM\\CurrentControlSet\\Enum\\USB";
+/// let ports = walk(PredefinedHkey::LOCAL_MACHINE, usb, |entry| entry.name == "PortName");
+/// ```
+///
+/// A key which can not be read is returned as an error, and the walk continues with the next key
+pub fn walk<K, F>(root: PredefinedHkey, path: K, predicate: F) -> Walk<F>
+where
+    K: Into<OsString>,
+    F: FnMut(&WalkEntry) -> bool,
+{
+    Walk {
+        root,
+        predicate,
+        pending: vec![path.into()],
+        values: None,
+    }
+}
+
 /// Helper
 pub type ScanResult<T> = Result<T, RegistryError>;
 
+/// The currently connected COM ports. The values name the device, and the data is the port name
+const SERIALCOMM: &str = "HARDWARE\\DEVICEMAP\\SERIALCOMM";
+
+/// The device interfaces of COM ports. The values name the port, and the data is the interface
+const COM_NAME_ARBITER: &str = "SYSTEM\\CurrentControlSet\\Control\\COM Name Arbiter\\Devices";
+
+/// The device interfaces of the COM Name Arbiter, and the notification of the next change
+struct ArbiterCache {
+    interfaces: HashMap<OsString, OsString>,
+    event: Event,
+    // Closing the key ends the notification
+    #[allow(unused)]
+    key: Hkey,
+}
+
+/// The COM Name Arbiter rarely changes, but is read on every arrival and rescan
+static ARBITER: Mutex<Option<ArbiterCache>> = Mutex::new(None);
+
+/// The device interface of every port in the COM Name Arbiter. The interfaces are cached until
+/// the kernel notifies a change of the key
+pub(crate) fn arbiter() -> ScanResult<HashMap<OsString, OsString>> {
+    let mut cache = ARBITER.lock();
+    if let Some(cached) = cache.as_ref() {
+        // The event resets when the wait is satisfied, so a change is seen once
+        match cached.event.wait(Some(Duration::ZERO)) {
+            Err(EventError::Timeout) => return Ok(cached.interfaces.clone()),
+            result => trace!(?result, "com name arbiter changed"),
+        }
+    }
+    *cache = None;
+    let key = open(PredefinedHkey::LOCAL_MACHINE, COM_NAME_ARBITER)?;
+    let event = Event::anonymous(EventReset::Automatic, EventInitialState::Unset)?;
+    // Registered before reading, so that a change while reading is not missed
+    notify_change(&key, &event)?;
+    let interfaces = open(PredefinedHkey::LOCAL_MACHINE, COM_NAME_ARBITER)?
+        .into_values()?
+        .map(|value| {
+            let (port, data) = value?;
+            Ok((port, data.try_into_os_string()?))
+        })
+        .collect::<ScanResult<HashMap<OsString, OsString>>>()?;
+    *cache = Some(ArbiterCache {
+        interfaces: interfaces.clone(),
+        event,
+        key,
+    });
+    Ok(interfaces)
+}
+
+/// The names of the COM ports of the present devices
+pub(crate) fn connected() -> ScanResult<Vec<OsString>> {
+    open(PredefinedHkey::LOCAL_MACHINE, SERIALCOMM)?
+        .into_values()?
+        .map(|value| value?.1.try_into_os_string().map_err(RegistryError::from))
+        .collect()
+}
+
 /// Scan the USB device registry.
 ///
 /// This routine will perform 2 registry lookups. First scan
@@ -358,48 +1532,75 @@ pub type ScanResult<T> = Result<T, RegistryError>;
 /// ports including the Vendor/Product ID's.
 pub fn scan() -> Result<HashMap<OsString, PortMeta>, RegistryError> {
     // We collect all the currently connected COM ports from the registry
-    let connected = open(
-        PredefinedHkey::LOCAL_MACHINE,
-        "HARDWARE\\DEVICEMAP\\SERIALCOMM",
-    )?
-    .into_values()?
-    .map(|value| value?.1.try_into_os_string().map_err(RegistryError::from))
-    .collect::<Result<Vec<OsString>, RegistryError>>()?;
-
-    // We collect all the vender and product id's from the registry
-    let devices = open(
-        PredefinedHkey::LOCAL_MACHINE,
-        "SYSTEM\\CurrentControlSet\\Control\\COM Name Arbiter\\Devices",
-    )?
-    .into_values()?
-    .map(|value| {
-        let (port, data) = value?;
-        let os_str = data.try_into_os_string()?;
-        PortMeta::parse_registry(&os_str.to_string_lossy())
-            .ok_or_else(|| RegistryError::UnableToParseRegistryData(os_str))
-            .map(|meta| (port, meta))
-    })
-    .filter_map(|result| match result {
-        Err(RegistryError::UnableToParseRegistryData(pnp)) => {
-            warn!(pnp=?pnp, "unable to parse registry data");
-            None
-        }
-        result => Some(result),
-    })
-    .collect::<Result<HashMap<OsString, PortMeta>, RegistryError>>()?;
+    let connected = connected()?;
 
-    // Filter the registry map to only list connected devices We loop again because we want to
-    // properly capture errors
-    Ok(devices
+    // We collect the vender and product id's of the connected devices from the cached arbiter
+    let interfaces = arbiter()?;
+    Ok(connected
         .into_iter()
-        .filter(|(port, _)| connected.contains(&port))
+        .filter_map(|port| {
+            let os_str = interfaces.get(&port)?;
+            let interface = os_str.to_string_lossy();
+            match PortMeta::parse_registry(&interface) {
+                Some(meta) => Some((port, meta.with_device_properties(&interface))),
+                None => {
+                    warn!(pnp=?os_str, "unable to parse registry data");
+                    None
+                }
+            }
+        })
         .collect())
 }
 
-/// Scan all the connected usb devices, and return the ID's for a chosen port (if it exists)
+/// Order ports by their port number. Ports which are not named COMn follow, ordered by name
+pub(crate) fn sorted(ports: HashMap<OsString, PortMeta>) -> Vec<(OsString, PortMeta)> {
+    let mut ports: Vec<_> = ports.into_iter().collect();
+    ports.sort_by_cached_key(|(port, _)| {
+        ComPortName::try_from(port.as_os_str()).map_err(|_| port.clone())
+    });
+    ports
+}
+
+/// The device instance of a device interface path. IE: the interface
+/// `\\?\USB#VID_2FE3&PID_0100#123#{86e0d1e0-8089-11d0-9ce4-08003e301f73}` is the device
+/// instance `USB\VID_2FE3&PID_0100\123`
+pub fn device_instance(interface: &str) -> Option<String> {
+    let (instance, _class) = interface.trim_start_matches("\\\\?\\").rsplit_once('#')?;
+    Some(instance.replace('#', "\\"))
+}
+
+/// Read the COM port name of a GUID_DEVINTERFACE_COMPORT device interface from the device
+/// parameters of its device instance. The parameters remain after the device is removed
+pub fn port_name(interface: &OsStr) -> Result<OsString, RegistryError> {
+    let instance = device_instance(&interface.to_string_lossy())
+        .ok_or_else(|| RegistryError::UnableToParseRegistryData(interface.to_owned()))?;
+    let key = format!("SYSTEM\\CurrentControlSet\\Enum\\{instance}\\Device Parameters");
+    trace!(?interface, %key, "reading port name");
+    Ok(open(PredefinedHkey::LOCAL_MACHINE, key)?.get_value::<OsString>("PortName")?)
+}
+
+/// Return the ID's for a chosen port (if it is connected). Unlike [`scan`] only the interface of
+/// the port is parsed, so an arrival does not describe every port
 pub fn scan_for(port: &OsString) -> Result<PortMeta, RegistryError> {
     trace!(?port, "scanning for usb device");
-    self::scan()
-        .map(|mut devices| devices.remove(port))?
-        .ok_or_else(|| RegistryError::ComPortMissingFromRegistry(port.to_owned()))
+    let missing = || RegistryError::ComPortMissingFromRegistry(port.to_owned());
+    let mut connected = false;
+    for value in open(PredefinedHkey::LOCAL_MACHINE, SERIALCOMM)?.into_values()? {
+        if value?.1.try_into_os_string()? == *port {
+            connected = true;
+            break;
+        }
+    }
+    if !connected {
+        return Err(missing());
+    }
+    let os_str = arbiter()?.remove(port).ok_or_else(missing)?;
+    let interface = os_str.to_string_lossy().into_owned();
+    match PortMeta::parse_registry(&interface) {
+        Some(meta) => Ok(meta.with_device_properties(&interface)),
+        None => {
+            warn!(pnp=?os_str, "unable to parse registry data");
+            Err(missing())
+        }
+    }
 }
@@ -3,33 +3,128 @@
 #[cfg(test)]
 mod tests;
 
-// TODO remove pub when we add async io to com port
+pub mod cfgmgr;
 pub mod channel;
+pub mod codec;
+pub mod comdb;
+mod comm;
+#[cfg(feature = "tokio")]
+mod compat;
+pub mod debounce;
+pub mod dedupe;
 pub mod event;
+pub mod ftdi;
 mod guid;
-mod hkey;
+pub mod hkey;
+mod loopback;
+mod name;
+pub mod pnp;
+pub mod port;
+pub mod reactor;
+pub mod renumerate;
+pub mod reopen;
+pub mod timeout;
+#[cfg(feature = "wmi")]
+pub mod wbem;
 mod wchar;
 mod wm;
 
-pub use hkey::{PortMeta, RegistryError};
+use futures::{stream::TakeUntil, StreamExt};
+pub use guid::Guid;
+pub use hkey::{
+    ParsePortMetaError, PortKind, PortMeta, PowerState, RegistryError, UsbId, UsbVendor, Win32Error,
+};
+pub use loopback::{loopback_test, verify_echo, LoopbackError};
+pub use name::{ComPortName, ParseComPortNameError};
+pub use port::{ComPort, OpenError, PortConfig, SyncComPort};
+pub use reopen::{ReopenEvent, ReopeningPort};
 use std::{collections::HashMap, ffi::OsString, io};
-pub use wm::{PlugEvent, WindowEvents};
+pub use timeout::AsyncReadTimeoutExt;
+pub use wm::{
+    AttachedWindow, Command, DeviceInterface, InterfaceEvent, InterfaceKind, ListenerHandle,
+    PausePolicy, PlugEvent, Registry, RemovalVeto, ServiceEvents, ServiceNotifier, SessionEvent,
+    ThreadPriority, VolumeEvent, WindowEvents,
+};
 
-/// Listen for [`wm::WindowEvents`]
-pub fn listen<N>(name: N) -> wm::WindowEvents
+/// Listen for [`wm::WindowEvents`]. Use a [`Registry`] to configure the listener. IE: to
+/// [`Registry::with_rescan_on_resume`]. Returns the error if the listener failed to start
+pub fn listen<N>(name: N) -> io::Result<wm::WindowEvents>
 where
     N: Into<OsString> + Send + Sync + 'static,
 {
     wm::Registry::new().with_serial_port().spawn(name)
 }
 
-/// Get a hash map of all the currently connected devices
+/// Listen for [`wm::WindowEvents`] until the token is cancelled. IE: to stop the listener of a
+/// service from another task. See [`prelude::DeviceStreamExt::until_cancelled`]
+pub fn listen_until<N>(
+    name: N,
+    token: &event::CancellationToken,
+) -> io::Result<TakeUntil<wm::WindowEvents, event::Cancelled>>
+where
+    N: Into<OsString> + Send + Sync + 'static,
+{
+    Ok(listen(name)?.take_until(token.cancelled()?))
+}
+
+/// Listen for [`wm::WindowEvents`] with a unique name. Unlike [`listen`] several listeners can
+/// coexist in one or more processes. Rescan with [`WindowEvents::rescan`]
+pub fn listen_unique() -> io::Result<wm::WindowEvents> {
+    wm::Registry::new().with_serial_port().spawn_unique()
+}
+
+/// Listen for device notifications inside a Windows service, which has no message pump. Forward
+/// the controls of the service to the [`ServiceNotifier`] of the events
+pub fn listen_service(
+    handle: windows_sys::Win32::System::Services::SERVICE_STATUS_HANDLE,
+) -> io::Result<wm::ServiceEvents> {
+    wm::Registry::new().with_serial_port().spawn_service(handle)
+}
+
+/// Get a hash map of all the currently connected devices. Scans with the Configuration Manager
+/// when the registry can not be scanned, see [`cfgmgr::scan`]
 pub fn scan() -> hkey::ScanResult<HashMap<OsString, hkey::PortMeta>> {
-    hkey::scan()
+    hkey::scan().or_else(|error| {
+        tracing::warn!(
+            ?error,
+            "registry scan failed, scanning with the configuration manager"
+        );
+        cfgmgr::scan()
+    })
+}
+
+/// Get a hash map of all the currently connected devices, and probe whether another handle holds
+/// each port open, see [`hkey::PortMeta::in_use`]. Unlike [`scan`] every port is opened
+pub fn scan_in_use() -> hkey::ScanResult<HashMap<OsString, hkey::PortMeta>> {
+    Ok(scan()?
+        .into_iter()
+        .map(|(port, meta)| {
+            let meta = meta.with_in_use(&port);
+            (port, meta)
+        })
+        .collect())
+}
+
+/// Get the currently connected COM ports ordered by port number. Unlike the hash map of [`scan`]
+/// the order is the same from run to run. IE: for tooling which compares the output of runs.
+/// Ports which are not named COMn are left out
+pub fn scan_sorted() -> hkey::ScanResult<Vec<(ComPortName, hkey::PortMeta)>> {
+    Ok(hkey::sorted(scan()?)
+        .into_iter()
+        .filter_map(|(port, meta)| Some((ComPortName::try_from(port).ok()?, meta)))
+        .collect())
+}
+
+/// Get a hash map of all the currently connected devices with WMI. IE: when policy denies access
+/// to the registry keys [`scan`] reads
+#[cfg(feature = "wmi")]
+pub fn scan_wmi() -> hkey::ScanResult<HashMap<OsString, hkey::PortMeta>> {
+    wbem::scan()
 }
 
 /// If you have a previous call to [`listen`], than you can have the listener stream re-emit
-/// currently connected devices
+/// currently connected devices. If listeners in this or another process share the name, the
+/// rescan may reach another listener, see [`listen_unique`]
 pub fn rescan<N>(name: N) -> io::Result<()>
 where
     N: Into<OsString>,
@@ -38,28 +133,34 @@ where
 }
 
 pub mod prelude {
+    pub use crate::debounce::Debounce;
+    pub use crate::dedupe::Dedupe;
+    pub use crate::renumerate::{EnumeratedPort, Renumeration, RenumerationEvent};
     use crate::{
-        event::{Receiver, Sender, WaitResult},
-        hkey::{PortMeta, RegistryError, ScanResult},
+        event::{CancellationToken, Cancelled, Receiver, Sender, WaitResult},
+        hkey::{PortMeta, RegistryError, ScanResult, UsbId},
         wm::PlugEvent,
     };
-    use futures::{ready, Future, Stream};
+    use futures::{
+        ready,
+        stream::{FuturesUnordered, TakeUntil},
+        Future, Stream, StreamExt,
+    };
     use pin_project_lite::pin_project;
     use std::{
-        borrow::Cow,
-        collections::HashMap,
+        collections::{HashMap, HashSet},
         ffi::OsString,
         io,
-        num::ParseIntError,
         pin::Pin,
         task::{Context, Poll},
+        time::Duration,
     };
-    use tracing::{debug, warn};
+    use tracing::{debug, trace, warn};
 
     pin_project! {
         #[project = UnpluggedProj]
         #[project_replace = UnpluggedProjReplace]
-        #[derive(Debug)]
+        #[derive(Debug, Clone)]
         #[must_use = "futures do nothing unless you `.await` or poll them"]
         pub enum Unplugged {
             Waiting {
@@ -107,6 +208,30 @@ pub mod prelude {
         }
     }
 
+    /// The ports a [`Tracking`] stream tracks
+    #[derive(Debug, Clone)]
+    pub enum TrackFilter {
+        /// Ports of any of the vendor and product ids, see [`DeviceStreamExt::track`]
+        Ids(HashSet<PortMeta>),
+        /// Ports of any of the uppercase serial numbers, see [`DeviceStreamExt::track_serials`]
+        Serials(HashSet<String>),
+    }
+
+    impl TrackFilter {
+        /// Whether the port is tracked. The serial number of a function of a composite device is
+        /// the serial number of its parent, see [`PortMeta::composite_serial`]
+        pub fn matches(&self, meta: &PortMeta) -> bool {
+            match self {
+                TrackFilter::Ids(ids) => ids.contains(meta),
+                TrackFilter::Serials(serials) => meta
+                    .serial()
+                    .map(str::to_owned)
+                    .or_else(|| meta.composite_serial())
+                    .is_some_and(|serial| serials.contains(&serial.to_uppercase())),
+            }
+        }
+    }
+
     #[derive(thiserror::Error, Debug)]
     pub enum TrackingError {
         #[error("io error => {0}")]
@@ -124,7 +249,7 @@ pub mod prelude {
             Streaming {
                 #[pin]
                 inner: St,
-                ids: Vec<PortMeta>,
+                filter: TrackFilter,
                 cache: HashMap<OsString, Sender>
             },
             Complete
@@ -139,7 +264,11 @@ pub mod prelude {
         fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
             loop {
                 match self.as_mut().project() {
-                    TrackingProj::Streaming { inner, ids, cache } => match inner.poll_next(cx) {
+                    TrackingProj::Streaming {
+                        inner,
+                        filter,
+                        cache,
+                    } => match inner.poll_next(cx) {
                         Poll::Pending => break Poll::Pending,
                         Poll::Ready(None) => {
                             self.project_replace(Self::Complete);
@@ -147,9 +276,14 @@ pub mod prelude {
                         }
                         Poll::Ready(Some(Err(e))) => break Poll::Ready(Some(Err(e.into()))),
                         Poll::Ready(Some(Ok(PlugEvent::Arrival(port, id)))) => {
-                            match ids.iter().find(|test| **test == id) {
-                                None => debug!(?port, ?id, "ignoring com device"),
-                                Some(id) => match TrackedPort::track(port.clone(), id.clone()) {
+                            match filter.matches(&id) {
+                                false => debug!(?port, ?id, "ignoring com device"),
+                                // A rescan reports the port again without a removal. Keep the
+                                // unplugged signal of the tracked port
+                                true if cache.contains_key(&port) => {
+                                    debug!(?port, "ignoring tracked com device")
+                                }
+                                true => match TrackedPort::track(port.clone(), id) {
                                     Err(e) => break Poll::Ready(Some(Err(e.into()))),
                                     Ok((sender, tracked)) => {
                                         cache.insert(port.clone(), sender);
@@ -167,6 +301,7 @@ pub mod prelude {
                                 },
                             }
                         }
+                        Poll::Ready(Some(Ok(ev))) => trace!(?ev, "ignoring device event"),
                     },
                     TrackingProj::Complete => {
                         panic!("Watch must not be polled after stream has finished")
@@ -176,19 +311,213 @@ pub mod prelude {
         }
     }
 
+    pin_project! {
+        /// A task started by [`Supervise`] which is cancelled when the port is unplugged. Resolves
+        /// with the port of the task
+        #[derive(Debug)]
+        struct Supervised<Fut> {
+            port: OsString,
+            #[pin]
+            task: Fut,
+            #[pin]
+            unplugged: Unplugged,
+            // Signals the unplugged future handed to the task
+            sender: Option<Sender>,
+        }
+    }
+
+    impl<Fut> Future for Supervised<Fut>
+    where
+        Fut: Future<Output = ()>,
+    {
+        type Output = OsString;
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.project();
+            if let Poll::Ready(result) = this.unplugged.poll(cx) {
+                debug!(port = ?this.port, ?result, "cancelling supervised task");
+                if let Some(Err(error)) = this.sender.take().map(Sender::set) {
+                    warn!(port = ?this.port, ?error, "failed to signal unplugged");
+                }
+                return Poll::Ready(this.port.clone());
+            }
+            this.task.poll(cx).map(|()| this.port.clone())
+        }
+    }
+
+    pin_project! {
+        /// A future which runs a task for every tracked port. See [`DeviceStreamExt::supervise`]
+        #[must_use = "futures do nothing unless you `.await` or poll them"]
+        pub struct Supervise<St, F, Fut> {
+            #[pin]
+            tracking: Tracking<St>,
+            task: F,
+            running: FuturesUnordered<Supervised<Fut>>,
+            // The ports of the running tasks
+            ports: HashSet<OsString>,
+        }
+    }
+
+    impl<St, F, Fut> Future for Supervise<St, F, Fut>
+    where
+        St: Stream<Item = ScanResult<PlugEvent>>,
+        F: FnMut(TrackedPort) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        type Output = Result<(), TrackingError>;
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut this = self.project();
+            loop {
+                match this.tracking.as_mut().poll_next(cx) {
+                    Poll::Pending => break,
+                    Poll::Ready(None) => {
+                        debug!(tasks = this.running.len(), "device stream closed");
+                        this.running.clear();
+                        this.ports.clear();
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                    // A port arriving again, IE: after a rescan, keeps its running task
+                    Poll::Ready(Some(Ok(tracked))) if this.ports.contains(&tracked.port) => {
+                        debug!(port = ?tracked.port, "com port already supervised")
+                    }
+                    Poll::Ready(Some(Ok(tracked))) => {
+                        // The task gets its own unplugged future, ours cancels the task
+                        let (sender, port) =
+                            TrackedPort::track(tracked.port.clone(), tracked.ids.clone())?;
+                        debug!(port = ?tracked.port, "supervising com port");
+                        this.ports.insert(tracked.port.clone());
+                        this.running.push(Supervised {
+                            port: tracked.port,
+                            task: (this.task)(port),
+                            unplugged: tracked.unplugged,
+                            sender: Some(sender),
+                        });
+                    }
+                }
+            }
+            while let Poll::Ready(Some(port)) = this.running.poll_next_unpin(cx) {
+                this.ports.remove(&port);
+            }
+            Poll::Pending
+        }
+    }
+
     pub trait DeviceStreamExt: Stream<Item = ScanResult<PlugEvent>> {
-        fn track<'v, 'p, V, P>(self, ids: Vec<(V, P)>) -> Result<Tracking<Self>, ParseIntError>
+        fn track<V, P>(self, ids: Vec<(V, P)>) -> Tracking<Self>
         where
-            V: Into<Cow<'v, str>>,
-            P: Into<Cow<'p, str>>,
+            V: UsbId,
+            P: UsbId,
             Self: Sized,
         {
             let collection = ids.into_iter().map(PortMeta::from).collect();
-            Ok(Tracking::Streaming {
+            Tracking::Streaming {
                 inner: self,
-                ids: collection,
+                filter: TrackFilter::Ids(collection),
                 cache: HashMap::new(),
-            })
+            }
+        }
+
+        /// Track ports by the serial number of their device, ignoring case. IE: to track the unit
+        /// under test of a rack of identical adapters. See [`PortMeta::serial`]
+        fn track_serials<S>(self, serials: Vec<S>) -> Tracking<Self>
+        where
+            S: Into<String>,
+            Self: Sized,
+        {
+            let collection = serials
+                .into_iter()
+                .map(|serial| serial.into().to_uppercase())
+                .collect();
+            Tracking::Streaming {
+                inner: self,
+                filter: TrackFilter::Serials(collection),
+                cache: HashMap::new(),
+            }
+        }
+
+        /// Like [`Self::track`], but ends once the token is cancelled. IE: to stop tracking from
+        /// another task
+        fn track_until<V, P>(
+            self,
+            ids: Vec<(V, P)>,
+            token: &CancellationToken,
+        ) -> io::Result<Tracking<TakeUntil<Self, Cancelled>>>
+        where
+            V: UsbId,
+            P: UsbId,
+            Self: Sized,
+        {
+            Ok(self.until_cancelled(token)?.track(ids))
+        }
+
+        /// End the device stream once the token is cancelled. IE: to stop [`crate::listen`] from
+        /// another task. Apply before [`Self::track`] to stop tracking as well
+        fn until_cancelled(
+            self,
+            token: &CancellationToken,
+        ) -> io::Result<TakeUntil<Self, Cancelled>>
+        where
+            Self: Sized,
+        {
+            Ok(self.take_until(token.cancelled()?))
+        }
+
+        /// Run a task for every tracked port. The task is dropped when its port is unplugged, and
+        /// every task is dropped when the device stream ends
+        fn supervise<V, P, F, Fut>(self, ids: Vec<(V, P)>, task: F) -> Supervise<Self, F, Fut>
+        where
+            V: UsbId,
+            P: UsbId,
+            F: FnMut(TrackedPort) -> Fut,
+            Fut: Future<Output = ()>,
+            Self: Sized,
+        {
+            Supervise {
+                tracking: self.track(ids),
+                task,
+                running: FuturesUnordered::new(),
+                ports: HashSet::new(),
+            }
+        }
+
+        /// Hold every arrival and removal for the window, and drop it if the port toggles back
+        /// within the window. IE: a flaky cable. Apply before [`Self::track`] to only track
+        /// steady devices
+        fn debounce(self, window: Duration) -> Debounce<Self>
+        where
+            Self: Sized,
+        {
+            Debounce::new(self, window)
+        }
+
+        /// Only report arrivals of ports which were not connected, and removals of ports which
+        /// were. IE: to ignore the arrivals re-emitted by a rescan
+        fn dedupe(self) -> Dedupe<Self>
+        where
+            Self: Sized,
+        {
+            Dedupe::new(self)
+        }
+
+        /// Follow devices which re-enumerate with different ids. IE: when jumping to a
+        /// bootloader. The removal of a device with one of the paired ids is held for the window,
+        /// and is reported as [`RenumerationEvent::Renumerated`] if a device with the other id of
+        /// the pair arrives in time
+        fn renumeration<V, P>(
+            self,
+            pairs: Vec<((V, P), (V, P))>,
+            window: Duration,
+        ) -> Renumeration<Self>
+        where
+            V: UsbId,
+            P: UsbId,
+            Self: Sized,
+        {
+            let pairs = pairs
+                .into_iter()
+                .map(|(a, b)| (PortMeta::from(a), PortMeta::from(b)))
+                .collect();
+            Renumeration::new(self, pairs, window)
         }
     }
 
@@ -0,0 +1,70 @@
+//! loopback
+//!
+//! A self test for test rigs. A pattern is written to a port whose transmit line is looped back to
+//! its receive line, and the echo is compared with the pattern.
+
+use crate::{
+    port::{OpenError, PortConfig},
+    timeout::AsyncReadTimeoutExt,
+};
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use std::{ffi::OsString, io, time::Duration};
+use tracing::trace;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoopbackError {
+    #[error("open error => {0}")]
+    Open(#[from] OpenError),
+    /// The echo did not arrive in time. See [`io::ErrorKind::TimedOut`]
+    #[error("io error => {0}")]
+    Io(#[from] io::Error),
+    /// The echo differs from the pattern
+    #[error("echo differs from pattern at byte {offset}")]
+    Mismatch {
+        /// The index of the first byte which differs
+        offset: usize,
+        /// The bytes received
+        echo: Vec<u8>,
+    },
+}
+
+/// Open a looped back port with the default [`PortConfig`], write the pattern and make sure the
+/// same bytes are read back within the timeout. IE: "COM4" or "\\\\.\\COM4"
+pub async fn loopback_test<P>(
+    port: P,
+    pattern: &[u8],
+    timeout: Duration,
+) -> Result<(), LoopbackError>
+where
+    P: Into<OsString>,
+{
+    let mut port = PortConfig::new().open(port)?;
+    // Stale bytes in the driver would be mistaken for the echo
+    port.purge_rx()?;
+    trace!(port = ?port.port(), len = pattern.len(), "starting loopback test");
+    verify_echo(&mut port, pattern, timeout).await
+}
+
+/// Write the pattern to a looped back stream and make sure the same bytes are read back within
+/// the timeout
+pub async fn verify_echo<S>(
+    stream: &mut S,
+    pattern: &[u8],
+    timeout: Duration,
+) -> Result<(), LoopbackError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(pattern).await?;
+    stream.flush().await?;
+    let mut echo = vec![0u8; pattern.len()];
+    stream.read_exact_timeout(&mut echo, timeout).await?;
+    match pattern
+        .iter()
+        .zip(&echo)
+        .position(|(sent, received)| sent != received)
+    {
+        None => Ok(()),
+        Some(offset) => Err(LoopbackError::Mismatch { offset, echo }),
+    }
+}
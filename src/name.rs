@@ -0,0 +1,85 @@
+//! name
+//!
+//! COM port names. CreateFile only opens COM1 through COM9 by their name, ports from COM10 up
+//! must be opened by their device path. IE: `\\.\COM10`
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    str::FromStr,
+};
+
+/// The prefix of a device path
+const DEVICE_PREFIX: &str = "\\\\.\\";
+
+/// The name of a COM port. IE: COM3. Names order by their port number
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComPortName(u32);
+
+impl ComPortName {
+    /// The name of a port number. IE: 3 for COM3
+    pub fn new(number: u32) -> ComPortName {
+        ComPortName(number)
+    }
+
+    /// The port number. IE: 3 for COM3
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+
+    /// The device path to open the port with. IE: `\\.\COM10`
+    pub fn device_path(&self) -> OsString {
+        OsString::from(format!("{DEVICE_PREFIX}{self}"))
+    }
+}
+
+impl fmt::Display for ComPortName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "COM{}", self.0)
+    }
+}
+
+/// The name is not a COM port name. See [`ComPortName`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("expected a COM port name, found {0:?}")]
+pub struct ParseComPortNameError(OsString);
+
+/// Parse "COM3", in any case, or the device path `\\.\COM3`
+impl FromStr for ComPortName {
+    type Err = ParseComPortNameError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseComPortNameError(OsString::from(s));
+        let name = s.strip_prefix(DEVICE_PREFIX).unwrap_or(s);
+        let number = match name.get(..3) {
+            Some(com) if com.eq_ignore_ascii_case("COM") => &name[3..],
+            _ => return Err(error()),
+        };
+        match number.bytes().all(|b| b.is_ascii_digit()) {
+            true => number.parse().map(ComPortName).map_err(|_| error()),
+            false => Err(error()),
+        }
+    }
+}
+
+impl TryFrom<&OsStr> for ComPortName {
+    type Error = ParseComPortNameError;
+    fn try_from(value: &OsStr) -> Result<Self, Self::Error> {
+        value
+            .to_str()
+            .ok_or_else(|| ParseComPortNameError(value.to_owned()))?
+            .parse()
+    }
+}
+
+impl TryFrom<OsString> for ComPortName {
+    type Error = ParseComPortNameError;
+    fn try_from(value: OsString) -> Result<Self, Self::Error> {
+        ComPortName::try_from(value.as_os_str())
+    }
+}
+
+impl From<ComPortName> for OsString {
+    fn from(value: ComPortName) -> Self {
+        OsString::from(value.to_string())
+    }
+}
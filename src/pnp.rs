@@ -0,0 +1,232 @@
+//! pnp
+//!
+//! Enable and disable devices with the SetupAPI. Disabling and enabling a USB serial adapter is a
+//! software power cycle, and the listener reports the port leave and arrive again. Changing the
+//! state of a device requires an elevated process.
+
+use crate::{
+    cfgmgr::check,
+    hkey::PowerState,
+    wchar::{split_wide, to_wide},
+};
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+};
+use tracing::{trace, warn};
+use windows_sys::Win32::{
+    Devices::{
+        DeviceAndDriverInstallation::{
+            CM_Get_DevNode_Status, SetupDiCallClassInstaller, SetupDiCreateDeviceInfoList,
+            SetupDiDestroyDeviceInfoList, SetupDiGetDevicePropertyW, SetupDiOpenDeviceInfoW,
+            SetupDiSetClassInstallParamsW, DICS_DISABLE, DICS_ENABLE, DICS_FLAG_GLOBAL,
+            DIF_PROPERTYCHANGE, DN_HAS_PROBLEM, HDEVINFO, SP_CLASSINSTALL_HEADER, SP_DEVINFO_DATA,
+            SP_PROPCHANGE_PARAMS,
+        },
+        Properties::{
+            DEVPKEY_Device_BusReportedDeviceDesc, DEVPKEY_Device_Children,
+            DEVPKEY_Device_FriendlyName, DEVPKEY_Device_LocationInfo, DEVPKEY_Device_LocationPaths,
+            DEVPKEY_Device_Parent, DEVPKEY_Device_PowerData, DEVPKEY_Device_Service, DEVPROPKEY,
+            DEVPROPTYPE, DEVPROP_TYPE_BINARY, DEVPROP_TYPE_STRING, DEVPROP_TYPE_STRING_LIST,
+        },
+    },
+    Foundation::{ERROR_INSUFFICIENT_BUFFER, ERROR_NOT_FOUND, FALSE, INVALID_HANDLE_VALUE},
+};
+
+/// A RAII guard for a device information set
+struct DeviceInfoSet(HDEVINFO);
+impl Drop for DeviceInfoSet {
+    fn drop(&mut self) {
+        let _ = unsafe { SetupDiDestroyDeviceInfoList(self.0) };
+    }
+}
+
+/// A device opened by its instance id
+struct Device {
+    set: DeviceInfoSet,
+    data: SP_DEVINFO_DATA,
+}
+
+impl Device {
+    fn open(instance_id: &OsStr) -> io::Result<Device> {
+        let wide = to_wide(instance_id);
+        // Safety: the device data is sized before it is passed to the SetupAPI
+        unsafe {
+            let set = match SetupDiCreateDeviceInfoList(std::ptr::null(), 0) {
+                INVALID_HANDLE_VALUE => return Err(io::Error::last_os_error()),
+                handle => DeviceInfoSet(handle),
+            };
+            let mut data = std::mem::zeroed::<SP_DEVINFO_DATA>();
+            data.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as _;
+            match SetupDiOpenDeviceInfoW(set.0, wide.as_ptr(), 0, 0, &mut data) {
+                FALSE => Err(io::Error::last_os_error()),
+                _ => Ok(Device { set, data }),
+            }
+        }
+    }
+
+    /// Read a property of the device. None when the device does not have the property, or the
+    /// property is not of the type
+    fn property(&self, key: &DEVPROPKEY, expect: DEVPROPTYPE) -> io::Result<Option<Vec<u16>>> {
+        let mut ty = 0;
+        let mut len = 0;
+        // Safety: the first call only reads the size of the property
+        let sized = unsafe {
+            SetupDiGetDevicePropertyW(
+                self.set.0,
+                &self.data,
+                key,
+                &mut ty,
+                std::ptr::null_mut(),
+                0,
+                &mut len,
+                0,
+            )
+        };
+        let error = io::Error::last_os_error();
+        match error.raw_os_error().map(|raw| raw as u32) {
+            _ if sized != FALSE => return Ok(None),
+            Some(ERROR_NOT_FOUND) => return Ok(None),
+            Some(ERROR_INSUFFICIENT_BUFFER) => {}
+            _ => return Err(error),
+        }
+        if ty != expect {
+            return Ok(None);
+        }
+        let mut buffer = vec![0u16; (len as usize + 1) / 2];
+        // Safety: the buffer holds len bytes
+        let read = unsafe {
+            SetupDiGetDevicePropertyW(
+                self.set.0,
+                &self.data,
+                key,
+                &mut ty,
+                buffer.as_mut_ptr() as _,
+                len,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        match read {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(Some(buffer)),
+        }
+    }
+
+    /// Read a string property of the device
+    fn string(&self, key: &DEVPROPKEY) -> io::Result<Option<String>> {
+        let buffer = self.property(key, DEVPROP_TYPE_STRING)?;
+        Ok(buffer.and_then(|buffer| split_wide(&buffer).next()))
+    }
+
+    /// Read a string list property of the device
+    fn strings(&self, key: &DEVPROPKEY) -> io::Result<Vec<String>> {
+        let buffer = self.property(key, DEVPROP_TYPE_STRING_LIST)?;
+        Ok(buffer.map_or_else(Vec::new, |buffer| split_wide(&buffer).collect()))
+    }
+
+    /// The problem code of the device. None when the device has no problem
+    fn problem(&self) -> io::Result<Option<u32>> {
+        let (mut status, mut problem) = (0, 0);
+        check(unsafe { CM_Get_DevNode_Status(&mut status, &mut problem, self.data.DevInst, 0) })?;
+        Ok((status & DN_HAS_PROBLEM != 0).then_some(problem))
+    }
+
+    /// The most recent power state of the device
+    fn power_state(&self) -> io::Result<Option<PowerState>> {
+        let buffer = self.property(&DEVPKEY_Device_PowerData, DEVPROP_TYPE_BINARY)?;
+        Ok(buffer.and_then(|buffer| parse_power_data(&buffer)))
+    }
+}
+
+/// The power state of a CM_POWER_DATA buffer. The state follows the size, as a little endian i32
+pub(crate) fn parse_power_data(buffer: &[u16]) -> Option<PowerState> {
+    let (low, high) = (*buffer.get(2)?, *buffer.get(3)?);
+    let state = (u32::from(high) << 16) | u32::from(low);
+    Some(PowerState::from_raw(state as i32))
+}
+
+/// The properties of a device which describe it to a user
+pub(crate) struct Description {
+    pub friendly_name: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub location_paths: Vec<String>,
+    /// The service of the bound driver. IE: usbser
+    pub service: Option<String>,
+    /// The device instance of the parent. IE: the composite device of a function
+    pub parent: Option<String>,
+    /// The problem code of the device. IE: 43
+    pub problem: Option<u32>,
+    pub power_state: Option<PowerState>,
+}
+
+/// Describe a device. IE: the friendly name "USB Serial Device (COM7)", the bus reported
+/// description "STM32 Virtual ComPort", and the location "Port_#0002.Hub_#0004". The problem
+/// code and power state are best effort, and are None when they can not be read
+pub(crate) fn describe(instance_id: &OsStr) -> io::Result<Description> {
+    let device = Device::open(instance_id)?;
+    let problem = device.problem().unwrap_or_else(|error| {
+        warn!(?error, ?instance_id, "unable to read device problem");
+        None
+    });
+    let power_state = device.power_state().unwrap_or_else(|error| {
+        warn!(?error, ?instance_id, "unable to read device power state");
+        None
+    });
+    Ok(Description {
+        friendly_name: device.string(&DEVPKEY_Device_FriendlyName)?,
+        description: device.string(&DEVPKEY_Device_BusReportedDeviceDesc)?,
+        location: device.string(&DEVPKEY_Device_LocationInfo)?,
+        location_paths: device.strings(&DEVPKEY_Device_LocationPaths)?,
+        service: device.string(&DEVPKEY_Device_Service)?,
+        parent: device.string(&DEVPKEY_Device_Parent)?,
+        problem,
+        power_state,
+    })
+}
+
+/// The device instances of the children of a device. IE: the functions of a composite USB device
+/// `USB\VID_2FE3&PID_0002\A12345` are `USB\VID_2FE3&PID_0002&MI_00\7&123456&0&0000` and so on.
+/// See [`crate::PortMeta::composite_parent`]
+pub fn children<I: Into<OsString>>(instance_id: I) -> io::Result<Vec<String>> {
+    let device = Device::open(&instance_id.into())?;
+    device.strings(&DEVPKEY_Device_Children)
+}
+
+/// Enable or disable a device by its instance id. IE: the device of the interface
+/// `\\?\USB#VID_2FE3&PID_0100#123#{86e0d1e0-8089-11d0-9ce4-08003e301f73}` is the instance
+/// `USB\VID_2FE3&PID_0100\123`
+pub fn set_enabled<I: Into<OsString>>(instance_id: I, enabled: bool) -> io::Result<()> {
+    let instance_id = instance_id.into();
+    trace!(?instance_id, enabled, "changing device state");
+    let device = Device::open(&instance_id)?;
+    let params = SP_PROPCHANGE_PARAMS {
+        ClassInstallHeader: SP_CLASSINSTALL_HEADER {
+            cbSize: std::mem::size_of::<SP_CLASSINSTALL_HEADER>() as _,
+            InstallFunction: DIF_PROPERTYCHANGE,
+        },
+        StateChange: match enabled {
+            true => DICS_ENABLE,
+            false => DICS_DISABLE,
+        },
+        Scope: DICS_FLAG_GLOBAL,
+        HwProfile: 0,
+    };
+    // Safety: the class install params are sized before they are passed to the SetupAPI
+    unsafe {
+        let installed = SetupDiSetClassInstallParamsW(
+            device.set.0,
+            &device.data,
+            &params.ClassInstallHeader,
+            std::mem::size_of::<SP_PROPCHANGE_PARAMS>() as _,
+        );
+        if installed == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        match SetupDiCallClassInstaller(DIF_PROPERTYCHANGE, device.set.0, &device.data) {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
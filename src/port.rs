@@ -0,0 +1,1465 @@
+//! port
+//!
+//! An async serial port. The COM port is opened for overlapped I/O and serviced by a dedicated
+//! thread which shuttles bytes between the kernel and the [`crate::channel`] queues.
+
+pub use crate::comm::{
+    Capabilities, DataBits, LineError, LineEvent, ModemStatus, Parity, ProviderSubtype,
+    SettableData, SettableParams, SettableStopParity, StopBits, Timeouts,
+};
+use crate::{
+    channel::{self, Reader, TaskQueue, ThreadQueue, WakeHandle, Writer},
+    comm::{self, dcb, Escape, Purge},
+    event::{
+        CancellationToken, Cancelled, Deadline, Event, EventInitialState, EventListener,
+        EventReset, Waiting,
+    },
+    name::ComPortName,
+    reactor::ReactorWaker,
+    wchar::to_wide,
+};
+use bytes::{Buf, Bytes};
+use futures::{future, ready, AsyncBufRead, AsyncRead, AsyncWrite, Future, FutureExt, Stream};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, io,
+    os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle, OwnedHandle, RawHandle},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    thread::JoinHandle,
+    time::Duration,
+};
+use tracing::{error, trace, warn};
+use windows_sys::Win32::{
+    Devices::Communication::{WaitCommEvent, DCB},
+    Foundation::{
+        GetLastError, BOOL, ERROR_ACCESS_DENIED, ERROR_IO_PENDING, ERROR_NOT_FOUND,
+        ERROR_OPERATION_ABORTED, ERROR_SHARING_VIOLATION, FALSE, GENERIC_READ, GENERIC_WRITE,
+        HANDLE, INVALID_HANDLE_VALUE, TRUE,
+    },
+    Storage::FileSystem::{
+        CreateFileW, ReadFile, WriteFile, FILE_FLAG_OVERLAPPED, FILE_SHARE_READ, FILE_SHARE_WRITE,
+        OPEN_EXISTING,
+    },
+    System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+};
+
+/// The number of buffers which may be queued in either direction before the queue is full
+const QUEUE_CAPACITY: usize = 32;
+
+/// The size of the buffer handed to each overlapped read
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Failed to open a COM port
+#[derive(thiserror::Error, Debug)]
+pub enum OpenError {
+    /// The port is held open by another handle which does not share it
+    #[error("com port {0:?} is busy")]
+    PortBusy(OsString),
+    #[error("io error => {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<OpenError> for io::Error {
+    fn from(value: OpenError) -> Self {
+        match value {
+            OpenError::Io(error) => error,
+            busy => io::Error::new(io::ErrorKind::PermissionDenied, busy),
+        }
+    }
+}
+
+/// Whether other handles may open the port while we hold it open
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShareMode {
+    /// No other handle may open the port. Most serial drivers only support exclusive opens
+    #[default]
+    Exclusive,
+    /// Other handles may open the port for reading and writing, if the driver allows it
+    Shared,
+}
+
+/// How long to wait between attempts to open a busy port. The delay doubles after each attempt
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: Option<usize>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Retry forever, starting at 50ms and doubling up to 2s between attempts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The delay before the second attempt
+    pub fn with_initial(mut self, initial: Duration) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    /// The longest delay between attempts
+    pub fn with_max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Give up with [`OpenError::PortBusy`] after this many attempts
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// The delay after a number of failed attempts
+    pub(crate) fn delay(&self, failed: usize) -> Duration {
+        let factor = 1u32
+            .checked_shl(failed.saturating_sub(1) as u32)
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// A shared handle to an open COM port. The handle is shared between the [`ComPort`] and the io
+/// thread so that the handle remains valid for as long as either side may use it
+#[derive(Clone)]
+pub struct PortHandle(Arc<OwnedHandle>);
+
+impl PortHandle {
+    /// Open a COM port for overlapped I/O
+    ///
+    /// [CreateFileW](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-createfilew)
+    fn open(path: &OsStr, share: ShareMode) -> io::Result<PortHandle> {
+        let wide = to_wide(path);
+        let share = match share {
+            ShareMode::Exclusive => 0,
+            ShareMode::Shared => FILE_SHARE_READ | FILE_SHARE_WRITE,
+        };
+        let raw = unsafe {
+            CreateFileW(
+                wide.as_ptr(),                // file name
+                GENERIC_READ | GENERIC_WRITE, // access
+                share,                        // share mode
+                std::ptr::null(),             // security attributes
+                OPEN_EXISTING,                // creation disposition
+                FILE_FLAG_OVERLAPPED,         // flags
+                0,                            // template
+            )
+        };
+        match raw {
+            INVALID_HANDLE_VALUE => Err(io::Error::last_os_error()),
+            // Safety: CreateFileW returned a valid handle which we now own
+            raw => Ok(PortHandle(Arc::new(unsafe {
+                OwnedHandle::from_raw_handle(raw as _)
+            }))),
+        }
+    }
+}
+
+impl From<OwnedHandle> for PortHandle {
+    fn from(handle: OwnedHandle) -> Self {
+        PortHandle(Arc::new(handle))
+    }
+}
+
+impl AsRawHandle for PortHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+impl PortHandle {
+    /// Release the handle. Fails with [`io::ErrorKind::ResourceBusy`] while the handle is still
+    /// shared, IE: with a [`LineEvents`] stream, or with a [`crate::reactor::Reactor`] which has
+    /// yet to release the port, because the other owner may still issue overlapped I/O on the
+    /// handle. The reactor releases a port once the port finished and its I/O completed. The
+    /// handle is closed when the last owner drops it
+    pub fn try_into_raw_handle(self) -> io::Result<RawHandle> {
+        match Arc::try_unwrap(self.0) {
+            Ok(owned) => Ok(owned.into_raw_handle()),
+            Err(_shared) => Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                "port handle is still shared",
+            )),
+        }
+    }
+}
+
+impl WakeHandle for PortHandle {
+    /// Cancel the read the io thread is blocked on so it may service the write queue. A write in
+    /// flight is cancelled as well and resumes with the bytes it did not transmit. If no I/O is in
+    /// flight the io thread will see the queued data on its next pass
+    fn wake(&self) -> io::Result<()> {
+        let result = unsafe { CancelIoEx(self.as_raw_handle() as _, std::ptr::null()) };
+        match result {
+            FALSE => match unsafe { GetLastError() } {
+                ERROR_NOT_FOUND => Ok(()),
+                raw => Err(io::Error::from_raw_os_error(raw as _)),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Wakes whatever is servicing the thread side of a port's queue
+#[derive(Clone)]
+pub enum PortWaker {
+    /// The port is serviced by a dedicated io thread which is woken by cancelling its read
+    Thread(PortHandle),
+    /// The port is serviced by a [`crate::reactor::Reactor`] which is woken by a completion packet
+    Reactor(ReactorWaker),
+}
+
+impl AsRawHandle for PortWaker {
+    fn as_raw_handle(&self) -> RawHandle {
+        match self {
+            Self::Thread(handle) => handle.as_raw_handle(),
+            Self::Reactor(waker) => waker.as_raw_handle(),
+        }
+    }
+}
+
+impl WakeHandle for PortWaker {
+    fn wake(&self) -> io::Result<()> {
+        match self {
+            Self::Thread(handle) => handle.wake(),
+            Self::Reactor(waker) => waker.wake(),
+        }
+    }
+}
+
+/// Line settings applied to a port when it is opened. Defaults to 115200 8N1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PortConfig {
+    baud_rate: u32,
+    parity: Parity,
+    data_bits: DataBits,
+    stop_bits: StopBits,
+    timeouts: Timeouts,
+    buffers: Option<(u32, u32)>,
+    share: ShareMode,
+}
+
+impl Default for PortConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: 115200,
+            parity: Parity::default(),
+            data_bits: DataBits::default(),
+            stop_bits: StopBits::default(),
+            timeouts: Timeouts::default(),
+            buffers: None,
+            share: ShareMode::default(),
+        }
+    }
+}
+
+impl PortConfig {
+    /// Create a new config with the default line settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The baud rate at which the device operates
+    pub fn with_baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// The parity scheme to be used
+    pub fn with_parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// The number of bits in the bytes transmitted and received
+    pub fn with_data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    /// The number of stop bits to be used
+    pub fn with_stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// The time-out parameters of the port. See [`ComPort::set_timeouts`]
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Recommend the size in bytes of the driver's internal receive and transmit buffers. High
+    /// throughput devices may drop bytes with the driver defaults
+    pub fn with_buffer_sizes(mut self, rx: u32, tx: u32) -> Self {
+        self.buffers = Some((rx, tx));
+        self
+    }
+
+    /// Whether other handles may open the port while we hold it open
+    pub fn with_share_mode(mut self, share: ShareMode) -> Self {
+        self.share = share;
+        self
+    }
+
+    /// The device accepts these line settings
+    pub fn is_supported_by(&self, capabilities: &Capabilities) -> bool {
+        capabilities.supports_baud_rate(self.baud_rate)
+            && capabilities.supports_data_bits(self.data_bits)
+            && capabilities.supports_parity(self.parity)
+            && capabilities.supports_stop_bits(self.stop_bits)
+    }
+
+    /// Open a COM port with these line settings. IE: "COM4" or "\\\\.\\COM4"
+    pub fn open<P>(self, port: P) -> Result<ComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        ComPort::open_with(port.into(), self)
+    }
+
+    /// Open a COM port with these line settings for blocking I/O. IE: "COM4" or "\\\\.\\COM4"
+    pub fn open_sync<P>(self, port: P) -> Result<SyncComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        SyncComPort::open_with(port.into(), self)
+    }
+
+    /// Open a COM port with these line settings, retrying with a backoff while the port is busy
+    pub async fn open_when_free<P>(self, port: P, backoff: Backoff) -> Result<ComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        self.retry_open(port.into(), backoff, None).await
+    }
+
+    /// See [`Self::open_when_free`]. Gives up with [`io::ErrorKind::Interrupted`] once the token
+    /// is cancelled
+    pub async fn open_when_free_until<P>(
+        self,
+        port: P,
+        backoff: Backoff,
+        token: &CancellationToken,
+    ) -> Result<ComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        self.retry_open(port.into(), backoff, Some(token)).await
+    }
+
+    async fn retry_open(
+        self,
+        port: OsString,
+        backoff: Backoff,
+        token: Option<&CancellationToken>,
+    ) -> Result<ComPort, OpenError> {
+        let mut failed = 0;
+        loop {
+            if token.map_or(false, CancellationToken::is_cancelled) {
+                break Err(io::Error::from(io::ErrorKind::Interrupted).into());
+            }
+            match ComPort::open_with(port.clone(), self) {
+                Err(OpenError::PortBusy(port)) => {
+                    failed += 1;
+                    if backoff
+                        .attempts
+                        .map_or(false, |attempts| failed >= attempts)
+                    {
+                        break Err(OpenError::PortBusy(port));
+                    }
+                    let delay = backoff.delay(failed);
+                    trace!(?port, ?delay, "com port busy, retrying");
+                    match token {
+                        None => Deadline::new(delay)?.await,
+                        Some(token) => {
+                            let _ = future::select(Deadline::new(delay)?, token.cancelled()?).await;
+                        }
+                    }
+                }
+                result => break result,
+            }
+        }
+    }
+
+    /// Write our line settings into a DCB. Settings we do not manage are left untouched
+    pub(crate) fn apply(&self, state: &mut DCB) {
+        state.BaudRate = self.baud_rate;
+        state.ByteSize = self.data_bits as _;
+        state.Parity = self.parity as _;
+        state.StopBits = self.stop_bits as _;
+        state._bitfield |= dcb::BINARY;
+        state._bitfield = match self.parity {
+            Parity::None => state._bitfield & !dcb::PARITY,
+            _ => state._bitfield | dcb::PARITY,
+        };
+    }
+}
+
+/// The io thread servicing a port. Dropping it wakes the thread and waits for it to finish, so a
+/// dropped port does not leave its thread blocked on the handle. A port serviced by a
+/// [`crate::reactor::Reactor`] has no thread to join and is only woken
+struct IoThread {
+    waker: PortWaker,
+    join_handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Drop for IoThread {
+    fn drop(&mut self) {
+        if let Err(error) = self.waker.wake() {
+            warn!(?error, "failed to wake io thread");
+        }
+        if let Some(join_handle) = self.join_handle.take() {
+            if join_handle.join().is_err() {
+                error!("io thread panicked");
+            }
+        }
+    }
+}
+
+/// An async serial port
+///
+/// Bytes read from the port are pushed onto the task side of a [`crate::channel`] by an io thread
+/// and bytes written to the port are pushed onto the thread side of the channel.
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The queue is closed before the io thread is
+/// woken and joined, so the thread sees the queue closed when it wakes
+pub struct ComPort {
+    port: OsString,
+    handle: PortHandle,
+    reader: Reader,
+    writer: Writer,
+    queue: TaskQueue<PortWaker>,
+    io: IoThread,
+}
+
+impl ComPort {
+    /// Open a COM port with the default [`PortConfig`]. IE: "COM4" or "\\\\.\\COM4"
+    pub fn open<P>(port: P) -> Result<ComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        PortConfig::new().open(port)
+    }
+
+    fn open_with(port: OsString, config: PortConfig) -> Result<ComPort, OpenError> {
+        let handle = open_configured(&port, &config)?;
+        trace!(?port, "opened com port");
+        Ok(ComPort::spawn(port, handle))
+    }
+
+    /// Take ownership of a handle opened elsewhere, IE: by FFI code. The handle must have been
+    /// opened with FILE_FLAG_OVERLAPPED. The line settings of the handle are left untouched
+    pub fn from_owned_handle<P>(port: P, handle: OwnedHandle) -> ComPort
+    where
+        P: Into<OsString>,
+    {
+        ComPort::spawn(port.into(), PortHandle::from(handle))
+    }
+
+    /// Service a handle with a dedicated io thread
+    fn spawn(port: OsString, handle: PortHandle) -> ComPort {
+        let waker = PortWaker::Thread(handle.clone());
+        let (queue, thread) = channel::bounded(waker.clone(), QUEUE_CAPACITY);
+        let theirs = handle.clone();
+        let name = port.clone();
+        let join_handle = std::thread::spawn(move || {
+            let result = io_dispatcher(&theirs, &thread);
+            match &result {
+                Ok(_) => trace!(port = ?name, "io dispatcher finished"),
+                Err(error) => error!(port = ?name, ?error, "io dispatcher error"),
+            }
+            result
+        });
+        ComPort::new(port, handle, waker, queue, Some(join_handle))
+    }
+
+    /// Assemble a port from a handle and the task side of the queue serviced on its behalf
+    pub(crate) fn new(
+        port: OsString,
+        handle: PortHandle,
+        waker: PortWaker,
+        queue: TaskQueue<PortWaker>,
+        join_handle: Option<JoinHandle<io::Result<()>>>,
+    ) -> ComPort {
+        ComPort {
+            port,
+            handle,
+            reader: queue.reader(),
+            writer: queue.writer(),
+            queue,
+            io: IoThread { waker, join_handle },
+        }
+    }
+
+    /// The com port name. IE: COM4
+    pub fn port(&self) -> &OsStr {
+        &self.port
+    }
+
+    /// Assert or clear the DTR (data-terminal-ready) line. Reads in flight are not cancelled
+    pub fn set_dtr(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.handle, Escape::SetDtr),
+            false => comm::escape(&self.handle, Escape::ClrDtr),
+        }
+    }
+
+    /// Assert or clear the RTS (request-to-send) line. Reads in flight are not cancelled
+    pub fn set_rts(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.handle, Escape::SetRts),
+            false => comm::escape(&self.handle, Escape::ClrRts),
+        }
+    }
+
+    /// Read the capabilities of the port
+    pub fn capabilities(&self) -> io::Result<Capabilities> {
+        comm::properties(&self.handle)
+    }
+
+    /// A handle to the traffic statistics of the queue between the port and its io thread
+    pub fn stats(&self) -> channel::Stats {
+        self.queue.stats()
+    }
+
+    /// Observe the bytes read from the port alongside the main reader. IE: to log traffic. See
+    /// [`TaskQueue::subscribe`]
+    pub fn subscribe(&self) -> Reader {
+        Reader::from(self.queue.subscribe())
+    }
+
+    /// Read the state of the modem control lines (CTS, DSR, RI and CD)
+    pub fn modem_status(&self) -> io::Result<ModemStatus> {
+        comm::modem_status(&self.handle)
+    }
+
+    /// Discard bytes received by the driver which have not yet been read by the port. Bytes
+    /// already handed to the reader are not discarded
+    pub fn purge_rx(&self) -> io::Result<()> {
+        comm::purge(&self.handle, Purge::RX_CLEAR)
+    }
+
+    /// Discard bytes queued in the driver which have not yet been transmitted
+    pub fn purge_tx(&self) -> io::Result<()> {
+        comm::purge(&self.handle, Purge::TX_CLEAR)
+    }
+
+    /// Terminate the outstanding overlapped reads and writes of the port. The port continues to
+    /// service its queues afterwards, so a terminated read is reissued and a terminated write
+    /// resumes with the bytes it did not transmit
+    pub fn abort_io(&self) -> io::Result<()> {
+        comm::purge(&self.handle, Purge::RX_ABORT | Purge::TX_ABORT)
+    }
+
+    /// Read and clear the line errors of the port. Line errors are also reported to the reader as
+    /// they occur, so a caller racing the reader may observe them first
+    pub fn line_errors(&self) -> io::Result<LineError> {
+        comm::clear_errors(&self.handle)
+    }
+
+    /// Read the time-out parameters of the port
+    pub fn timeouts(&self) -> io::Result<Timeouts> {
+        comm::get_timeouts(&self.handle)
+    }
+
+    /// Set the time-out parameters of the port.
+    ///
+    /// NOTE the io thread notices a closed port, or a write queued while no read is in flight,
+    /// when its current read returns. Time-outs which let a read block indefinitely delay both
+    pub fn set_timeouts(&self, timeouts: Timeouts) -> io::Result<()> {
+        comm::set_timeouts(&self.handle, timeouts)
+    }
+
+    /// Fail reads and writes with [`io::ErrorKind::Interrupted`] once the token is cancelled. IE:
+    /// to stop a task waiting on a silent device. See [`Cancellable`]
+    pub fn until_cancelled(self, token: &CancellationToken) -> io::Result<Cancellable<ComPort>> {
+        Cancellable::new(self, token)
+    }
+
+    /// Split the port into a read half and a write half which may be used from different tasks.
+    /// The port is closed when both halves are dropped
+    pub fn split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        let ComPort {
+            port,
+            handle,
+            reader,
+            writer,
+            queue,
+            io,
+        } = self;
+        let shared = Arc::new(SplitShared {
+            port,
+            handle,
+            queue,
+            io,
+        });
+        let read = OwnedReadHalf {
+            reader,
+            shared: Arc::clone(&shared),
+        };
+        (read, OwnedWriteHalf { writer, shared })
+    }
+
+    /// Listen for line events. A port has a single event mask, so creating a new stream replaces
+    /// the mask of any previous stream
+    pub fn line_events(&self, mask: LineEvent) -> io::Result<LineEvents> {
+        comm::set_mask(&self.handle, mask)?;
+        LineEvents::with_wait(self.handle.clone(), WaitCommEvent)
+    }
+}
+
+impl AsRawHandle for ComPort {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle.as_raw_handle()
+    }
+}
+
+impl ComPort {
+    /// Stop servicing the port and release its handle. Bytes not yet read are lost. See
+    /// [`PortHandle::try_into_raw_handle`]
+    pub fn try_into_raw_handle(self) -> io::Result<RawHandle> {
+        let ComPort {
+            handle,
+            reader,
+            writer,
+            queue,
+            io,
+            ..
+        } = self;
+        drop((reader, writer, queue));
+        drop(io);
+        handle.try_into_raw_handle()
+    }
+}
+
+impl fmt::Debug for ComPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ComPort").field("port", &self.port).finish()
+    }
+}
+
+impl AsyncRead for ComPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for ComPort {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
+
+impl AsyncWrite for ComPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+/// The parts of a [`ComPort`] shared by its halves after a [`ComPort::split`]
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). See [`ComPort`]
+struct SplitShared {
+    port: OsString,
+    handle: PortHandle,
+    queue: TaskQueue<PortWaker>,
+    io: IoThread,
+}
+
+/// The halves passed to [`OwnedReadHalf::reunite`] came from different ports
+#[derive(thiserror::Error, Debug)]
+#[error("tried to reunite halves that are not from the same port")]
+pub struct ReuniteError(pub OwnedReadHalf, pub OwnedWriteHalf);
+
+/// The read half of a [`ComPort`]. See [`ComPort::split`]
+pub struct OwnedReadHalf {
+    reader: Reader,
+    shared: Arc<SplitShared>,
+}
+
+impl OwnedReadHalf {
+    /// The com port name. IE: COM4
+    pub fn port(&self) -> &OsStr {
+        &self.shared.port
+    }
+
+    /// Join the halves back into a [`ComPort`]
+    pub fn reunite(self, write: OwnedWriteHalf) -> Result<ComPort, ReuniteError> {
+        if !Arc::ptr_eq(&self.shared, &write.shared) {
+            return Err(ReuniteError(self, write));
+        }
+        let OwnedReadHalf { reader, shared } = self;
+        let OwnedWriteHalf { writer, shared: _ } = write;
+        match Arc::try_unwrap(shared) {
+            Ok(shared) => Ok(ComPort {
+                port: shared.port,
+                handle: shared.handle,
+                reader,
+                writer,
+                queue: shared.queue,
+                io: shared.io,
+            }),
+            Err(_) => unreachable!("the halves hold the only references"),
+        }
+    }
+}
+
+impl fmt::Debug for OwnedReadHalf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedReadHalf")
+            .field("port", &self.shared.port)
+            .finish()
+    }
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for OwnedReadHalf {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
+
+/// The write half of a [`ComPort`]. See [`ComPort::split`]
+pub struct OwnedWriteHalf {
+    writer: Writer,
+    shared: Arc<SplitShared>,
+}
+
+impl OwnedWriteHalf {
+    /// The com port name. IE: COM4
+    pub fn port(&self) -> &OsStr {
+        &self.shared.port
+    }
+
+    /// Join the halves back into a [`ComPort`]
+    pub fn reunite(self, read: OwnedReadHalf) -> Result<ComPort, ReuniteError> {
+        read.reunite(self)
+    }
+
+    /// Assert or clear the DTR (data-terminal-ready) line. Reads in flight are not cancelled
+    pub fn set_dtr(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.shared.handle, Escape::SetDtr),
+            false => comm::escape(&self.shared.handle, Escape::ClrDtr),
+        }
+    }
+
+    /// Assert or clear the RTS (request-to-send) line. Reads in flight are not cancelled
+    pub fn set_rts(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.shared.handle, Escape::SetRts),
+            false => comm::escape(&self.shared.handle, Escape::ClrRts),
+        }
+    }
+}
+
+impl fmt::Debug for OwnedWriteHalf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedWriteHalf")
+            .field("port", &self.shared.port)
+            .finish()
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+/// A reader or writer which fails with [`io::ErrorKind::Interrupted`] once a
+/// [`CancellationToken`] is cancelled. Wraps a [`ComPort`] or one of its halves. Closing is not
+/// cancelled, so buffered bytes may still be written after a cancel
+#[derive(Debug)]
+pub struct Cancellable<T> {
+    inner: T,
+    cancelled: Cancelled,
+    token: CancellationToken,
+}
+
+impl<T> Cancellable<T> {
+    pub fn new(inner: T, token: &CancellationToken) -> io::Result<Cancellable<T>> {
+        Ok(Cancellable {
+            inner,
+            cancelled: token.cancelled()?,
+            token: token.clone(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Poll the inner reader or writer unless the token is cancelled, and wake on a cancel while
+    /// it is pending. The cancelled future is not polled again once the token is cancelled
+    fn poll_inner<R, F>(&mut self, cx: &mut Context<'_>, poll: F) -> Poll<io::Result<R>>
+    where
+        T: Unpin,
+        F: FnOnce(Pin<&mut T>, &mut Context<'_>) -> Poll<io::Result<R>>,
+    {
+        let cancelled = || io::Error::new(io::ErrorKind::Interrupted, "cancelled");
+        if self.token.is_cancelled() {
+            return Poll::Ready(Err(cancelled()));
+        }
+        match poll(Pin::new(&mut self.inner), cx) {
+            Poll::Ready(result) => Poll::Ready(result),
+            Poll::Pending => Pin::new(&mut self.cancelled)
+                .poll(cx)
+                .map(|_| Err(cancelled())),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Cancellable<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_inner(cx, |inner, cx| inner.poll_read(cx, buf))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Cancellable<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_inner(cx, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_inner(cx, AsyncWrite::poll_flush)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Starts an overlapped wait for line events. IE: WaitCommEvent
+pub(crate) type WaitLineEvent =
+    unsafe extern "system" fn(HANDLE, *mut u32, *mut OVERLAPPED) -> BOOL;
+
+/// The state of an overlapped WaitCommEvent. The kernel writes into this state while the wait is
+/// pending so it is boxed to keep its address stable
+struct PendingEvent {
+    overlapped: Overlapped,
+    mask: u32,
+    wait: WaitLineEvent,
+    /// The kernel owns the state until the wait completes
+    in_flight: bool,
+}
+
+// Safety: The OVERLAPPED structure is only touched by the kernel and by the owner of the box
+unsafe impl Send for PendingEvent {}
+
+impl PendingEvent {
+    /// Start waiting for a line event. Returns the events when the wait completes immediately
+    ///
+    /// [WaitCommEvent](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-waitcommevent)
+    fn start(&mut self, handle: &PortHandle) -> io::Result<Option<LineEvent>> {
+        self.overlapped.event.reset()?;
+        self.mask = 0;
+        let result = unsafe {
+            (self.wait)(
+                handle.as_raw_handle() as _,
+                &mut self.mask,
+                &mut self.overlapped.inner,
+            )
+        };
+        match result {
+            FALSE => match unsafe { GetLastError() } {
+                ERROR_IO_PENDING => {
+                    self.in_flight = true;
+                    Ok(None)
+                }
+                raw => Err(io::Error::from_raw_os_error(raw as _)),
+            },
+            _ => Ok(Some(LineEvent::from_bits_truncate(self.mask))),
+        }
+    }
+
+    /// Retrieve the result of a wait the kernel has signaled as complete
+    fn complete(&mut self, handle: &PortHandle) -> io::Result<LineEvent> {
+        self.overlapped.complete(handle)?;
+        self.in_flight = false;
+        Ok(LineEvent::from_bits_truncate(self.mask))
+    }
+
+    /// Cancel a pending wait, and block until the kernel is done with the state
+    fn cancel(&mut self, handle: &PortHandle) {
+        if self.in_flight {
+            let raw = handle.as_raw_handle() as _;
+            let _ = unsafe { CancelIoEx(raw, &self.overlapped.inner) };
+            let _ = self.overlapped.wait(handle);
+            self.in_flight = false;
+        }
+    }
+}
+
+/// A stream of line events created by [`ComPort::line_events`]
+///
+/// NOTE writing to a [`ComPort`] serviced by an io thread wakes the thread by cancelling all I/O
+/// on the handle. A cancelled wait is restarted transparently.
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The listener must stop waiting on the
+/// pending event before the event is closed
+pub struct LineEvents {
+    handle: PortHandle,
+    listener: EventListener,
+    pending: Box<PendingEvent>,
+    waiting: Option<Waiting>,
+    started: bool,
+}
+
+impl LineEvents {
+    /// Line events of a handle, waited for with the function. IE: a pipe which stands in for a
+    /// port in tests
+    pub(crate) fn with_wait(handle: PortHandle, wait: WaitLineEvent) -> io::Result<LineEvents> {
+        Ok(LineEvents {
+            handle,
+            listener: EventListener::new()?,
+            pending: Box::new(PendingEvent {
+                overlapped: Overlapped::new()?.without_completion_packet(),
+                mask: 0,
+                wait,
+                in_flight: false,
+            }),
+            waiting: None,
+            started: false,
+        })
+    }
+
+    /// Wait on the overlapped event of our pending WaitCommEvent
+    fn listen(&mut self) -> io::Result<Waiting> {
+        let event = &self.pending.overlapped.event;
+        match self.started {
+            false => {
+                self.started = true;
+                Ok(self.listener.start(event, None))
+            }
+            true => self
+                .listener
+                .restart(event, None)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl fmt::Debug for LineEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LineEvents")
+            .field("waiting", &self.waiting.is_some())
+            .finish()
+    }
+}
+
+impl Stream for LineEvents {
+    type Item = io::Result<LineEvent>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match this.waiting.as_mut() {
+                None => match this.pending.start(&this.handle) {
+                    Ok(Some(events)) => break Poll::Ready(Some(Ok(events))),
+                    Ok(None) => match this.listen() {
+                        Ok(waiting) => this.waiting = Some(waiting),
+                        Err(e) => {
+                            // Nothing waits for the kernel to finish with the state anymore
+                            this.pending.cancel(&this.handle);
+                            break Poll::Ready(Some(Err(e)));
+                        }
+                    },
+                    Err(e) => break Poll::Ready(Some(Err(e))),
+                },
+                Some(waiting) => {
+                    let result = ready!(waiting.poll_unpin(cx));
+                    this.waiting = None;
+                    if let Err(e) = result.signaled() {
+                        this.pending.cancel(&this.handle);
+                        break Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, e))));
+                    }
+                    match this.pending.complete(&this.handle) {
+                        Ok(events) => break Poll::Ready(Some(Ok(events))),
+                        Err(e) if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED as _) => {
+                            this.pending.in_flight = false;
+                            trace!("line event wait cancelled, restarting");
+                        }
+                        Err(e) => {
+                            this.pending.cancel(&this.handle);
+                            break Poll::Ready(Some(Err(e)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LineEvents {
+    fn drop(&mut self) {
+        // The kernel holds pointers into our pending state until the wait resolves
+        self.pending.cancel(&self.handle);
+    }
+}
+
+/// A blocking serial port. Reads and writes block the calling thread, so no io thread or executor
+/// is needed
+///
+/// A read returns as soon as any bytes arrive. When the read time-outs elapse first the read fails
+/// with [`io::ErrorKind::TimedOut`]. See [`Timeouts`]
+pub struct SyncComPort {
+    port: OsString,
+    handle: PortHandle,
+    rx: Overlapped,
+    tx: Overlapped,
+    /// A line error reported after the bytes read with it
+    error: Option<LineError>,
+}
+
+// Safety: The OVERLAPPED structures are only referenced by the kernel during a call which
+// borrows the port mutably, so they are never shared between threads
+unsafe impl Send for SyncComPort {}
+
+impl SyncComPort {
+    /// Open a COM port with the default [`PortConfig`]. IE: "COM4" or "\\\\.\\COM4"
+    pub fn open<P>(port: P) -> Result<SyncComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        PortConfig::new().open_sync(port)
+    }
+
+    fn open_with(port: OsString, config: PortConfig) -> Result<SyncComPort, OpenError> {
+        let handle = open_configured(&port, &config)?;
+        trace!(?port, "opened blocking com port");
+        Ok(SyncComPort {
+            port,
+            handle,
+            rx: Overlapped::new()?,
+            tx: Overlapped::new()?,
+            error: None,
+        })
+    }
+
+    /// The com port name. IE: COM4
+    pub fn port(&self) -> &OsStr {
+        &self.port
+    }
+
+    /// Assert or clear the DTR (data-terminal-ready) line
+    pub fn set_dtr(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.handle, Escape::SetDtr),
+            false => comm::escape(&self.handle, Escape::ClrDtr),
+        }
+    }
+
+    /// Assert or clear the RTS (request-to-send) line
+    pub fn set_rts(&self, level: bool) -> io::Result<()> {
+        match level {
+            true => comm::escape(&self.handle, Escape::SetRts),
+            false => comm::escape(&self.handle, Escape::ClrRts),
+        }
+    }
+
+    /// Read the state of the modem control lines
+    pub fn modem_status(&self) -> io::Result<ModemStatus> {
+        comm::modem_status(&self.handle)
+    }
+
+    /// Read the time-out parameters of the port
+    pub fn timeouts(&self) -> io::Result<Timeouts> {
+        comm::get_timeouts(&self.handle)
+    }
+
+    /// Set the time-out parameters of the port. Applies from the next read or write
+    pub fn set_timeouts(&self, timeouts: Timeouts) -> io::Result<()> {
+        comm::set_timeouts(&self.handle, timeouts)
+    }
+}
+
+impl AsRawHandle for SyncComPort {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle.as_raw_handle()
+    }
+}
+
+impl SyncComPort {
+    /// Release the handle of the port. See [`PortHandle::try_into_raw_handle`]
+    pub fn try_into_raw_handle(self) -> io::Result<RawHandle> {
+        self.handle.try_into_raw_handle()
+    }
+}
+
+impl io::Read for SyncComPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = self.error.take() {
+            return Err(error.into());
+        }
+        let n = self.rx.read(&self.handle, buf)?;
+        self.error = comm::take_line_error(&self.handle);
+        match n {
+            0 if buf.is_empty() => Ok(0),
+            0 => match self.error.take() {
+                Some(error) => Err(error.into()),
+                None => Err(io::ErrorKind::TimedOut.into()),
+            },
+            n => Ok(n),
+        }
+    }
+}
+
+impl io::Write for SyncComPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.tx.write(&self.handle, buf)? {
+            // A cancelled write is retried by write_all
+            Transferred {
+                len: 0,
+                aborted: true,
+            } if !buf.is_empty() => Err(io::ErrorKind::Interrupted.into()),
+            Transferred { len: 0, .. } if !buf.is_empty() => Err(io::ErrorKind::TimedOut.into()),
+            Transferred { len, .. } => Ok(len),
+        }
+    }
+
+    /// Writes return once the driver has accepted the bytes, so there is nothing to flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Read the capabilities of a port which is not open, so that settings may be validated before
+/// opening the port with them. IE: "COM4" or "\\\\.\\COM4"
+pub fn capabilities<P>(port: P) -> Result<Capabilities, OpenError>
+where
+    P: Into<OsString>,
+{
+    let handle = open_device(&port.into(), ShareMode::Shared)?;
+    Ok(comm::properties(&handle)?)
+}
+
+/// Probe whether another handle holds a port open, IE: so a device picker can gray out the port.
+/// The port is opened exclusively and closed at once without changing its line settings, though
+/// some drivers raise DTR while the port is open
+pub fn in_use<P>(port: P) -> io::Result<bool>
+where
+    P: Into<OsString>,
+{
+    match open_device(&port.into(), ShareMode::Exclusive) {
+        Ok(_handle) => Ok(false),
+        Err(OpenError::PortBusy(_)) => Ok(true),
+        Err(OpenError::Io(error)) => Err(error),
+    }
+}
+
+/// Open a port and apply the line settings of a config
+pub(crate) fn open_configured(port: &OsStr, config: &PortConfig) -> Result<PortHandle, OpenError> {
+    let handle = open_device(port, config.share)?;
+    if let Some((rx, tx)) = config.buffers {
+        comm::setup(&handle, rx, tx)?;
+    }
+    let mut state = comm::get_state(&handle)?;
+    config.apply(&mut state);
+    comm::set_state(&handle, &state)?;
+    comm::set_timeouts(&handle, config.timeouts)?;
+    Ok(handle)
+}
+
+/// Open a port, reporting ports held open by another handle as busy
+fn open_device(port: &OsStr, share: ShareMode) -> Result<PortHandle, OpenError> {
+    PortHandle::open(&device_path(port), share).map_err(|error| match error.raw_os_error() {
+        Some(code)
+            if code == ERROR_ACCESS_DENIED as i32 || code == ERROR_SHARING_VIOLATION as i32 =>
+        {
+            OpenError::PortBusy(port.to_owned())
+        }
+        _ => OpenError::Io(error),
+    })
+}
+
+/// Normalize a port name into a device path. IE: COM4 => \\.\COM4
+fn device_path(port: &OsStr) -> OsString {
+    if let Ok(name) = ComPortName::try_from(port) {
+        return name.device_path();
+    }
+    // Ports which are not named COMn are opened by their name. IE: CNCA0
+    match port.to_string_lossy().starts_with("\\\\.\\") {
+        true => port.to_owned(),
+        false => {
+            let mut path = OsString::from("\\\\.\\");
+            path.push(port);
+            path
+        }
+    }
+}
+
+/// An OVERLAPPED structure and the event the kernel signals when the operation completes
+struct Overlapped {
+    inner: OVERLAPPED,
+    event: Event,
+}
+
+impl Overlapped {
+    fn new() -> io::Result<Overlapped> {
+        let event = Event::anonymous(EventReset::Manual, EventInitialState::Unset)?;
+        // Safety: OVERLAPPED is a plain C struct and is valid when zeroed
+        let mut inner: OVERLAPPED = unsafe { std::mem::zeroed() };
+        inner.hEvent = event.as_raw_handle() as _;
+        Ok(Overlapped { inner, event })
+    }
+
+    /// Keep the kernel from queuing a completion packet when the port is associated with a
+    /// [`crate::reactor::Reactor`]. The event is still signaled
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-getqueuedcompletionstatus#remarks)
+    fn without_completion_packet(mut self) -> Overlapped {
+        self.inner.hEvent |= 1;
+        self
+    }
+
+    /// Block until the pending operation completes. A cancelled operation is not an error and
+    /// reports the bytes transferred prior to the cancellation
+    ///
+    /// [GetOverlappedResult](https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-getoverlappedresult)
+    fn wait(&mut self, handle: &PortHandle) -> io::Result<Transferred> {
+        let mut transferred = 0u32;
+        let result = unsafe {
+            GetOverlappedResult(
+                handle.as_raw_handle() as _,
+                &self.inner,
+                &mut transferred,
+                TRUE,
+            )
+        };
+        let len = transferred as usize;
+        match result {
+            FALSE => match unsafe { GetLastError() } {
+                ERROR_OPERATION_ABORTED => Ok(Transferred { len, aborted: true }),
+                raw => Err(io::Error::from_raw_os_error(raw as _)),
+            },
+            _ => Ok(Transferred {
+                len,
+                aborted: false,
+            }),
+        }
+    }
+
+    /// Retrieve the result of an operation the kernel has already signaled as complete
+    ///
+    /// [GetOverlappedResult](https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-getoverlappedresult)
+    fn complete(&self, handle: &PortHandle) -> io::Result<usize> {
+        let mut transferred = 0u32;
+        let result = unsafe {
+            GetOverlappedResult(
+                handle.as_raw_handle() as _,
+                &self.inner,
+                &mut transferred,
+                FALSE,
+            )
+        };
+        match result {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(transferred as _),
+        }
+    }
+
+    /// Read from the port into buf. Returns early with what ever was read when cancelled
+    ///
+    /// [ReadFile](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-readfile)
+    fn read(&mut self, handle: &PortHandle, buf: &mut [u8]) -> io::Result<usize> {
+        let result = unsafe {
+            ReadFile(
+                handle.as_raw_handle() as _,
+                buf.as_mut_ptr(),
+                buf.len() as _,
+                std::ptr::null_mut(),
+                &mut self.inner,
+            )
+        };
+        let transferred = match result {
+            FALSE => match unsafe { GetLastError() } {
+                ERROR_IO_PENDING => self.wait(handle)?,
+                raw => return Err(io::Error::from_raw_os_error(raw as _)),
+            },
+            _ => self.wait(handle)?,
+        };
+        Ok(transferred.len)
+    }
+
+    /// Write buf to the port. Returns early with what ever was written when cancelled
+    ///
+    /// [WriteFile](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-writefile)
+    fn write(&mut self, handle: &PortHandle, buf: &[u8]) -> io::Result<Transferred> {
+        let result = unsafe {
+            WriteFile(
+                handle.as_raw_handle() as _,
+                buf.as_ptr(),
+                buf.len() as _,
+                std::ptr::null_mut(),
+                &mut self.inner,
+            )
+        };
+        match result {
+            FALSE => match unsafe { GetLastError() } {
+                ERROR_IO_PENDING => self.wait(handle),
+                raw => Err(io::Error::from_raw_os_error(raw as _)),
+            },
+            _ => self.wait(handle),
+        }
+    }
+
+    /// Write all of the bytes to the port. A write cancelled by a wake, or by
+    /// [`ComPort::abort_io`], resumes with the bytes it did not transmit
+    fn write_all(&mut self, handle: &PortHandle, mut bytes: Bytes) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.write(handle, &bytes)? {
+                Transferred {
+                    len: 0,
+                    aborted: false,
+                } => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Transferred { len, .. } => bytes.advance(len),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The bytes an overlapped operation transferred
+struct Transferred {
+    len: usize,
+    /// The operation was cancelled before it completed
+    aborted: bool,
+}
+
+/// Service the port. Outgoing bytes are collected from the thread side of the queue and written
+/// to the port, and then we block on a read until bytes arrive, the read times out, or the task
+/// wakes us with more bytes to write. The dispatcher finishes when the task side of the queue is
+/// closed or the port fails.
+///
+/// When the task falls behind and its queue is full, the bytes read are held and the port is not
+/// read again until the task makes room. The driver buffers what arrives in the mean time.
+fn io_dispatcher(handle: &PortHandle, queue: &ThreadQueue) -> io::Result<()> {
+    let mut rx = Overlapped::new()?;
+    let mut tx = Overlapped::new()?;
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let mut held: Option<Bytes> = None;
+    loop {
+        let (bytes, done) = queue.collect();
+        if !bytes.is_empty() {
+            trace!(len = bytes.len(), "writing bytes");
+            if let Err(e) = tx.write_all(handle, bytes) {
+                let _ = queue.push_err(io::Error::new(e.kind(), e.to_string()));
+                queue.fail(io::Error::new(e.kind(), e.to_string()));
+                break Err(e);
+            }
+        }
+        if done {
+            break Ok(());
+        }
+        if let Some(bytes) = held.take() {
+            if let Err(bytes) = queue.push_ok(bytes) {
+                held = Some(bytes);
+                if let Err(e) = queue.wait_room(None) {
+                    let e = io::Error::new(io::ErrorKind::Other, e);
+                    queue.fail(io::Error::new(e.kind(), e.to_string()));
+                    break Err(e);
+                }
+                continue;
+            }
+        }
+        match rx.read(handle, &mut buf) {
+            Ok(n) => {
+                if n > 0 {
+                    trace!(len = n, "read bytes");
+                    if let Err(bytes) = queue.push_ok(Bytes::copy_from_slice(&buf[..n])) {
+                        trace!(len = bytes.len(), "read queue full, holding bytes");
+                        held = Some(bytes);
+                    }
+                }
+                if let Some(line) = comm::take_line_error(handle) {
+                    let _ = queue.push_err(line.into());
+                }
+            }
+            Err(e) => {
+                let _ = queue.push_err(match comm::take_line_error(handle) {
+                    Some(line) => line.into(),
+                    None => io::Error::new(e.kind(), e.to_string()),
+                });
+                // Nothing consumes the writes once the dispatcher stops
+                queue.fail(io::Error::new(e.kind(), e.to_string()));
+                break Err(e);
+            }
+        }
+    }
+}
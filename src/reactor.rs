@@ -0,0 +1,468 @@
+//! reactor
+//!
+//! An I/O completion port reactor. Many ports are serviced by a small pool of worker threads
+//! instead of a dedicated io thread per port. Completed reads and writes, and wakes from the task
+//! side of a port's queue, all arrive as completion packets.
+
+use crate::{
+    channel::{self, ThreadQueue, WakeHandle},
+    comm,
+    port::{self, ComPort, OpenError, PortConfig, PortHandle, PortWaker},
+};
+use bytes::{Buf, Bytes};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt, io,
+    os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+use tracing::{error, trace, warn};
+use windows_sys::Win32::{
+    Foundation::{
+        GetLastError, ERROR_IO_PENDING, ERROR_OPERATION_ABORTED, FALSE, INVALID_HANDLE_VALUE,
+    },
+    Storage::FileSystem::{ReadFile, WriteFile},
+    System::{
+        Threading::INFINITE,
+        IO::{
+            CancelIoEx, CreateIoCompletionPort, GetQueuedCompletionStatus,
+            PostQueuedCompletionStatus, OVERLAPPED,
+        },
+    },
+};
+
+/// The completion key of the packet which tells a worker to exit. Port keys start at 1
+const SHUTDOWN_KEY: usize = 0;
+
+/// The number of buffers which may be queued in either direction before the queue is full
+const QUEUE_CAPACITY: usize = 32;
+
+/// The size of the buffer handed to each overlapped read
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// State shared between the reactor, its workers and the wakers of every registered port
+struct Shared {
+    /// The I/O completion port
+    iocp: OwnedHandle,
+    /// The ports currently registered with the reactor. A port is removed once it has finished and
+    /// no I/O is in flight, so the kernel never holds a pointer into a removed port
+    ports: Mutex<HashMap<usize, Arc<Mutex<PortIo>>>>,
+    /// The next completion key
+    next: AtomicUsize,
+    /// Set when the reactor is dropped. Ports stop issuing I/O and finish
+    shutdown: AtomicBool,
+    /// The number of workers, which is the number of shutdown packets to post
+    workers: usize,
+}
+
+impl Shared {
+    /// Queue a completion packet which carries no I/O
+    ///
+    /// [PostQueuedCompletionStatus](https://learn.microsoft.com/en-us/windows/win32/fileio/postqueuedcompletionstatus)
+    fn post(&self, key: usize) -> io::Result<()> {
+        let iocp = self.iocp.as_raw_handle() as _;
+        match unsafe { PostQueuedCompletionStatus(iocp, 0, key, std::ptr::null()) } {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tell every worker to exit
+    fn post_shutdown(&self) {
+        for _ in 0..self.workers {
+            if let Err(error) = self.post(SHUTDOWN_KEY) {
+                error!(?error, "failed to post reactor shutdown");
+            }
+        }
+    }
+
+    /// Remove a finished port. The workers are told to exit once the last port is removed after
+    /// the reactor was dropped
+    fn remove(&self, key: usize) {
+        let mut ports = self.ports.lock();
+        ports.remove(&key);
+        if ports.is_empty() && self.shutdown.load(Ordering::SeqCst) {
+            self.post_shutdown();
+        }
+    }
+}
+
+/// Wakes a port registered with a [`Reactor`] by posting a completion packet
+#[derive(Clone)]
+pub struct ReactorWaker {
+    shared: Arc<Shared>,
+    key: usize,
+}
+
+impl AsRawHandle for ReactorWaker {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.shared.iocp.as_raw_handle()
+    }
+}
+
+impl WakeHandle for ReactorWaker {
+    fn wake(&self) -> io::Result<()> {
+        self.shared.post(self.key)
+    }
+}
+
+/// An I/O completion port reactor servicing many [`ComPort`]s with a small pool of threads
+///
+/// Dropping the reactor cancels the I/O of every registered port and waits for the workers to
+/// finish. The readers of those ports receive an error followed by EOF.
+pub struct Reactor {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Reactor {
+    /// Create a reactor with a pool of worker threads
+    ///
+    /// [CreateIoCompletionPort](https://learn.microsoft.com/en-us/windows/win32/fileio/createiocompletionport)
+    pub fn new(threads: usize) -> io::Result<Reactor> {
+        let threads = threads.max(1);
+        let raw = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, 0, 0, threads as _) };
+        let iocp = match raw {
+            0 => Err(io::Error::last_os_error()),
+            // Safety: CreateIoCompletionPort returned a valid handle which we now own
+            raw => Ok(unsafe { OwnedHandle::from_raw_handle(raw as _) }),
+        }?;
+        let shared = Arc::new(Shared {
+            iocp,
+            ports: Mutex::new(HashMap::new()),
+            next: AtomicUsize::new(SHUTDOWN_KEY + 1),
+            shutdown: AtomicBool::new(false),
+            workers: threads,
+        });
+        let workers = (0..threads)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || worker(&shared))
+            })
+            .collect();
+        Ok(Reactor { shared, workers })
+    }
+
+    /// Open a COM port serviced by this reactor. IE: "COM4" or "\\\\.\\COM4"
+    pub fn open<P>(&self, port: P, config: PortConfig) -> Result<ComPort, OpenError>
+    where
+        P: Into<OsString>,
+    {
+        let port: OsString = port.into();
+        let handle = port::open_configured(&port, &config)?;
+        Ok(self.register(port, handle)?)
+    }
+
+    /// Service a handle opened elsewhere, IE: by FFI code. The handle must have been opened with
+    /// FILE_FLAG_OVERLAPPED and must not be associated with another completion port. The line
+    /// settings of the handle are left untouched
+    pub fn from_owned_handle<P>(&self, port: P, handle: OwnedHandle) -> io::Result<ComPort>
+    where
+        P: Into<OsString>,
+    {
+        self.register(port.into(), PortHandle::from(handle))
+    }
+
+    /// Associate a handle with the completion port and kick off its I/O
+    fn register(&self, port: OsString, handle: PortHandle) -> io::Result<ComPort> {
+        let key = self.shared.next.fetch_add(1, Ordering::SeqCst);
+        let iocp = self.shared.iocp.as_raw_handle() as _;
+        match unsafe { CreateIoCompletionPort(handle.as_raw_handle() as _, iocp, key, 0) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }?;
+        let waker = PortWaker::Reactor(ReactorWaker {
+            shared: Arc::clone(&self.shared),
+            key,
+        });
+        let (queue, thread) = channel::bounded(waker.clone(), QUEUE_CAPACITY);
+        let io = PortIo::new(handle.clone(), thread);
+        self.shared
+            .ports
+            .lock()
+            .insert(key, Arc::new(Mutex::new(io)));
+        // Kick off the first read
+        waker.wake()?;
+        trace!(?port, key, "registered com port with reactor");
+        Ok(ComPort::new(port, handle, waker, queue, None))
+    }
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reactor")
+            .field("workers", &self.workers.len())
+            .field("ports", &self.shared.ports.lock().len())
+            .finish()
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        let keys = self.shared.ports.lock().keys().copied().collect::<Vec<_>>();
+        match keys.is_empty() {
+            true => self.shared.post_shutdown(),
+            false => keys.into_iter().for_each(|key| {
+                if let Err(error) = self.shared.post(key) {
+                    error!(key, ?error, "failed to wake port for reactor shutdown");
+                }
+            }),
+        }
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("reactor worker panicked");
+            }
+        }
+    }
+}
+
+/// The I/O state of a port registered with a reactor
+///
+/// The kernel holds pointers to the overlapped structures and buffers while I/O is in flight, so
+/// they are boxed to keep their address stable and the state is only dropped once no I/O is in
+/// flight.
+struct PortIo {
+    handle: PortHandle,
+    queue: ThreadQueue,
+    rx: Box<OVERLAPPED>,
+    rx_buf: Vec<u8>,
+    tx: Box<OVERLAPPED>,
+    tx_buf: Bytes,
+    /// Bytes read while the task's queue was full. The port is not read again until the task
+    /// makes room, which wakes the port
+    held: Option<Bytes>,
+    /// A read is in flight
+    reading: bool,
+    /// A write is in flight
+    writing: bool,
+    /// The task closed its side of the queue. Finish writing and stop reading
+    draining: bool,
+    /// The pending read was cancelled because we are draining
+    cancelled: bool,
+    /// The port failed or the reactor shut down. Stop all I/O
+    failed: bool,
+}
+
+// Safety: The OVERLAPPED structures are only touched by the kernel and behind the port mutex
+unsafe impl Send for PortIo {}
+
+impl PortIo {
+    fn new(handle: PortHandle, queue: ThreadQueue) -> PortIo {
+        PortIo {
+            handle,
+            queue,
+            // Safety: OVERLAPPED is a plain C struct and is valid when zeroed
+            rx: Box::new(unsafe { std::mem::zeroed() }),
+            rx_buf: vec![0u8; READ_BUFFER_SIZE],
+            tx: Box::new(unsafe { std::mem::zeroed() }),
+            tx_buf: Bytes::new(),
+            held: None,
+            reading: false,
+            writing: false,
+            draining: false,
+            cancelled: false,
+            failed: false,
+        }
+    }
+
+    /// Handle a completion packet. A packet with no overlapped structure is a wake. A cancelled
+    /// operation is not an error and reports the bytes transferred prior to the cancellation. A
+    /// cancelled write, IE: by [`ComPort::abort_io`], resumes with the bytes it did not transmit
+    fn complete(
+        &mut self,
+        overlapped: *mut OVERLAPPED,
+        transferred: usize,
+        result: io::Result<()>,
+    ) {
+        if overlapped.is_null() {
+            return;
+        }
+        let aborted = matches!(
+            &result,
+            Err(e) if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED as _)
+        );
+        let result = match aborted {
+            true => Ok(()),
+            false => result,
+        };
+        if std::ptr::eq(overlapped, &*self.rx) {
+            self.reading = false;
+            if transferred > 0 {
+                let bytes = Bytes::copy_from_slice(&self.rx_buf[..transferred]);
+                if let Err(bytes) = self.queue.push_ok(bytes) {
+                    trace!(len = bytes.len(), "read queue full, holding bytes");
+                    self.held = Some(bytes);
+                }
+            }
+            match (result, comm::take_line_error(&self.handle)) {
+                (Err(_), Some(line)) => self.fail(line.into()),
+                (Err(e), None) => self.fail(e),
+                (Ok(_), Some(line)) => {
+                    let _ = self.queue.push_err(line.into());
+                }
+                (Ok(_), None) => {}
+            }
+        } else if std::ptr::eq(overlapped, &*self.tx) {
+            self.writing = false;
+            self.tx_buf.advance(transferred);
+            match result {
+                // The rest of the buffer is written again by the next pump
+                Ok(_) if aborted => trace!(transferred, "write cancelled"),
+                Ok(_) if transferred == 0 => self.fail(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(_) => {}
+                Err(e) => self.fail(e),
+            }
+        } else {
+            warn!("completion packet for unknown overlapped structure");
+        }
+    }
+
+    /// Report an error to the task and cancel all I/O
+    fn fail(&mut self, error: io::Error) {
+        if !self.failed {
+            self.failed = true;
+            self.queue
+                .fail(io::Error::new(error.kind(), error.to_string()));
+            let _ = self.queue.push_err(error);
+            let _ = unsafe { CancelIoEx(self.handle.as_raw_handle() as _, std::ptr::null()) };
+        }
+    }
+
+    /// Hand the held bytes to the task. Returns false while the task's queue is still full
+    fn release(&mut self) -> bool {
+        match self.held.take().map(|bytes| self.queue.push_ok(bytes)) {
+            Some(Err(bytes)) => {
+                self.held = Some(bytes);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Start what ever I/O the port is ready for. Returns true once the port has finished and no
+    /// I/O is in flight
+    fn pump(&mut self, shutdown: bool) -> bool {
+        if shutdown {
+            self.fail(io::Error::new(io::ErrorKind::Other, "reactor shut down"));
+        }
+        if !self.writing && !self.failed {
+            if self.tx_buf.is_empty() && !self.draining {
+                let (bytes, done) = self.queue.collect();
+                self.tx_buf = bytes;
+                self.draining = done;
+            }
+            if !self.tx_buf.is_empty() {
+                match self.start_write() {
+                    Ok(_) => self.writing = true,
+                    Err(e) => self.fail(e),
+                }
+            }
+        }
+        if self.draining && self.reading && !self.cancelled {
+            self.cancelled = true;
+            let _ = unsafe { CancelIoEx(self.handle.as_raw_handle() as _, &*self.rx) };
+        }
+        if !self.reading && !self.draining && !self.failed && self.release() {
+            match self.start_read() {
+                Ok(_) => self.reading = true,
+                Err(e) => self.fail(e),
+            }
+        }
+        let drained = self.draining && self.tx_buf.is_empty();
+        !self.reading && !self.writing && (self.failed || drained)
+    }
+
+    /// Start an overlapped read. Completion is reported by a completion packet
+    ///
+    /// [ReadFile](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-readfile)
+    fn start_read(&mut self) -> io::Result<()> {
+        *self.rx = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            ReadFile(
+                self.handle.as_raw_handle() as _,
+                self.rx_buf.as_mut_ptr(),
+                self.rx_buf.len() as _,
+                std::ptr::null_mut(),
+                &mut *self.rx,
+            )
+        };
+        pending(result)
+    }
+
+    /// Start an overlapped write. Completion is reported by a completion packet
+    ///
+    /// [WriteFile](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-writefile)
+    fn start_write(&mut self) -> io::Result<()> {
+        *self.tx = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            WriteFile(
+                self.handle.as_raw_handle() as _,
+                self.tx_buf.as_ptr(),
+                self.tx_buf.len() as _,
+                std::ptr::null_mut(),
+                &mut *self.tx,
+            )
+        };
+        pending(result)
+    }
+}
+
+/// An overlapped operation which succeeded immediately still queues a completion packet
+fn pending(result: i32) -> io::Result<()> {
+    match result {
+        FALSE => match unsafe { GetLastError() } {
+            ERROR_IO_PENDING => Ok(()),
+            raw => Err(io::Error::from_raw_os_error(raw as _)),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Dequeue completion packets and drive the I/O of the port each packet belongs to
+///
+/// [GetQueuedCompletionStatus](https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-getqueuedcompletionstatus)
+fn worker(shared: &Shared) {
+    let iocp = shared.iocp.as_raw_handle() as _;
+    loop {
+        let mut transferred = 0u32;
+        let mut key = 0usize;
+        let mut overlapped: *mut OVERLAPPED = std::ptr::null_mut();
+        let result = unsafe {
+            GetQueuedCompletionStatus(iocp, &mut transferred, &mut key, &mut overlapped, INFINITE)
+        };
+        let result = match result {
+            FALSE if overlapped.is_null() => {
+                let error = io::Error::last_os_error();
+                error!(?error, "reactor worker failed to dequeue completion packet");
+                break;
+            }
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        };
+        if key == SHUTDOWN_KEY {
+            trace!("reactor worker finished");
+            break;
+        }
+        let port = match shared.ports.lock().get(&key) {
+            Some(port) => Arc::clone(port),
+            None => {
+                trace!(key, "completion packet for finished port");
+                continue;
+            }
+        };
+        let mut io = port.lock();
+        io.complete(overlapped, transferred as _, result);
+        if io.pump(shared.shutdown.load(Ordering::SeqCst)) {
+            drop(io);
+            trace!(key, "com port finished");
+            shared.remove(key);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! renumerate
+//!
+//! Devices which jump to a bootloader (IE: for a firmware update) leave and arrive again with a
+//! different product id, and often with a different COM port. A [`Renumeration`] stream pairs
+//! such a removal with the arrival which follows it, so the device can be followed across the
+//! jump.
+
+use crate::{
+    event::Deadline,
+    hkey::{PortMeta, ScanResult},
+    wm::PlugEvent,
+};
+use futures::{Future, Stream};
+use pin_project_lite::pin_project;
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::OsString,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tracing::{debug, trace};
+
+/// A COM port and the ids of the device behind it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumeratedPort {
+    /// The com port name. IE: COM4
+    pub port: OsString,
+    /// The Vendor/Product ID's of the serial port
+    pub ids: PortMeta,
+}
+
+/// An event emitted from a [`Renumeration`] stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenumerationEvent {
+    /// A device arrived which does not follow a removal
+    Arrival(EnumeratedPort),
+    /// A device was removed and no paired device arrived within the window
+    Removed(OsString),
+    /// A device was removed and arrived again with the paired ids
+    Renumerated {
+        old: EnumeratedPort,
+        new: EnumeratedPort,
+    },
+}
+
+/// A removal waiting for the paired device to arrive
+#[derive(Debug)]
+struct Removal {
+    old: EnumeratedPort,
+    window: Deadline,
+}
+
+pin_project! {
+    /// A stream which pairs the removal of a device with the arrival of its other identity. See
+    /// [`crate::prelude::DeviceStreamExt::renumeration`]
+    #[derive(Debug)]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Renumeration<St> {
+        #[pin]
+        inner: St,
+        // Pairs of ids the device may switch between. IE: (application, bootloader)
+        pairs: Vec<(PortMeta, PortMeta)>,
+        window: Duration,
+        // The ids of every port which arrived, so removals can be paired
+        ports: HashMap<OsString, PortMeta>,
+        // Removals in the order their windows close
+        pending: VecDeque<Removal>,
+        finished: bool,
+    }
+}
+
+impl<St> Renumeration<St> {
+    pub(crate) fn new(inner: St, pairs: Vec<(PortMeta, PortMeta)>, window: Duration) -> Self {
+        Self {
+            inner,
+            pairs,
+            window,
+            ports: HashMap::new(),
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+/// True when a device with the old ids may arrive again with the new ids
+fn paired(pairs: &[(PortMeta, PortMeta)], old: &PortMeta, new: &PortMeta) -> bool {
+    pairs
+        .iter()
+        .any(|(a, b)| (a == old && b == new) || (b == old && a == new))
+}
+
+impl<St> Stream for Renumeration<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>>,
+{
+    type Item = ScanResult<RenumerationEvent>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            // Removals whose window closed are reported as they are
+            if let Some(pending) = this.pending.front_mut() {
+                if *this.finished || Pin::new(&mut pending.window).poll(cx).is_ready() {
+                    let pending = this.pending.pop_front().unwrap();
+                    trace!(port = ?pending.old.port, "renumeration window closed");
+                    return Poll::Ready(Some(Ok(RenumerationEvent::Removed(pending.old.port))));
+                }
+            }
+            if *this.finished {
+                return Poll::Ready(None);
+            }
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => *this.finished = true,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(PlugEvent::Arrival(port, ids)))) => {
+                    this.ports.insert(port.clone(), ids.clone());
+                    let new = EnumeratedPort { port, ids };
+                    let pairs = &*this.pairs;
+                    let event = match this
+                        .pending
+                        .iter()
+                        .position(|pending| paired(pairs, &pending.old.ids, &new.ids))
+                    {
+                        None => RenumerationEvent::Arrival(new),
+                        Some(n) => {
+                            let old = this.pending.remove(n).unwrap().old;
+                            debug!(old = ?old.port, new = ?new.port, "device renumerated");
+                            RenumerationEvent::Renumerated { old, new }
+                        }
+                    };
+                    return Poll::Ready(Some(Ok(event)));
+                }
+                Poll::Ready(Some(Ok(PlugEvent::RemoveComplete(port)))) => {
+                    let ids = match this.ports.remove(&port) {
+                        Some(ids) if this.pairs.iter().any(|(a, b)| *a == ids || *b == ids) => ids,
+                        _ => return Poll::Ready(Some(Ok(RenumerationEvent::Removed(port)))),
+                    };
+                    trace!(?port, "waiting for device to renumerate");
+                    let window = Deadline::new(*this.window)?;
+                    let old = EnumeratedPort { port, ids };
+                    this.pending.push_back(Removal { old, window });
+                }
+                Poll::Ready(Some(Ok(ev))) => trace!(?ev, "ignoring device event"),
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! reopen
+//!
+//! A COM port which survives being unplugged. The port follows a [`Tracking`] stream and is
+//! reopened whenever the tracked device arrives again, so readers and writers are not torn down
+//! each time a cable is pulled.
+
+use crate::{
+    comm::LineError,
+    hkey::{PortMeta, ScanResult},
+    port::{ComPort, OpenError, PortConfig},
+    prelude::{TrackedPort, Tracking, TrackingError, Unplugged},
+    wm::PlugEvent,
+};
+use futures::{ready, AsyncRead, AsyncWrite, Future, Stream};
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use tracing::{debug, trace};
+
+/// A change in the connection of a [`ReopeningPort`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReopenEvent {
+    /// The device arrived for the first time and the port was opened
+    Connected(OsString),
+    /// The device was unplugged. Reads and writes wait until it arrives again
+    Disconnected(OsString),
+    /// The device arrived again and the port was reopened
+    Reconnected(OsString),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReopenError {
+    #[error("tracking error => {0}")]
+    Tracking(#[from] TrackingError),
+    #[error("open error => {0}")]
+    Open(#[from] OpenError),
+}
+
+impl From<ReopenError> for io::Error {
+    fn from(value: ReopenError) -> Self {
+        match value {
+            ReopenError::Open(error) => error.into(),
+            error => io::Error::new(io::ErrorKind::Other, error),
+        }
+    }
+}
+
+/// A COM port which is reopened when the tracked device is unplugged and arrives again
+///
+/// The first tracked device to arrive is adopted, and afterwards only a device with the same
+/// vendor id, product id and serial number is reopened. Reads and writes are pending while the
+/// device is unplugged. An error of the port, other than a line error, is taken as the device
+/// leaving, so reads and writes wait for it to arrive again. Poll the port as a [`Stream`] to
+/// observe [`ReopenEvent`]s. The stream ends when the underlying [`Tracking`] stream ends.
+///
+/// Devices are only adopted as they arrive. Call [`crate::rescan`] to have devices which are
+/// already connected arrive again.
+pub struct ReopeningPort<St> {
+    tracking: Tracking<St>,
+    open: Box<dyn FnMut(OsString) -> Result<ComPort, OpenError> + Send>,
+    ids: Option<PortMeta>,
+    port: Option<ComPort>,
+    name: Option<OsString>,
+    unplugged: Option<Unplugged>,
+    finished: bool,
+    events: VecDeque<ReopenEvent>,
+    waker: Option<Waker>,
+}
+
+impl<St> ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    /// Open tracked devices with the line settings of a config
+    pub fn new(tracking: Tracking<St>, config: PortConfig) -> Self {
+        Self {
+            tracking,
+            open: Box::new(move |port| config.open(port)),
+            ids: None,
+            port: None,
+            name: None,
+            unplugged: None,
+            finished: false,
+            events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    /// Open the ports of arriving devices with a function instead of the config. IE: to service
+    /// the ports with a [`crate::reactor::Reactor`]
+    pub fn with_open<F>(mut self, open: F) -> Self
+    where
+        F: FnMut(OsString) -> Result<ComPort, OpenError> + Send + 'static,
+    {
+        self.open = Box::new(open);
+        self
+    }
+
+    /// The com port name of the connected device. IE: COM4
+    pub fn port(&self) -> Option<&OsString> {
+        self.port.as_ref().and(self.name.as_ref())
+    }
+
+    /// The currently open port. None while the device is unplugged
+    pub fn get_ref(&self) -> Option<&ComPort> {
+        self.port.as_ref()
+    }
+
+    /// Queue an event and wake whoever is waiting on the event stream
+    fn emit(&mut self, event: ReopenEvent) {
+        trace!(?event, "reopening port event");
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake()
+        }
+    }
+
+    /// Adopt an arriving device, unless we are still attached to another device. Identical
+    /// adapters share their ids, so the serial number must match as well
+    fn arrived(&mut self, tracked: TrackedPort) -> Result<(), ReopenError> {
+        let other = self
+            .ids
+            .as_ref()
+            .is_some_and(|ids| *ids != tracked.ids || ids.serial() != tracked.ids.serial());
+        if self.unplugged.is_some() || other {
+            debug!(port = ?tracked.port, ids = ?tracked.ids, "ignoring com device");
+            return Ok(());
+        }
+        let port = (self.open)(tracked.port.clone())?;
+        let event = match self.ids.replace(tracked.ids) {
+            None => ReopenEvent::Connected(tracked.port.clone()),
+            Some(_) => ReopenEvent::Reconnected(tracked.port.clone()),
+        };
+        self.port = Some(port);
+        self.name = Some(tracked.port);
+        self.unplugged = Some(tracked.unplugged);
+        self.emit(event);
+        Ok(())
+    }
+
+    /// Resolves when a port is open. None when the tracking stream has ended without a port
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<(), ReopenError>>> {
+        loop {
+            // The tracking stream must be driven for removals to resolve the unplugged future
+            if !self.finished {
+                match Pin::new(&mut self.tracking).poll_next(cx) {
+                    Poll::Pending => {}
+                    Poll::Ready(None) => self.finished = true,
+                    Poll::Ready(Some(Err(error))) => break Poll::Ready(Some(Err(error.into()))),
+                    Poll::Ready(Some(Ok(tracked))) => match self.arrived(tracked) {
+                        Err(error) => break Poll::Ready(Some(Err(error))),
+                        Ok(()) => continue,
+                    },
+                }
+            }
+
+            if let Some(unplugged) = self.unplugged.as_mut() {
+                if let Poll::Ready(_) = Pin::new(unplugged).poll(cx) {
+                    self.unplugged = None;
+                    self.port = None;
+                    if let Some(name) = self.name.take() {
+                        self.emit(ReopenEvent::Disconnected(name));
+                    }
+                    continue;
+                }
+            }
+
+            break match (&self.port, self.finished) {
+                (Some(_), _) => Poll::Ready(Some(Ok(()))),
+                (None, true) => Poll::Ready(None),
+                (None, false) => Poll::Pending,
+            };
+        }
+    }
+
+    /// Close a port which failed, IE: because the device was unplugged before the removal
+    /// arrived. Line errors do not close the port and are returned to the caller
+    fn failed(&mut self, error: io::Error) -> Option<io::Error> {
+        match error.get_ref().is_some_and(|inner| inner.is::<LineError>()) {
+            true => Some(error),
+            false => {
+                debug!(port = ?self.name, ?error, "com port failed, waiting for the device");
+                self.port = None;
+                None
+            }
+        }
+    }
+}
+
+impl<St> AsyncRead for ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match ready!(this.poll_connected(cx)) {
+                None => break Poll::Ready(Ok(0)),
+                Some(Err(error)) => break Poll::Ready(Err(error.into())),
+                Some(Ok(())) => {}
+            }
+            let port = this.port.as_mut().expect("connected port");
+            match ready!(Pin::new(port).poll_read(cx, buf)) {
+                // The io thread has finished, wait for the device to be unplugged and arrive again
+                Ok(0) if !buf.is_empty() => this.port = None,
+                Err(error) => {
+                    if let Some(error) = this.failed(error) {
+                        break Poll::Ready(Err(error));
+                    }
+                }
+                result => break Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl<St> AsyncWrite for ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match ready!(this.poll_connected(cx)) {
+                None => break Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+                Some(Err(error)) => break Poll::Ready(Err(error.into())),
+                Some(Ok(())) => {}
+            }
+            let port = this.port.as_mut().expect("connected port");
+            match ready!(Pin::new(port).poll_write(cx, buf)) {
+                Err(error) => {
+                    if let Some(error) = this.failed(error) {
+                        break Poll::Ready(Err(error));
+                    }
+                }
+                result => break Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().port.as_mut() {
+            None => Poll::Ready(Ok(())),
+            Some(port) => Pin::new(port).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().port.as_mut() {
+            None => Poll::Ready(Ok(())),
+            Some(port) => Pin::new(port).poll_close(cx),
+        }
+    }
+}
+
+impl<St> Stream for ReopeningPort<St>
+where
+    St: Stream<Item = ScanResult<PlugEvent>> + Unpin,
+{
+    type Item = Result<ReopenEvent, ReopenError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let finished = match this.poll_connected(cx) {
+            Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+            Poll::Ready(None) => true,
+            _ => false,
+        };
+        match this.events.pop_front() {
+            Some(event) => Poll::Ready(Some(Ok(event))),
+            None if finished => Poll::Ready(None),
+            None => {
+                this.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! cfgmgr
+use crate::cfgmgr::check;
+use windows_sys::Win32::{
+    Devices::DeviceAndDriverInstallation::{CR_OUT_OF_MEMORY, CR_SUCCESS},
+    Foundation::ERROR_NOT_ENOUGH_MEMORY,
+};
+
+#[test]
+fn comport_test_cfgmgr_check() {
+    assert!(check(CR_SUCCESS).is_ok());
+    let error = check(CR_OUT_OF_MEMORY).unwrap_err();
+    assert_eq!(Some(ERROR_NOT_ENOUGH_MEMORY as i32), error.raw_os_error());
+}
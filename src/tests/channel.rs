@@ -1,13 +1,21 @@
 //! channel
 
-use crate::channel::{self, WakeHandle};
-use bytes::BytesMut;
-use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use crate::{
+    channel::{self, Pace, ThrottledWriter, WakeHandle},
+    event::EventError,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
 use std::{
     io,
     os::windows::io::{AsRawHandle, RawHandle},
     pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     task::Poll,
+    time::{Duration, Instant},
 };
 
 struct MockHandle {}
@@ -78,7 +86,7 @@ fn comport_test_channel_thread() {
     assert_eq!(None, thread.pop());
 
     // push data from the task side to the thread side
-    let bytes = BytesMut::from("hi");
+    let bytes = Bytes::from("hi");
     task.push(bytes.clone()).unwrap();
     assert_eq!(Some(Some(bytes)), thread.pop());
 
@@ -227,3 +235,436 @@ async fn comport_test_channel_writer() {
     let poll = writer.as_mut().poll_flush(&mut cx);
     assert!(poll.is_ready());
 }
+
+#[tokio::test]
+async fn comport_test_channel_throttled_writer() {
+    // Make sure chunks are passed on after the delay
+    let pace = Pace::ChunkDelay {
+        chunk: 2,
+        delay: Duration::from_millis(20),
+    };
+    let mut writer = ThrottledWriter::new(futures::io::Cursor::new(Vec::new()), pace);
+    let start = Instant::now();
+    assert_eq!(2, writer.write(b"hello!").await.unwrap());
+    writer.write_all(b"llo!").await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    assert_eq!(b"hello!", &writer.into_inner().into_inner()[..]);
+
+    // Make sure the average rate holds. 300 bytes at 1000 bytes per second is 3 chunks of 100
+    let pace = Pace::BytesPerSecond(1000);
+    let mut writer = ThrottledWriter::new(futures::io::Cursor::new(Vec::new()), pace);
+    let start = Instant::now();
+    writer.write_all(&[0u8; 300]).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(180));
+    assert_eq!(300, writer.into_inner().into_inner().len());
+}
+
+#[test]
+fn comport_test_channel_grow() {
+    // Make sure a full queue overflows until it grows
+    let (task, thread) = channel::bounded(MockHandle {}, 2);
+    task.push(BytesMut::from("a")).unwrap();
+    task.push(BytesMut::from("b")).unwrap();
+    assert!(matches!(
+        task.push(BytesMut::from("c")),
+        Err(channel::TaskError::Overflow(bytes)) if bytes == "c"
+    ));
+    task.grow(3);
+    assert_eq!(3, task.capacity());
+    task.push(BytesMut::from("c")).unwrap();
+    thread.push_ok(BytesMut::from("1")).unwrap();
+    thread.push_ok(BytesMut::from("2")).unwrap();
+    thread.push_ok(BytesMut::from("3")).unwrap();
+    assert!(thread.push_ok(BytesMut::from("4")).is_err());
+
+    // Make sure the capacity never shrinks
+    task.grow(1);
+    assert_eq!(3, task.capacity());
+    let (bytes, done) = thread.collect();
+    assert_eq!("abc", bytes);
+    assert!(!done);
+
+    // Make sure an unbounded queue never overflows
+    let (task, thread) = channel::unbounded(MockHandle {});
+    for _ in 0..1000 {
+        task.push(BytesMut::from("x")).unwrap();
+    }
+    drop(task);
+    let (bytes, done) = thread.collect();
+    assert_eq!(1000, bytes.len());
+    assert!(done);
+}
+
+#[test]
+fn comport_test_channel_wait_room() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+    let wakes = Arc::new(AtomicUsize::new(0));
+    let (task, thread) = channel::bounded(CountingHandle(Arc::clone(&wakes)), 1);
+    let mut stream = task.listen();
+
+    // Make sure the thread waits for room while the task is behind
+    thread.push_ok(BytesMut::from("a")).unwrap();
+    let bytes = thread.push_ok(BytesMut::from("b")).unwrap_err();
+    let error = thread
+        .wait_room(Some(Duration::from_millis(20)))
+        .unwrap_err();
+    assert!(matches!(error, EventError::Timeout));
+    assert_eq!(0, wakes.load(Ordering::SeqCst));
+
+    // Make sure the task makes room and wakes the thread once
+    assert_ready_eq!("a", stream.poll_next_unpin(&mut cx));
+    assert_eq!(1, wakes.load(Ordering::SeqCst));
+    thread.wait_room(Some(Duration::ZERO)).unwrap();
+    thread.push_ok(bytes).unwrap();
+    assert_ready_eq!("b", stream.poll_next_unpin(&mut cx));
+    assert_eq!(1, wakes.load(Ordering::SeqCst));
+
+    // Make sure a write wakes a thread waiting for room
+    thread.push_ok(BytesMut::from("c")).unwrap();
+    assert!(thread.push_ok(BytesMut::from("d")).is_err());
+    task.push(BytesMut::from("x")).unwrap();
+    thread.wait_room(Some(Duration::ZERO)).unwrap();
+}
+
+#[test]
+fn comport_test_channel_thread_wait() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+
+    // Make sure the thread times out when nothing is pushed
+    assert!(matches!(
+        thread.wait(Some(Duration::from_millis(10))),
+        Err(EventError::Timeout)
+    ));
+
+    // Make sure the thread wakes up for data and for shutdown
+    let waiter = std::thread::spawn(move || {
+        thread.wait(None).unwrap();
+        assert_eq!(Some(Some(Bytes::from("hi"))), thread.pop());
+        thread.wait(None).unwrap();
+        assert_eq!(Some(None), thread.pop());
+    });
+    std::thread::sleep(Duration::from_millis(10));
+    task.push(BytesMut::from("hi")).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    drop(task);
+    waiter.join().unwrap();
+}
+
+#[test]
+fn comport_test_channel_thread_pop_blocking() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+
+    // Make sure the thread times out when nothing is pushed
+    assert!(matches!(
+        thread.pop_blocking(Some(Duration::from_millis(10))),
+        Err(EventError::Timeout)
+    ));
+
+    // Make sure the thread gets the data and then the shutdown
+    let waiter = std::thread::spawn(move || {
+        let timeout = Some(Duration::from_secs(5));
+        assert_eq!(
+            Some(Bytes::from("hi")),
+            thread.pop_blocking(timeout).unwrap()
+        );
+        assert_eq!(None, thread.pop_blocking(timeout).unwrap());
+    });
+    std::thread::sleep(Duration::from_millis(10));
+    task.push(Bytes::from("hi")).unwrap();
+    drop(task);
+    waiter.join().unwrap();
+}
+
+#[tokio::test]
+async fn comport_test_channel_reader_buf_read() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut reader = task.reader();
+
+    // Make sure lines are joined across queue items
+    thread.push_ok(BytesMut::from("hel")).unwrap();
+    thread.push_ok(BytesMut::from("lo\nwor")).unwrap();
+    thread.push_ok(BytesMut::from("ld\n")).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!("hello\n", line);
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!("world\n", line);
+
+    // Make sure buffered reads and plain reads share the same bytes
+    thread.push_ok(BytesMut::from("ab;cd")).unwrap();
+    let mut frame = Vec::new();
+    reader.read_until(b';', &mut frame).await.unwrap();
+    assert_eq!(b"ab;", &frame[..]);
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"cd", &buf);
+
+    // Make sure errors are surfaced and eof is empty
+    thread.push_err(io::ErrorKind::BrokenPipe.into()).unwrap();
+    assert!(reader.fill_buf().await.is_err());
+    drop(thread);
+    assert!(reader.fill_buf().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn comport_test_channel_stats() {
+    let (task, thread) = channel::bounded(MockHandle {}, 2);
+    let stats = task.stats();
+
+    // Make sure traffic in both directions is counted
+    let mut writer = task.writer();
+    writer.write_all(b"hello").await.unwrap();
+    task.push(BytesMut::from("world")).unwrap();
+    thread.push_ok(BytesMut::from("abc")).unwrap();
+    assert_eq!(10, stats.bytes_written());
+    assert_eq!(3, stats.bytes_read());
+    assert_eq!(2, stats.thread_high_water());
+    assert_eq!(1, stats.task_high_water());
+    assert_eq!(0, stats.overflows());
+
+    // Make sure rejected pushes are counted and the high water mark stays
+    assert!(task.push(BytesMut::from("!")).is_err());
+    thread.push_ok(BytesMut::from("d")).unwrap();
+    assert!(thread.push_ok(BytesMut::from("e")).is_err());
+    assert_eq!(2, thread.stats().overflows());
+    thread.collect();
+    assert_eq!(2, stats.thread_high_water());
+    assert_eq!(2, stats.task_high_water());
+}
+
+#[tokio::test]
+async fn comport_test_channel_reader_next_bytes() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut reader = task.reader();
+
+    // Make sure buffers are handed over without a copy
+    let bytes = Bytes::from(vec![1, 2, 3, 4]);
+    let ptr = bytes.as_ptr();
+    thread.push_ok(bytes).unwrap();
+    let next = reader.next_bytes().await.unwrap().unwrap();
+    assert_eq!(ptr, next.as_ptr());
+
+    // Make sure the remainder of a partial read is handed over first
+    thread.push_ok(Bytes::from("hello")).unwrap();
+    thread.push_ok(Bytes::from("world")).unwrap();
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!("llo", reader.next_bytes().await.unwrap().unwrap());
+    assert_eq!("world", reader.next_bytes().await.unwrap().unwrap());
+
+    // Make sure a lone buffer is collected without a copy
+    let bytes = Bytes::from(vec![5, 6, 7]);
+    let ptr = bytes.as_ptr();
+    task.push(bytes).unwrap();
+    let (collected, _) = thread.collect();
+    assert_eq!(ptr, collected.as_ptr());
+
+    drop(thread);
+    assert!(reader.next_bytes().await.is_none());
+}
+
+#[tokio::test]
+async fn comport_test_channel_writer_close_after_drain() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Make sure close waits for pending writes to be consumed before signaling eof
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut writer = pin!(task.writer().close_after_drain(None));
+    writer.write_all(b"hello").await.unwrap();
+    assert!(writer.as_mut().poll_close(&mut cx).is_pending());
+    assert_eq!(Some(Some(Bytes::from("hello"))), thread.pop());
+    assert!(writer.as_mut().poll_close(&mut cx).is_ready());
+    assert_eq!(Some(None), thread.pop());
+
+    // Make sure eof is signaled when the drain times out
+    let timeout = Some(Duration::from_millis(20));
+    let mut writer = task.writer().close_after_drain(timeout);
+    writer.write_all(b"world").await.unwrap();
+    let error = writer.close().await.unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, error.kind());
+    let (bytes, done) = thread.collect();
+    assert_eq!("world", bytes);
+    assert!(done);
+}
+
+#[tokio::test]
+async fn comport_test_channel_split() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut reader = task.reader();
+    let mut writer = task.writer();
+    let other = thread.clone();
+    let (rx, tx) = thread.split();
+
+    // Make sure dropping a clone of the sender does not end the reads
+    drop(rx.clone());
+    drop(other);
+
+    // Make sure the thread can stop reading and keep flushing writes
+    rx.push_ok(Bytes::from("hello")).unwrap();
+    drop(rx);
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf[..]);
+    writer.write_all(b"world").await.unwrap();
+    assert_eq!(Some(Some(Bytes::from("world"))), tx.pop());
+    writer.flush().await.unwrap();
+
+    // Make sure the thread sees the end of the writes
+    drop(task);
+    drop(writer);
+    let (bytes, done) = tx.collect();
+    assert!(bytes.is_empty());
+    assert!(done);
+}
+
+#[tokio::test]
+async fn comport_test_channel_writer_error() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Make sure a pending flush sees the failed write
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut writer = pin!(task.writer());
+    writer.write_all(b"hello").await.unwrap();
+    assert!(writer.as_mut().poll_flush(&mut cx).is_pending());
+    thread.fail(io::ErrorKind::TimedOut.into());
+    let error = writer.flush().await.unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, error.kind());
+
+    // Make sure writes after the failure are not silently queued
+    let error = writer.write(b"world").await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+}
+
+#[tokio::test]
+async fn comport_test_channel_subscribe() {
+    let (task, thread) = channel::bounded(MockHandle {}, 2);
+    let mut stream = task.listen();
+    thread.push_ok(Bytes::from("early")).unwrap();
+    let mut sniffer = task.subscribe();
+    let mut logger = task.subscribe();
+
+    // Make sure every subscriber sees the bytes pushed after it subscribed
+    thread.push_ok(Bytes::from("hello")).unwrap();
+    assert_eq!("early", stream.next().await.unwrap().unwrap());
+    assert_eq!("hello", stream.next().await.unwrap().unwrap());
+    assert_eq!("hello", sniffer.next().await.unwrap().unwrap());
+
+    // Make sure a slow subscriber lags without stalling the others
+    thread.push_ok(Bytes::from("a")).unwrap();
+    assert_eq!("a", stream.next().await.unwrap().unwrap());
+    thread.push_ok(Bytes::from("b")).unwrap();
+    assert_eq!("b", stream.next().await.unwrap().unwrap());
+    assert_eq!(1, logger.lagged());
+    assert_eq!(0, sniffer.lagged());
+    assert_eq!("a", sniffer.next().await.unwrap().unwrap());
+    assert_eq!("b", sniffer.next().await.unwrap().unwrap());
+
+    // Make sure errors and the end of the stream reach subscribers
+    thread.push_err(io::ErrorKind::BrokenPipe.into()).unwrap();
+    drop(thread);
+    let error = sniffer.next().await.unwrap().unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+    assert!(sniffer.next().await.is_none());
+    let mut reader = channel::Reader::from(logger);
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"helloa", &buf[..]);
+}
+
+#[tokio::test]
+async fn comport_test_channel_priority() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut writer = task.writer();
+
+    // Make sure control frames jump ahead of bulk writes
+    writer.write_all(b"bulk1").await.unwrap();
+    writer.write_all(b"bulk2").await.unwrap();
+    task.push_priority(Bytes::from("abort")).unwrap();
+    assert_eq!(Some(Some(Bytes::from("abort"))), thread.pop());
+    assert_eq!(Some(Some(Bytes::from("bulk1"))), thread.pop());
+
+    // Make sure collect puts control frames first and flush waits for them
+    task.push_priority(Bytes::from("ping")).unwrap();
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+    assert!(pin!(&mut writer).poll_flush(&mut cx).is_pending());
+    let (bytes, done) = thread.collect();
+    assert_eq!("pingbulk2", bytes);
+    assert!(!done);
+    assert!(pin!(&mut writer).poll_flush(&mut cx).is_ready());
+}
+
+/// Counts how often the thread is woken
+struct CountingHandle(Arc<AtomicUsize>);
+impl AsRawHandle for CountingHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        0 as _
+    }
+}
+
+impl WakeHandle for CountingHandle {
+    fn wake(&self) -> std::io::Result<()> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn comport_test_channel_writer_wakes() {
+    let wakes = Arc::new(AtomicUsize::new(0));
+    let handle = CountingHandle(Arc::clone(&wakes));
+    let (task, thread) = channel::bounded(handle, 4);
+
+    // Make sure every write and the close wake the thread
+    let mut writer = task.writer();
+    writer.write_all(b"hello").await.unwrap();
+    writer.write_all(b"world").await.unwrap();
+    assert_eq!(2, wakes.load(Ordering::SeqCst));
+    writer.close().await.unwrap();
+    assert_eq!(3, wakes.load(Ordering::SeqCst));
+    let (bytes, done) = thread.collect();
+    assert_eq!("helloworld", bytes);
+    assert!(done);
+}
+
+#[tokio::test]
+async fn comport_test_channel_mock() {
+    let (mut port, device) = channel::mock();
+    device.respond("PING\n", "PONG\n");
+    device.respond("VER?\n", "1.0.0\n");
+
+    // Make sure scripted requests are answered, even when written in pieces
+    let mut line = String::new();
+    port.write_all(b"PI").await.unwrap();
+    port.write_all(b"NG\nVER?\n").await.unwrap();
+    port.read_line(&mut line).await.unwrap();
+    assert_eq!("PONG\n", line);
+    line.clear();
+    port.read_line(&mut line).await.unwrap();
+    assert_eq!("1.0.0\n", line);
+    assert_eq!("PING\nVER?\n", device.written());
+
+    // Make sure responses are delayed by the latency
+    device.set_latency(Duration::from_millis(30));
+    let start = Instant::now();
+    port.write_all(b"PING\n").await.unwrap();
+    line.clear();
+    port.read_line(&mut line).await.unwrap();
+    assert_eq!("PONG\n", line);
+    assert!(start.elapsed() >= Duration::from_millis(25));
+
+    // Make sure unsolicited bytes, errors and unplugging reach the application
+    device.send("EVENT\n");
+    device.inject_error(io::ErrorKind::InvalidData.into());
+    line.clear();
+    port.read_line(&mut line).await.unwrap();
+    assert_eq!("EVENT\n", line);
+    let error = port.read(&mut [0; 8]).await.unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    drop(device);
+    assert_eq!(0, port.read(&mut [0; 8]).await.unwrap());
+}
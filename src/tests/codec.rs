@@ -0,0 +1,102 @@
+//! codec
+
+use crate::codec::{CobsCodec, CodecError, Decoder, Encoder, Framed, LinesCodec, SlipCodec};
+use bytes::BytesMut;
+use futures::{executor::block_on, io::Cursor, SinkExt, StreamExt};
+
+#[test]
+fn comport_test_codec_lines() {
+    // Make sure lines split across reads are joined and carriage returns are stripped
+    let mut codec = LinesCodec::new().with_max_length(8);
+    let mut buf = BytesMut::from(&b"hello\r\nwor"[..]);
+    assert_eq!("hello", codec.decode(&mut buf).unwrap().unwrap());
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"ld\n");
+    assert_eq!("world", codec.decode(&mut buf).unwrap().unwrap());
+
+    // Make sure long lines are reported once and discarded up to the next newline
+    buf.extend_from_slice(b"too long for us");
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(CodecError::MaxLengthExceeded(8))
+    ));
+    buf.extend_from_slice(b"...\nok\n");
+    assert_eq!("ok", codec.decode(&mut buf).unwrap().unwrap());
+
+    // Make sure an unterminated final line is emitted at eof
+    buf.extend_from_slice(b"last");
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    assert_eq!("last", codec.decode_eof(&mut buf).unwrap().unwrap());
+    assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+}
+
+#[test]
+fn comport_test_codec_cobs() {
+    let mut codec = CobsCodec::new();
+    let mut buf = BytesMut::new();
+
+    // Known encodings
+    codec.encode([0x11u8, 0x22, 0x00, 0x33], &mut buf).unwrap();
+    assert_eq!(&[0x03, 0x11, 0x22, 0x02, 0x33, 0x00], &buf[..]);
+    buf.clear();
+    codec.encode([0x00u8], &mut buf).unwrap();
+    assert_eq!(&[0x01, 0x01, 0x00], &buf[..]);
+    buf.clear();
+
+    // Make sure full blocks round trip
+    for len in [0, 1, 253, 254, 255, 600] {
+        let data = (0..len).map(|n| (n % 7) as u8).collect::<Vec<_>>();
+        codec.encode(&data, &mut buf).unwrap();
+        assert!(!buf[..buf.len() - 1].contains(&0));
+        assert_eq!(&data[..], &codec.decode(&mut buf).unwrap().unwrap()[..]);
+        assert!(buf.is_empty());
+    }
+
+    // Make sure invalid frames are reported and the next frame still decodes
+    buf.extend_from_slice(&[0x05, 0x11, 0x00, 0x02, 0x22, 0x00]);
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(CodecError::InvalidFrame)
+    ));
+    assert_eq!(&[0x22], &codec.decode(&mut buf).unwrap().unwrap()[..]);
+}
+
+#[test]
+fn comport_test_codec_slip() {
+    let mut codec = SlipCodec::new();
+    let mut buf = BytesMut::new();
+
+    // Make sure special bytes are escaped and round trip
+    codec.encode([0x01u8, 0xC0, 0xDB, 0x02], &mut buf).unwrap();
+    assert_eq!(&[0xC0, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0x02, 0xC0], &buf[..]);
+    assert_eq!(
+        &[0x01, 0xC0, 0xDB, 0x02],
+        &codec.decode(&mut buf).unwrap().unwrap()[..]
+    );
+
+    // Make sure invalid escapes are reported
+    buf.extend_from_slice(&[0xDB, 0x01, 0xC0]);
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(CodecError::InvalidFrame)
+    ));
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+}
+
+#[test]
+fn comport_test_codec_framed() {
+    block_on(async {
+        // Make sure messages written through the sink are read back from the stream
+        let mut framed = Framed::new(Cursor::new(Vec::new()), LinesCodec::new());
+        framed.send("one").await.unwrap();
+        framed.send(String::from("two")).await.unwrap();
+        let mut inner = framed.into_inner();
+        assert_eq!(b"one\ntwo\n", &inner.get_ref()[..]);
+        inner.set_position(0);
+
+        let mut framed = Framed::new(inner, LinesCodec::new());
+        assert_eq!("one", framed.next().await.unwrap().unwrap());
+        assert_eq!("two", framed.next().await.unwrap().unwrap());
+        assert!(framed.next().await.is_none());
+    });
+}
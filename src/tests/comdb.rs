@@ -0,0 +1,10 @@
+//! comdb
+use crate::{comdb::reserved_ports, ComPortName};
+
+#[test]
+fn comport_test_comdb_reserved_ports() {
+    let usage = [1, 0, 1, 0, 0, 0, 0, 0, 0, 1];
+    let expect: Vec<_> = [1, 3, 10].into_iter().map(ComPortName::new).collect();
+    assert_eq!(expect, reserved_ports(&usage));
+    assert!(reserved_ports(&[0; 256]).is_empty());
+}
@@ -0,0 +1,97 @@
+//! comm
+use crate::comm::{
+    Capabilities, DataBits, LineError, Parity, ProviderSubtype, SettableParams, StopBits, Timeouts,
+};
+use crate::port::PortConfig;
+use std::{io, time::Duration};
+use windows_sys::Win32::{
+    Devices::Communication::{
+        COMMPROP, COMMTIMEOUTS, PARITY_EVEN, PARITY_NONE, STOPBITS_10, STOPBITS_20,
+    },
+    System::WindowsProgramming::{
+        BAUD_115200, BAUD_9600, BAUD_USER, PST_RS232, PST_UNSPECIFIED, SP_BAUD, SP_PARITY,
+    },
+};
+
+#[test]
+fn comport_test_comm_timeouts() {
+    // Make sure the defaults return as soon as any bytes arrive
+    let raw = COMMTIMEOUTS::from(Timeouts::default());
+    assert_eq!(u32::MAX, raw.ReadIntervalTimeout);
+    assert_eq!(u32::MAX, raw.ReadTotalTimeoutMultiplier);
+    assert_eq!(100, raw.ReadTotalTimeoutConstant);
+    assert_eq!(0, raw.WriteTotalTimeoutMultiplier);
+    assert_eq!(0, raw.WriteTotalTimeoutConstant);
+
+    // Make sure we round trip and saturate at MAXDWORD
+    let timeouts = Timeouts {
+        read_interval: Duration::from_millis(10),
+        read_total_multiplier: Duration::from_millis(1),
+        read_total_constant: Duration::from_secs(1),
+        write_total_multiplier: Duration::from_millis(2),
+        write_total_constant: Duration::MAX,
+    };
+    let raw = COMMTIMEOUTS::from(timeouts);
+    assert_eq!(u32::MAX, raw.WriteTotalTimeoutConstant);
+    let back = Timeouts::from(raw);
+    assert_eq!(Timeouts::MAX, back.write_total_constant);
+    assert_eq!(timeouts.read_interval, back.read_interval);
+    assert_eq!(timeouts.read_total_constant, back.read_total_constant);
+}
+
+#[test]
+fn comport_test_comm_line_error() {
+    // Make sure we display each error
+    let line = LineError::FRAME | LineError::PARITY;
+    assert_eq!("line error => FRAME | PARITY", line.to_string());
+
+    // Make sure we can recover the line error from an io error
+    let error = io::Error::from(line);
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    let inner = error.get_ref().and_then(|e| e.downcast_ref::<LineError>());
+    assert_eq!(Some(&line), inner);
+}
+
+#[test]
+fn comport_test_comm_capabilities() {
+    // A fixed rate RS232 provider
+    let mut raw: COMMPROP = unsafe { std::mem::zeroed() };
+    raw.dwMaxBaud = BAUD_115200;
+    raw.dwProvSubType = PST_RS232;
+    raw.dwSettableParams = SP_BAUD | SP_PARITY;
+    raw.dwSettableBaud = BAUD_9600 | BAUD_115200;
+    raw.wSettableData = 0x0008;
+    raw.wSettableStopParity = STOPBITS_10 | STOPBITS_20 | PARITY_NONE | PARITY_EVEN;
+    let caps = Capabilities::from(raw);
+    assert_eq!(Some(115200), caps.max_baud);
+    assert_eq!(ProviderSubtype::Rs232, caps.provider_subtype);
+    assert_eq!(
+        SettableParams::BAUD | SettableParams::PARITY,
+        caps.settable_params
+    );
+    assert!(caps.supports_baud_rate(9600));
+    assert!(!caps.supports_baud_rate(19200));
+    assert!(caps.supports_data_bits(DataBits::Eight));
+    assert!(!caps.supports_data_bits(DataBits::Seven));
+    assert!(caps.supports_parity(Parity::Even));
+    assert!(!caps.supports_parity(Parity::Odd));
+    assert!(caps.supports_stop_bits(StopBits::Two));
+    assert!(!caps.supports_stop_bits(StopBits::OnePointFive));
+    assert!(PortConfig::new().is_supported_by(&caps));
+    assert!(!PortConfig::new()
+        .with_baud_rate(19200)
+        .is_supported_by(&caps));
+
+    // Programmable rates are accepted, and unknown subtypes are preserved
+    raw.dwMaxBaud = BAUD_USER;
+    raw.dwSettableBaud = BAUD_USER;
+    raw.dwProvSubType = 0x1234;
+    let caps = Capabilities::from(raw);
+    assert_eq!(None, caps.max_baud);
+    assert_eq!(ProviderSubtype::Other(0x1234), caps.provider_subtype);
+    assert!(caps.supports_baud_rate(250000));
+    assert_ne!(
+        ProviderSubtype::from(PST_UNSPECIFIED),
+        caps.provider_subtype
+    );
+}
@@ -0,0 +1,38 @@
+//! compat
+
+use crate::channel::{self, WakeHandle};
+use bytes::{Bytes, BytesMut};
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+struct MockHandle {}
+impl AsRawHandle for MockHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        0 as _
+    }
+}
+
+impl WakeHandle for MockHandle {
+    fn wake(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn comport_test_compat_tokio() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+
+    // Make sure the reader fills a tokio read buffer
+    thread.push_ok(BytesMut::from("hello")).unwrap();
+    let mut reader = task.reader();
+    let mut buf = [0u8; 8];
+    let n = AsyncReadExt::read(&mut reader, &mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf[..n]);
+
+    // Make sure the writer accepts tokio writes
+    let mut writer = task.writer();
+    AsyncWriteExt::write_all(&mut writer, b"world")
+        .await
+        .unwrap();
+    assert_eq!(Some(Some(Bytes::from("world"))), thread.pop());
+}
@@ -0,0 +1,35 @@
+//! debounce
+
+use crate::{prelude::*, PlugEvent, PortMeta};
+use futures::{stream, StreamExt};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn comport_test_debounce() {
+    let ids = PortMeta::from(("0483", "5740"));
+    let events = vec![
+        PlugEvent::Arrival("COM3".into(), ids.clone()),
+        PlugEvent::RemoveComplete("COM3".into()),
+        PlugEvent::Arrival("COM4".into(), ids.clone()),
+        PlugEvent::Arrival("COM4".into(), ids.clone()),
+        PlugEvent::QueryRemove("COM5".into()),
+        PlugEvent::RemoveComplete("COM5".into()),
+        PlugEvent::Arrival("COM5".into(), ids.clone()),
+    ];
+    let window = Duration::from_millis(50);
+    let start = Instant::now();
+    let mut stream = stream::iter(events.into_iter().map(Ok))
+        .chain(stream::pending())
+        .debounce(window);
+
+    // Make sure other events pass through immediately
+    let event = stream.next().await.unwrap().unwrap();
+    assert!(matches!(event, PlugEvent::QueryRemove(port) if port == "COM5"));
+
+    // Make sure toggles are dropped, and a steady arrival is reported once the window closes
+    let event = stream.next().await.unwrap().unwrap();
+    assert!(matches!(event, PlugEvent::Arrival(port, _) if port == "COM4"));
+    assert!(start.elapsed() >= Duration::from_millis(40));
+    let next = tokio::time::timeout(Duration::from_millis(100), stream.next()).await;
+    assert!(next.is_err());
+}
@@ -0,0 +1,30 @@
+//! dedupe
+
+use crate::{prelude::*, PlugEvent, PortMeta};
+use futures::{stream, StreamExt};
+use std::ffi::OsStr;
+
+#[tokio::test]
+async fn comport_test_dedupe() {
+    let app = PortMeta::from(("0483", "5740"));
+    let boot = PortMeta::from(("0483", "df11"));
+    let events = vec![
+        PlugEvent::Arrival("COM3".into(), app.clone()),
+        PlugEvent::Arrival("COM3".into(), app.clone()),
+        PlugEvent::Arrival("COM3".into(), boot.clone()),
+        PlugEvent::RemoveComplete("COM3".into()),
+        PlugEvent::RemoveComplete("COM3".into()),
+        PlugEvent::RemoveComplete("COM4".into()),
+    ];
+    let mut stream = stream::iter(events.into_iter().map(Ok)).dedupe();
+
+    // Make sure only transitions are reported
+    let event = stream.next().await.unwrap().unwrap();
+    assert!(matches!(event, PlugEvent::Arrival(port, ids) if port == "COM3" && ids == app));
+    let event = stream.next().await.unwrap().unwrap();
+    assert!(matches!(event, PlugEvent::Arrival(port, ids) if port == "COM3" && ids == boot));
+    assert!(stream.connected().contains_key(OsStr::new("COM3")));
+    let event = stream.next().await.unwrap().unwrap();
+    assert!(matches!(event, PlugEvent::RemoveComplete(port) if port == "COM3"));
+    assert!(stream.next().await.is_none());
+}
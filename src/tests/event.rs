@@ -1,5 +1,9 @@
-use crate::event::{self, Event, EventInitialState, EventListener, EventReset, WaitError};
+use crate::event::{
+    self, CancellationToken, Event, EventInitialState, EventListener, EventReset, WaitError,
+    WaitResult,
+};
 use futures::FutureExt;
+use std::time::Duration;
 
 #[test]
 fn comport_test_event() {
@@ -26,7 +30,7 @@ fn comport_test_event() {
     ev.set().unwrap();
     std::thread::sleep(std::time::Duration::from_millis(10));
     let poll = fut.poll_unpin(&mut cx);
-    assert!(poll.is_ready());
+    assert_eq!(std::task::Poll::Ready(WaitResult::Signaled), poll);
 
     // Reset the event and listen again. (No longer in progress)
     ev.reset().unwrap();
@@ -56,11 +60,184 @@ fn comport_test_event_oneshot() {
     // Make sure we are pending
     let poll = receiver.poll_unpin(&mut cx);
     assert!(poll.is_pending());
+    assert!(!sender.is_set());
+    assert!(!receiver.is_set());
 
-    // Make sure we set event and are no longer pending anymore
-    // NOTE we set the time delay to allow kernel some time to drive our future
+    // Make sure the flag is set without polling
     sender.set().unwrap();
+    assert!(receiver.is_set());
+
+    // Make sure we are no longer pending anymore
+    // NOTE we set the time delay to allow kernel some time to drive our future
     std::thread::sleep(std::time::Duration::from_millis(10));
     let poll = receiver.poll_unpin(&mut cx);
     assert!(poll.is_ready());
 }
+
+#[tokio::test]
+async fn comport_test_event_timeout() {
+    let ev = Event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let pool = EventListener::new().unwrap();
+
+    // Make sure a timeout resolves as an outcome rather than a signal
+    let result = pool.start(&ev, Some(Duration::from_millis(10))).await;
+    assert_eq!(WaitResult::TimedOut, result);
+    assert_eq!(Err(WaitError::Timeout), result.signaled());
+
+    // Make sure a signal before the timeout resolves as a signal
+    ev.set().unwrap();
+    let result = pool
+        .restart(&ev, Some(Duration::from_secs(5)))
+        .unwrap()
+        .await;
+    assert_eq!(WaitResult::Signaled, result);
+}
+
+#[tokio::test]
+async fn comport_test_event_select_all() {
+    let abort = Event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let ready = Event::anonymous(EventReset::Automatic, EventInitialState::Unset).unwrap();
+    let events = [abort, ready];
+
+    // Make sure the index of the signaled event is reported
+    let select = event::select_all(&events).unwrap();
+    events[1].set().unwrap();
+    assert_eq!(Ok(1), select.await);
+
+    // Make sure the first signaled event wins, and a later auto-reset signal is not lost
+    let select = event::select_all(&events).unwrap();
+    events[0].set().unwrap();
+    assert_eq!(Ok(0), select.await);
+    events[1].set().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    events[1].wait(Some(Duration::from_millis(10))).unwrap();
+
+    // Make sure nothing to wait for is an error
+    assert!(event::select_all(&[]).is_err());
+}
+
+#[tokio::test]
+async fn comport_test_event_cancellation_token() {
+    let parent = CancellationToken::new().unwrap();
+    let child = parent.child().unwrap();
+    let grandchild = child.child().unwrap();
+
+    // Make sure cancelling a child leaves the parent alone
+    let cancelled = grandchild.cancelled().unwrap();
+    grandchild.cancel();
+    cancelled.await;
+    assert!(grandchild.is_cancelled());
+    assert!(!child.is_cancelled());
+    assert!(!parent.is_cancelled());
+
+    // Make sure cancelling the parent cancels the children, even ones created afterwards
+    let cancelled = child.cancelled().unwrap();
+    parent.cancel();
+    cancelled.await;
+    assert!(child.is_cancelled());
+    assert!(parent.child().unwrap().is_cancelled());
+    parent.cancelled().unwrap().await;
+}
+
+#[tokio::test]
+async fn comport_test_event_oneshot_clone() {
+    let (sender, receiver) = event::oneshot().unwrap();
+    let early = receiver.clone();
+
+    // Make sure every clone sees the one signal, including clones made after the signal
+    sender.set().unwrap();
+    assert_eq!(WaitResult::Signaled, receiver.await);
+    assert_eq!(WaitResult::Signaled, early.clone().await);
+    assert_eq!(WaitResult::Signaled, early.await);
+
+    // Make sure clones waiting in different tasks are all woken by the one signal
+    let (sender, receiver) = event::oneshot().unwrap();
+    let first = tokio::spawn(receiver.clone());
+    let second = tokio::spawn(receiver);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    sender.set().unwrap();
+    assert_eq!(WaitResult::Signaled, first.await.unwrap());
+    assert_eq!(WaitResult::Signaled, second.await.unwrap());
+}
+
+#[tokio::test]
+async fn comport_test_event_deadline() {
+    // Make sure the deadline resolves once the duration elapses
+    let start = std::time::Instant::now();
+    event::sleep(Duration::from_millis(20)).unwrap().await;
+    assert!(start.elapsed() >= Duration::from_millis(15));
+
+    // Make sure a moved deadline resolves at the new due time, even after it passed
+    let mut deadline = event::Deadline::new(Duration::from_millis(10)).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    let start = std::time::Instant::now();
+    deadline.reset(Duration::from_millis(50)).unwrap();
+    (&mut deadline).await;
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
+
+#[test]
+fn comport_test_event_oneshot_recycled() {
+    // Make sure a recycled event is handed out reset
+    for _ in 0..8 {
+        let (sender, receiver) = event::oneshot().unwrap();
+        assert!(!receiver.is_set());
+        sender.set().unwrap();
+        assert!(receiver.is_set());
+    }
+}
+
+#[tokio::test]
+async fn comport_test_event_oneshot_local() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Make sure a local oneshot drives an unplugged future without a kernel event
+    let (sender, receiver) = event::oneshot_local();
+    let mut unplugged = crate::prelude::Unplugged::Waiting {
+        inner: receiver.clone(),
+    };
+    assert!(unplugged.poll_unpin(&mut cx).is_pending());
+    assert!(!sender.is_set());
+    sender.set().unwrap();
+    assert!(receiver.is_set());
+    assert_eq!(WaitResult::Signaled, unplugged.await);
+    assert_eq!(WaitResult::Signaled, receiver.await);
+}
+
+#[tokio::test]
+async fn comport_test_event_oneshot_with_deadline() {
+    // Make sure the deadline elapses when the signal never comes
+    let (_sender, receiver) = event::oneshot().unwrap();
+    let result = receiver
+        .with_deadline(Duration::from_millis(10))
+        .unwrap()
+        .await;
+    assert_eq!(Err(WaitError::Timeout), result);
+
+    // Make sure the signal wins before the deadline
+    let (sender, receiver) = event::oneshot().unwrap();
+    let waiting = receiver.with_deadline(Duration::from_secs(5)).unwrap();
+    sender.set().unwrap();
+    assert_eq!(Ok(()), waiting.await);
+
+    // Make sure a local signal is mapped like a kernel one, and an elapsed deadline is not Ok
+    let (sender, receiver) = event::oneshot_local();
+    let waiting = receiver.with_deadline(Duration::from_secs(5)).unwrap();
+    sender.set().unwrap();
+    assert_eq!(Ok(()), waiting.await);
+    let (_sender, receiver) = event::oneshot_local();
+    let waiting = receiver.with_deadline(Duration::from_millis(10)).unwrap();
+    assert_eq!(Err(WaitError::Timeout), waiting.await);
+}
+
+#[test]
+fn comport_test_event_wait_result() {
+    // Make sure a failed wait is not mistaken for a signal
+    assert_eq!(Ok(()), WaitResult::Signaled.signaled());
+    assert_eq!(Err(WaitError::Abandoned), WaitResult::Abandoned.signaled());
+    assert_eq!(Err(WaitError::Cancelled), WaitResult::Cancelled.signaled());
+    assert_eq!(Err(WaitError::Timeout), WaitResult::TimedOut.signaled());
+    let result = WaitResult::Failed(0xFFFFFFFF);
+    assert_eq!(Err(WaitError::Failed(0xFFFFFFFF)), result.signaled());
+}
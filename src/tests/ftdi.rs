@@ -0,0 +1,24 @@
+//! ftdi
+
+use crate::ftdi::FtdiParams;
+use std::time::Duration;
+
+#[test]
+fn comport_test_ftdi_params() {
+    // Make sure parameters within the driver's range are accepted
+    assert!(FtdiParams::new().validate().is_ok());
+    let params = FtdiParams::new()
+        .with_latency_timer(Duration::from_millis(1))
+        .with_transfer_sizes(64, 65536);
+    assert!(params.validate().is_ok());
+
+    // Make sure parameters the driver would reject are refused
+    let params = FtdiParams::new().with_latency_timer(Duration::ZERO);
+    assert!(params.validate().is_err());
+    let params = FtdiParams::new().with_latency_timer(Duration::from_millis(256));
+    assert!(params.validate().is_err());
+    let params = FtdiParams::new().with_transfer_sizes(100, 4096);
+    assert!(params.validate().is_err());
+    let params = FtdiParams::new().with_transfer_sizes(4096, 65600);
+    assert!(params.validate().is_err());
+}
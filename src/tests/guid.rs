@@ -0,0 +1,13 @@
+use crate::{guid, guid::Guid};
+
+#[test]
+fn comport_test_guid_display() {
+    let ports = Guid::from(guid!(
+        0x4d36e978, 0xe325, 0x11ce, 0xbf, 0xc1, 0x08, 0x00, 0x2b, 0xe1, 0x03, 0x18
+    ));
+    assert_eq!("{4D36E978-E325-11CE-BFC1-08002BE10318}", ports.to_string());
+    assert_eq!(
+        ports,
+        Guid::new("4D36E978-E325-11CE-BFC1-08002BE10318").unwrap()
+    );
+}
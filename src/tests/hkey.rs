@@ -11,3 +11,230 @@ fn comport_test_hkey_parse() {
     assert_eq!("2fe3", caps[0]);
     assert_eq!("0002", caps[1]);
 }
+
+#[test]
+fn comport_test_hkey_device_instance() {
+    let iface = r#"\\?\USB#VID_2FE3&PID_0100#123#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let instance = crate::hkey::device_instance(iface).unwrap();
+    assert_eq!(r#"USB\VID_2FE3&PID_0100\123"#, instance);
+    assert_eq!(None, crate::hkey::device_instance("COM3"));
+}
+
+#[test]
+fn comport_test_hkey_serial() {
+    use crate::hkey::{parse_serial, PortMeta};
+    let usb = r#"\\?\usb#vid_0483&pid_5740#205c3594#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let ftdi =
+        r#"\\?\ftdibus#vid_0403+pid_6001+a12345a#0000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let composite =
+        r#"\\?\usb#vid_2fe3&pid_0002&mi_00#7&123456&0&0000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    assert_eq!(Some("205c3594".to_string()), parse_serial(usb));
+    assert_eq!(Some("a12345a".to_string()), parse_serial(ftdi));
+    assert_eq!(None, parse_serial(composite));
+    let parent = r#"USB\VID_2FE3&PID_0002\A12345"#;
+    assert_eq!(Some("A12345".to_string()), parse_serial(parent));
+    let meta = PortMeta::parse_registry(usb).unwrap();
+    assert_eq!(Some("205c3594"), meta.serial());
+    // The serial number does not matter when ids are matched
+    assert_eq!(PortMeta::from(("0483", "5740")), meta);
+}
+
+#[test]
+fn comport_test_hkey_interface() {
+    use crate::hkey::{parse_interface, PortMeta};
+    let composite =
+        r#"\\?\usb#vid_2fe3&pid_0002&mi_02#7&123456&0&0002#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let single = r#"\\?\usb#vid_0483&pid_5740#205c3594#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    assert_eq!(Some(2), parse_interface(composite));
+    assert_eq!(
+        Some(0x0a),
+        parse_interface(r#"\\?\USB#VID_2FE3&PID_0002&MI_0A#1"#)
+    );
+    assert_eq!(None, parse_interface(single));
+    assert_eq!(
+        Some(2),
+        PortMeta::parse_registry(composite).unwrap().interface()
+    );
+}
+
+#[test]
+fn comport_test_hkey_numeric_ids() {
+    use crate::hkey::PortMeta;
+    let meta = PortMeta::parse_registry(
+        r#"\\?\usb#vid_0403&pid_6001#a12345#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#,
+    )
+    .unwrap();
+    assert_eq!(Some(0x0403), meta.vid_u16());
+    assert_eq!(Some(0x6001), meta.pid_u16());
+    assert_eq!(PortMeta::from((0x0403u16, 0x6001u16)), meta);
+    assert_eq!(PortMeta::from(("0403", "6001")), meta);
+    assert_eq!("0403", PortMeta::from((0x403u16, 0x6001u16)).vendor);
+
+    // Make sure the numeric ids follow the public fields
+    let mut meta = meta;
+    meta.vendor = "2fe3".into();
+    meta.product = "zz".into();
+    assert_eq!(Some(0x2fe3), meta.vid_u16());
+    assert_eq!(None, meta.pid_u16());
+}
+
+#[test]
+fn comport_test_hkey_registry_data() {
+    use crate::hkey::RegistryData;
+    use windows_sys::Win32::System::Registry::{REG_BINARY, REG_DWORD, REG_QWORD};
+    let qword = RegistryData::from_data(REG_QWORD, 0x0102030405060708u64.to_le_bytes().to_vec());
+    assert_eq!(0x0102030405060708, qword.try_into::<u64>().unwrap());
+    let dword = RegistryData::from_data(REG_DWORD, 7u32.to_le_bytes().to_vec());
+    let dword = dword.try_into::<u64>().unwrap_err().into_registry_data();
+    assert_eq!(7, dword.try_into::<u32>().unwrap());
+    let binary = RegistryData::from_data(REG_BINARY, vec![1, 2, 3]);
+    assert_eq!(vec![1, 2, 3], binary.try_into_bytes().unwrap());
+}
+
+#[test]
+fn comport_test_hkey_registry_data_from() {
+    use crate::hkey::RegistryData;
+    use std::ffi::OsString;
+    assert_eq!(7, RegistryData::from(7u32).try_into::<u32>().unwrap());
+    assert_eq!(7, RegistryData::from(7u64).try_into::<u64>().unwrap());
+    let port = RegistryData::from("COM42");
+    // The data includes the terminating null
+    assert_eq!(12, port.data.len());
+    assert_eq!("COM42", port.try_into::<OsString>().unwrap());
+    let binary = RegistryData::from(vec![1, 2]);
+    assert_eq!(vec![1, 2], binary.try_into::<Vec<u8>>().unwrap());
+}
+
+#[test]
+fn comport_test_hkey_open_options() {
+    use crate::hkey::{OpenOptions, RegistryView};
+    use windows_sys::Win32::System::Registry::{KEY_READ, KEY_SET_VALUE, KEY_WOW64_64KEY};
+    assert_eq!(KEY_READ, OpenOptions::new().access());
+    let options = OpenOptions::new()
+        .with_access(KEY_READ | KEY_SET_VALUE)
+        .with_view(RegistryView::Registry64);
+    assert_eq!(KEY_READ | KEY_SET_VALUE | KEY_WOW64_64KEY, options.access());
+}
+
+#[test]
+fn comport_test_hkey_win32_error() {
+    use crate::hkey::{RegistryError, Win32Error};
+    use windows_sys::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+    let error = Win32Error(ERROR_FILE_NOT_FOUND);
+    let io = std::io::Error::from(error);
+    assert_eq!(Some(ERROR_FILE_NOT_FOUND as i32), io.raw_os_error());
+    assert_eq!(io.to_string(), error.to_string());
+    assert!(matches!(
+        RegistryError::from(error),
+        RegistryError::Win32(Win32Error(ERROR_FILE_NOT_FOUND))
+    ));
+}
+
+#[test]
+fn comport_test_hkey_port_meta_str() {
+    use crate::hkey::PortMeta;
+    let meta: PortMeta = "2FE3:0100".parse().unwrap();
+    assert_eq!(PortMeta::from(("2fe3", "0100")), meta);
+    assert_eq!(Some(0x2fe3), meta.vid_u16());
+    assert_eq!("2FE3:0100", meta.to_string());
+    assert_eq!(meta, meta.to_string().parse().unwrap());
+    assert!("2fe3".parse::<PortMeta>().is_err());
+    assert!("2fe3:01".parse::<PortMeta>().is_err());
+    assert!("+fe3:0100".parse::<PortMeta>().is_err());
+}
+
+#[test]
+fn comport_test_hkey_port_meta_new() {
+    use crate::hkey::PortMeta;
+    let meta = PortMeta::new("2FE3", "0100").with_serial("A12345");
+    assert_eq!("2fe3", meta.vendor);
+    assert_eq!("0100", meta.product);
+    assert_eq!(Some("A12345"), meta.serial());
+    // Make sure the serial number does not change the ids a port matches
+    assert_eq!(PortMeta::from(("2fe3", "0100")), meta);
+}
+
+#[test]
+fn comport_test_hkey_port_meta_hash() {
+    use crate::hkey::PortMeta;
+    use std::collections::HashSet;
+    let ids: HashSet<PortMeta> = [PortMeta::from(("2FE3", "0100"))].into_iter().collect();
+    let usb = r#"\\?\usb#vid_2fe3&pid_0100#205c3594#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    assert!(ids.contains(&PortMeta::parse_registry(usb).unwrap()));
+    // The vendor and product are public, so they may be set in any case
+    let mut upper = PortMeta::from(("2fe3", "0100"));
+    upper.vendor = "2FE3".to_string();
+    assert!(ids.contains(&upper));
+    assert!(!ids.contains(&PortMeta::from(("0403", "6001"))));
+}
+
+#[test]
+fn comport_test_hkey_bluetooth() {
+    use crate::hkey::{PortKind, PortMeta};
+    let outgoing = r#"\\?\BTHENUM#{00001101-0000-1000-8000-00805F9B34FB}_LOCALMFG&0002#7&2A0A3E8F&0&001122AABBCC_C00000000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let meta = PortMeta::parse_registry(outgoing).unwrap();
+    assert_eq!(PortKind::Bluetooth, meta.kind());
+    assert_eq!(Some("00:11:22:AA:BB:CC"), meta.bluetooth_address());
+    let incoming = r#"\\?\bthenum#{00001101-0000-1000-8000-00805f9b34fb}_localmfg&0000#7&2a0a3e8f&0&000000000000_00000000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let meta = PortMeta::parse_registry(incoming).unwrap();
+    assert_eq!(PortKind::Bluetooth, meta.kind());
+    assert_eq!(None, meta.bluetooth_address());
+    // Make sure ports without ids are told apart by their kind and address
+    let other = outgoing.replace("001122AABBCC", "001122DDEEFF");
+    let virtual_port = r#"\\?\ROOT#PORTS#0000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let outgoing = PortMeta::parse_registry(outgoing).unwrap();
+    assert_eq!(outgoing, outgoing.clone());
+    assert_ne!(outgoing, PortMeta::parse_registry(&other).unwrap());
+    assert_ne!(outgoing, meta);
+    assert_ne!(meta, PortMeta::parse_registry(virtual_port).unwrap());
+    let usb = r#"\\?\usb#vid_2fe3&pid_0100#123#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    assert_eq!(
+        PortKind::UsbCdcAcm,
+        PortMeta::parse_registry(usb).unwrap().kind()
+    );
+}
+
+#[test]
+fn comport_test_hkey_port_kind() {
+    use crate::hkey::{PortKind, PortMeta, UsbVendor};
+    let kind = |s: &str| PortMeta::parse_registry(s).map(|meta| meta.kind());
+    let ftdi =
+        r#"\\?\ftdibus#vid_0403+pid_6001+a12345a#0000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let cp210x = r#"\\?\usb#vid_10c4&pid_ea60#0001#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let onboard = r#"\\?\acpi#pnp0501#0#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    let virt = r#"\\?\com0com#port#cnca0#{86e0d1e0-8089-11d0-9ce4-08003e301f73}"#;
+    assert_eq!(Some(PortKind::UsbVendor(UsbVendor::Ftdi)), kind(ftdi));
+    assert_eq!(Some(PortKind::UsbVendor(UsbVendor::Cp210x)), kind(cp210x));
+    assert_eq!(Some(PortKind::Onboard), kind(onboard));
+    assert_eq!(Some(PortKind::Virtual), kind(virt));
+    // The bound driver is preferred over the vendor id
+    let usbser = PortKind::classify("USB", Some("usbser"), Some(0x0403));
+    assert_eq!(PortKind::UsbCdcAcm, usbser);
+    let prolific = PortKind::classify("USB", Some("Ser2pl"), Some(0x067b));
+    assert_eq!(PortKind::UsbVendor(UsbVendor::Other), prolific);
+}
+
+#[test]
+fn comport_test_hkey_sorted() {
+    use crate::hkey::{sorted, PortMeta};
+    use std::{collections::HashMap, ffi::OsString};
+    let ports: HashMap<_, _> = ["COM10", "CNCA0", "COM3", "COM9"]
+        .into_iter()
+        .map(|port| (OsString::from(port), PortMeta::from(("2fe3", "0100"))))
+        .collect();
+    let ports: Vec<_> = sorted(ports).into_iter().map(|(port, _)| port).collect();
+    assert_eq!(vec!["COM3", "COM9", "COM10", "CNCA0"], ports);
+}
+
+#[test]
+fn comport_test_hkey_power_state() {
+    use crate::hkey::{PortMeta, PowerState};
+    assert_eq!(PowerState::D0, PowerState::from_raw(1));
+    assert_eq!(PowerState::D3, PowerState::from_raw(4));
+    assert_eq!(PowerState::Unspecified, PowerState::from_raw(0));
+    assert_eq!(PowerState::Unspecified, PowerState::from_raw(6));
+    // A port which was not described has no problem
+    let meta = PortMeta::from(("2fe3", "0100"));
+    assert_eq!(None, meta.problem());
+    assert_eq!(None, meta.power_state());
+}
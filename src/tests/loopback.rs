@@ -0,0 +1,105 @@
+//! loopback
+
+use crate::loopback::{verify_echo, LoopbackError};
+use futures::{AsyncRead, AsyncWrite};
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// A looped back line which optionally corrupts a byte, or is not looped back at all
+struct MockLine {
+    echo: VecDeque<u8>,
+    corrupt: Option<usize>,
+    looped: bool,
+    written: usize,
+}
+
+impl AsyncRead for MockLine {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.echo.is_empty() {
+            // Nothing ever arrives, the deadline must fire
+            true => Poll::Pending,
+            false => {
+                let n = buf.len().min(self.echo.len());
+                for (idx, byte) in self.echo.drain(..n).enumerate() {
+                    buf[idx] = byte;
+                }
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MockLine {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.looped {
+            return Poll::Ready(Ok(buf.len()));
+        }
+        for byte in buf {
+            let byte = match self.corrupt == Some(self.written) {
+                true => !byte,
+                false => *byte,
+            };
+            self.echo.push_back(byte);
+            self.written += 1;
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl MockLine {
+    fn new(corrupt: Option<usize>, looped: bool) -> Self {
+        Self {
+            echo: VecDeque::new(),
+            corrupt,
+            looped,
+            written: 0,
+        }
+    }
+}
+
+#[tokio::test]
+async fn comport_test_loopback() {
+    let timeout = Duration::from_millis(20);
+
+    // Make sure a faithful echo passes
+    let mut line = MockLine::new(None, true);
+    verify_echo(&mut line, b"pattern", timeout).await.unwrap();
+
+    // Make sure the first corrupted byte is reported
+    let mut line = MockLine::new(Some(3), true);
+    match verify_echo(&mut line, b"pattern", timeout).await {
+        Err(LoopbackError::Mismatch { offset, echo }) => {
+            assert_eq!(3, offset);
+            assert_eq!(7, echo.len());
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    // Make sure a missing echo times out
+    let mut line = MockLine::new(None, false);
+    match verify_echo(&mut line, b"pattern", timeout).await {
+        Err(LoopbackError::Io(error)) => assert_eq!(io::ErrorKind::TimedOut, error.kind()),
+        result => panic!("unexpected result {result:?}"),
+    }
+}
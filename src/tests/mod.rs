@@ -1,4 +1,26 @@
+mod cfgmgr;
 mod channel;
+mod codec;
+mod comdb;
+mod comm;
+#[cfg(feature = "tokio")]
+mod compat;
+mod debounce;
+mod dedupe;
 mod event;
+mod ftdi;
+mod guid;
 mod hkey;
+mod loopback;
+mod name;
+mod pnp;
+mod port;
+mod prelude;
+mod reactor;
+mod renumerate;
+mod reopen;
+mod timeout;
+#[cfg(feature = "wmi")]
+mod wbem;
 mod wchar;
+mod wm;
@@ -0,0 +1,22 @@
+use crate::name::ComPortName;
+use std::ffi::OsString;
+
+#[test]
+fn comport_test_name_parse() {
+    let name: ComPortName = "COM3".parse().unwrap();
+    assert_eq!(3, name.number());
+    assert_eq!("COM3", name.to_string());
+    assert_eq!(name, "com3".parse().unwrap());
+    assert_eq!(name, r"\\.\COM3".parse().unwrap());
+    assert!("COM".parse::<ComPortName>().is_err());
+    assert!("COM+3".parse::<ComPortName>().is_err());
+    assert!("CNCA0".parse::<ComPortName>().is_err());
+}
+
+#[test]
+fn comport_test_name_device_path() {
+    let name = ComPortName::try_from(OsString::from("COM10")).unwrap();
+    assert_eq!(OsString::from(r"\\.\COM10"), name.device_path());
+    assert_eq!(OsString::from("COM10"), OsString::from(name));
+    assert!(ComPortName::new(9) < name);
+}
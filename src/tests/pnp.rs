@@ -0,0 +1,16 @@
+//! pnp
+use crate::{hkey::PowerState, pnp::parse_power_data};
+
+#[test]
+fn comport_test_pnp_power_data() {
+    // CM_POWER_DATA starts with its size, then the most recent state
+    let buffer: &[u16] = &[56, 0, 3, 0, 0, 0];
+    assert_eq!(Some(PowerState::D2), parse_power_data(buffer));
+    let buffer: &[u16] = &[56, 0, 1, 0];
+    assert_eq!(Some(PowerState::D0), parse_power_data(buffer));
+    // A state out of range in the high word
+    let buffer: &[u16] = &[56, 0, 1, 1];
+    assert_eq!(Some(PowerState::Unspecified), parse_power_data(buffer));
+    // Too short to hold the state
+    assert_eq!(None, parse_power_data(&[56, 0, 1]));
+}
@@ -0,0 +1,274 @@
+//! port
+use crate::{
+    comm::dcb,
+    event::CancellationToken,
+    port::{
+        Backoff, ComPort, DataBits, LineEvent, LineEvents, OpenError, Parity, PortConfig,
+        PortHandle, StopBits,
+    },
+    wchar::to_wide,
+};
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    time::Duration,
+};
+use windows_sys::Win32::{
+    Devices::Communication::{DCB, EVENPARITY, NOPARITY, ONESTOPBIT, TWOSTOPBITS},
+    Foundation::{
+        BOOL, ERROR_PIPE_CONNECTED, FALSE, GENERIC_READ, GENERIC_WRITE, HANDLE,
+        INVALID_HANDLE_VALUE,
+    },
+    Storage::FileSystem::{
+        CreateFileW, ReadFile, FILE_FLAG_OVERLAPPED, OPEN_EXISTING, PIPE_ACCESS_DUPLEX,
+    },
+    System::{
+        Pipes::{ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, PIPE_TYPE_BYTE, PIPE_WAIT},
+        IO::OVERLAPPED,
+    },
+};
+
+/// A pipe standing in for a port. The client end is opened for overlapped I/O like a port. The
+/// pipe buffers a single byte, so writes to the client stay in flight until the server end is read
+pub(crate) fn pipe(name: &str) -> (File, OwnedHandle) {
+    let name = format!("\\\\.\\pipe\\comport-test-{}-{name}", std::process::id());
+    let wide = to_wide(&name);
+    let server = unsafe {
+        CreateNamedPipeW(
+            wide.as_ptr(),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_WAIT,
+            1,
+            1,
+            1,
+            0,
+            std::ptr::null(),
+        )
+    };
+    assert_ne!(
+        INVALID_HANDLE_VALUE,
+        server,
+        "{}",
+        io::Error::last_os_error()
+    );
+    let server = unsafe { File::from_raw_handle(server as _) };
+    let client = unsafe {
+        CreateFileW(
+            wide.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            0,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            FILE_FLAG_OVERLAPPED,
+            0,
+        )
+    };
+    assert_ne!(
+        INVALID_HANDLE_VALUE,
+        client,
+        "{}",
+        io::Error::last_os_error()
+    );
+    let client = unsafe { OwnedHandle::from_raw_handle(client as _) };
+    if unsafe { ConnectNamedPipe(server.as_raw_handle() as _, std::ptr::null_mut()) } == FALSE {
+        let error = io::Error::last_os_error();
+        assert_eq!(Some(ERROR_PIPE_CONNECTED as i32), error.raw_os_error());
+    }
+    (server, client)
+}
+
+#[test]
+fn comport_test_port_config() {
+    let mut state: DCB = unsafe { std::mem::zeroed() };
+
+    // Make sure the defaults are 115200 8N1
+    PortConfig::new().apply(&mut state);
+    assert_eq!(115200, state.BaudRate);
+    assert_eq!(8, state.ByteSize);
+    assert_eq!(NOPARITY, state.Parity);
+    assert_eq!(ONESTOPBIT, state.StopBits);
+    assert_eq!(dcb::BINARY, state._bitfield & dcb::BINARY);
+    assert_eq!(0, state._bitfield & dcb::PARITY);
+
+    // Make sure parity checking is enabled with a parity scheme
+    PortConfig::new()
+        .with_baud_rate(9600)
+        .with_parity(Parity::Even)
+        .with_data_bits(DataBits::Seven)
+        .with_stop_bits(StopBits::Two)
+        .apply(&mut state);
+    assert_eq!(9600, state.BaudRate);
+    assert_eq!(7, state.ByteSize);
+    assert_eq!(EVENPARITY, state.Parity);
+    assert_eq!(TWOSTOPBITS, state.StopBits);
+    assert_eq!(dcb::PARITY, state._bitfield & dcb::PARITY);
+
+    // Make sure parity checking is disabled again
+    PortConfig::new().apply(&mut state);
+    assert_eq!(0, state._bitfield & dcb::PARITY);
+}
+
+#[test]
+fn comport_test_port_backoff() {
+    // Make sure the delay doubles and saturates at the max
+    let backoff = Backoff::new()
+        .with_initial(Duration::from_millis(10))
+        .with_max(Duration::from_millis(50));
+    assert_eq!(Duration::from_millis(10), backoff.delay(1));
+    assert_eq!(Duration::from_millis(20), backoff.delay(2));
+    assert_eq!(Duration::from_millis(40), backoff.delay(3));
+    assert_eq!(Duration::from_millis(50), backoff.delay(4));
+    assert_eq!(Duration::from_millis(50), backoff.delay(usize::MAX));
+
+    // Make sure a busy port survives conversion into an io error
+    let error = io::Error::from(OpenError::PortBusy("COM4".into()));
+    assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+    let inner = error.into_inner().unwrap().downcast::<OpenError>().unwrap();
+    assert!(matches!(*inner, OpenError::PortBusy(port) if port == "COM4"));
+}
+
+#[test]
+fn comport_test_port_write_in_flight() {
+    let (mut server, client) = pipe("write-in-flight");
+    let mut port = ComPort::from_owned_handle("PIPE", client);
+    let first = vec![1u8; 0x10000];
+    let second = vec![2u8; 16];
+    futures::executor::block_on(async {
+        port.write_all(&first).await.unwrap();
+        // Make sure the first write is in flight when the second write wakes the io thread
+        std::thread::sleep(Duration::from_millis(100));
+        port.write_all(&second).await.unwrap();
+    });
+
+    // Make sure the cancelled write resumed, and the port still writes afterwards
+    let mut received = vec![0u8; first.len() + second.len()];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(first[..], received[..first.len()]);
+    assert_eq!(second[..], received[first.len()..]);
+    futures::executor::block_on(port.write_all(&second)).unwrap();
+    let mut received = vec![0u8; second.len()];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(second, received);
+}
+
+#[test]
+fn comport_test_port_backpressure() {
+    let (mut server, client) = pipe("backpressure");
+    let mut port = ComPort::from_owned_handle("PIPE", client);
+    let sent: Vec<u8> = (0..0x4000u32).map(|n| n as u8).collect();
+    let data = sent.clone();
+    let writer = std::thread::spawn(move || {
+        for chunk in data.chunks(64) {
+            server.write_all(chunk).unwrap();
+        }
+        server
+    });
+
+    // Make sure a reader which falls behind receives every byte
+    std::thread::sleep(Duration::from_millis(200));
+    let mut received = vec![0u8; sent.len()];
+    futures::executor::block_on(port.read_exact(&mut received)).unwrap();
+    assert_eq!(sent, received);
+    let _server = writer.join().unwrap();
+    assert_eq!(sent.len() as u64, port.stats().bytes_read());
+}
+
+#[test]
+fn comport_test_port_until_cancelled() {
+    let (_server, client) = pipe("until-cancelled");
+    let token = CancellationToken::new().unwrap();
+    let mut port = ComPort::from_owned_handle("PIPE", client)
+        .until_cancelled(&token)
+        .unwrap();
+    let cancel = token.clone();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        cancel.cancel();
+    });
+
+    // Make sure a read waiting on a silent device is interrupted by the cancel
+    let mut buf = [0u8; 8];
+    let error = futures::executor::block_on(port.read(&mut buf)).unwrap_err();
+    assert_eq!(io::ErrorKind::Interrupted, error.kind());
+    canceller.join().unwrap();
+
+    // Make sure the port can not be written once cancelled
+    let error = futures::executor::block_on(port.write(&[1])).unwrap_err();
+    assert_eq!(io::ErrorKind::Interrupted, error.kind());
+}
+
+#[test]
+fn comport_test_port_try_into_raw_handle() {
+    let (_server, client) = pipe("try-into-raw-handle");
+    let raw = client.as_raw_handle();
+    let port = ComPort::from_owned_handle("PIPE", client);
+    // Make sure the original handle is released once the io thread stopped using it
+    assert_eq!(raw, port.try_into_raw_handle().unwrap());
+    drop(unsafe { OwnedHandle::from_raw_handle(raw) });
+
+    // Make sure a handle another owner still uses is not released
+    let (_server, client) = pipe("try-into-raw-handle-shared");
+    let handle = PortHandle::from(client);
+    let other = handle.clone();
+    let error = handle.try_into_raw_handle().unwrap_err();
+    assert_eq!(io::ErrorKind::ResourceBusy, error.kind());
+    drop(other);
+}
+
+#[test]
+fn comport_test_port_drop_joins() {
+    let (mut server, client) = pipe("drop-joins");
+    let port = ComPort::from_owned_handle("PIPE", client);
+    std::thread::sleep(Duration::from_millis(50));
+
+    // Make sure dropping the port stops its io thread, which closes the last client handle
+    drop(port);
+    assert!(server.write_all(&[1]).is_err());
+}
+
+/// Read the line event mask from a pipe, so the wait stays pending until the server end writes
+unsafe extern "system" fn read_mask(
+    handle: HANDLE,
+    mask: *mut u32,
+    overlapped: *mut OVERLAPPED,
+) -> BOOL {
+    ReadFile(handle, mask as _, 4, std::ptr::null_mut(), overlapped)
+}
+
+#[test]
+fn comport_test_port_line_events_cancelled() {
+    let (mut server, client) = pipe("line-events-cancelled");
+    let handle = PortHandle::from(client);
+    let mut events = LineEvents::with_wait(handle.clone(), read_mask).unwrap();
+
+    // Make sure a completed wait reports its events
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+    assert!(events.poll_next_unpin(&mut cx).is_pending());
+    server
+        .write_all(&LineEvent::CTS.bits().to_le_bytes())
+        .unwrap();
+    let received = futures::executor::block_on(events.next()).unwrap().unwrap();
+    assert_eq!(LineEvent::CTS, received);
+
+    // Make sure a pending wait is cancelled before its state is freed, so the kernel does not
+    // complete it with the bytes written afterwards
+    assert!(events.poll_next_unpin(&mut cx).is_pending());
+    drop(events);
+    server.write_all(&[1]).unwrap();
+    let mut available = 0;
+    let result = unsafe {
+        PeekNamedPipe(
+            handle.as_raw_handle() as _,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            &mut available,
+            std::ptr::null_mut(),
+        )
+    };
+    assert_ne!(FALSE, result);
+    assert_eq!(1, available);
+}
@@ -0,0 +1,139 @@
+//! prelude
+
+use crate::{prelude::*, PlugEvent, PortMeta};
+use futures::{channel::mpsc, future, SinkExt, StreamExt};
+use std::{
+    ffi::OsString,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Counts the tasks which were dropped
+struct Guard(Arc<AtomicUsize>);
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+async fn eventually(count: &AtomicUsize, expect: usize) {
+    for _ in 0..100 {
+        if count.load(Ordering::SeqCst) == expect {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("expected {expect} got {}", count.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn comport_test_prelude_supervise() {
+    let (mut tx, rx) = mpsc::unbounded();
+    let started = Arc::new(AtomicUsize::new(0));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let (s, d) = (Arc::clone(&started), Arc::clone(&dropped));
+    let supervisor = rx.supervise(vec![("0403", "6001")], move |port: TrackedPort| {
+        assert_eq!(OsString::from("COM4"), port.port);
+        s.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard(Arc::clone(&d));
+        async move {
+            let _guard = guard;
+            future::pending::<()>().await
+        }
+    });
+    let driver = async move {
+        // Make sure untracked devices are ignored and tracked devices start a task
+        let ids = PortMeta::from(("0403", "6001"));
+        let other = PortMeta::from(("1234", "5678"));
+        tx.send(Ok(PlugEvent::Arrival("COM3".into(), other)))
+            .await
+            .unwrap();
+        tx.send(Ok(PlugEvent::Arrival("COM4".into(), ids.clone())))
+            .await
+            .unwrap();
+        eventually(&started, 1).await;
+        assert_eq!(0, dropped.load(Ordering::SeqCst));
+
+        // Make sure the task is cancelled when the port is unplugged
+        let port = OsString::from("COM4");
+        tx.send(Ok(PlugEvent::RemoveComplete(port.clone())))
+            .await
+            .unwrap();
+        eventually(&dropped, 1).await;
+
+        // Make sure remaining tasks are cancelled when the stream ends
+        tx.send(Ok(PlugEvent::Arrival(port, ids))).await.unwrap();
+        eventually(&started, 2).await;
+        dropped
+    };
+    let (result, dropped) = futures::join!(supervisor, driver);
+    result.unwrap();
+    assert_eq!(2, dropped.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn comport_test_prelude_track_serials() {
+    let (mut tx, rx) = mpsc::unbounded();
+    let mut tracking = rx.track_serials(vec!["A12345", "b67890"]);
+    let usb = |serial: &str| {
+        let interface = format!(r#"\\?\usb#vid_0403&pid_6001#{serial}#{{86e0d1e0-8089-11d0}}"#);
+        PortMeta::parse_registry(&interface).unwrap()
+    };
+    let (other, unit) = (usb("c11111"), usb("a12345"));
+    tx.send(Ok(PlugEvent::Arrival("COM3".into(), other)))
+        .await
+        .unwrap();
+    tx.send(Ok(PlugEvent::Arrival("COM4".into(), unit)))
+        .await
+        .unwrap();
+    drop(tx);
+
+    // Make sure only the unit under test is tracked, ignoring the case of the serial number
+    let tracked = tracking.next().await.unwrap().unwrap();
+    assert_eq!(OsString::from("COM4"), tracked.port);
+    assert_eq!(Some("a12345"), tracked.ids.serial());
+    assert!(tracking.next().await.is_none());
+}
+
+#[tokio::test]
+async fn comport_test_prelude_supervise_rescan() {
+    let (mut tx, rx) = mpsc::unbounded();
+    let started = Arc::new(AtomicUsize::new(0));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let (s, d) = (Arc::clone(&started), Arc::clone(&dropped));
+    let supervisor = rx.supervise(vec![("0403", "6001")], move |_: TrackedPort| {
+        s.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard(Arc::clone(&d));
+        async move {
+            let _guard = guard;
+            future::pending::<()>().await
+        }
+    });
+    let driver = async move {
+        let ids = PortMeta::from(("0403", "6001"));
+        tx.send(Ok(PlugEvent::Arrival("COM4".into(), ids.clone())))
+            .await
+            .unwrap();
+        eventually(&started, 1).await;
+
+        // Make sure a rescan does not start a second task for the port
+        tx.send(Ok(PlugEvent::Arrival("COM4".into(), ids)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(1, started.load(Ordering::SeqCst));
+
+        // Make sure the task of the port is still cancelled when the port is unplugged
+        tx.send(Ok(PlugEvent::RemoveComplete("COM4".into())))
+            .await
+            .unwrap();
+        eventually(&dropped, 1).await;
+        dropped
+    };
+    let (result, dropped) = futures::join!(supervisor, driver);
+    result.unwrap();
+    assert_eq!(1, dropped.load(Ordering::SeqCst));
+}
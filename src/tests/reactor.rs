@@ -0,0 +1,54 @@
+//! reactor
+use super::port::pipe;
+use crate::reactor::Reactor;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use std::{
+    io::{Read, Write},
+    os::windows::io::AsRawHandle,
+    time::Duration,
+};
+use windows_sys::Win32::System::IO::CancelIoEx;
+
+#[test]
+fn comport_test_reactor_write_aborted() {
+    let reactor = Reactor::new(1).unwrap();
+    let (mut server, client) = pipe("reactor-write-aborted");
+    let mut port = reactor.from_owned_handle("PIPE", client).unwrap();
+    let bytes = vec![1u8; 0x10000];
+    futures::executor::block_on(port.write_all(&bytes)).unwrap();
+
+    // Make sure a write cancelled while in flight resumes, like after ComPort::abort_io
+    std::thread::sleep(Duration::from_millis(100));
+    unsafe { CancelIoEx(port.as_raw_handle() as _, std::ptr::null()) };
+    let mut received = vec![0u8; bytes.len()];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(bytes, received);
+
+    // Make sure the port still writes afterwards
+    futures::executor::block_on(port.write_all(&[2, 3])).unwrap();
+    let mut received = [0u8; 2];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!([2, 3], received);
+}
+
+#[test]
+fn comport_test_reactor_backpressure() {
+    let reactor = Reactor::new(1).unwrap();
+    let (mut server, client) = pipe("reactor-backpressure");
+    let mut port = reactor.from_owned_handle("PIPE", client).unwrap();
+    let sent: Vec<u8> = (0..0x4000u32).map(|n| n as u8).collect();
+    let data = sent.clone();
+    let writer = std::thread::spawn(move || {
+        for chunk in data.chunks(64) {
+            server.write_all(chunk).unwrap();
+        }
+        server
+    });
+
+    // Make sure the port is not read again until the task makes room, so no byte is lost
+    std::thread::sleep(Duration::from_millis(200));
+    let mut received = vec![0u8; sent.len()];
+    futures::executor::block_on(port.read_exact(&mut received)).unwrap();
+    assert_eq!(sent, received);
+    let _server = writer.join().unwrap();
+}
@@ -0,0 +1,58 @@
+//! renumerate
+
+use crate::{
+    prelude::*,
+    renumerate::{EnumeratedPort, RenumerationEvent},
+    PlugEvent, PortMeta,
+};
+use futures::{stream, StreamExt};
+use std::{
+    ffi::OsString,
+    time::{Duration, Instant},
+};
+
+fn port(port: &str, ids: &PortMeta) -> EnumeratedPort {
+    EnumeratedPort {
+        port: OsString::from(port),
+        ids: ids.clone(),
+    }
+}
+
+#[tokio::test]
+async fn comport_test_renumerate() {
+    let app = PortMeta::from(("0483", "5740"));
+    let boot = PortMeta::from(("0483", "df11"));
+    let other = PortMeta::from(("0403", "6001"));
+    let events = vec![
+        PlugEvent::Arrival("COM7".into(), other.clone()),
+        PlugEvent::Arrival("COM4".into(), app.clone()),
+        PlugEvent::RemoveComplete("COM4".into()),
+        PlugEvent::Arrival("COM5".into(), boot.clone()),
+        PlugEvent::RemoveComplete("COM7".into()),
+        PlugEvent::RemoveComplete("COM5".into()),
+    ];
+    let window = Duration::from_millis(50);
+    let mut stream = stream::iter(events.into_iter().map(Ok))
+        .chain(stream::pending())
+        .renumeration(vec![(("0483", "5740"), ("0483", "df11"))], window);
+
+    // Make sure unpaired devices pass through and paired devices are correlated
+    let expect = vec![
+        RenumerationEvent::Arrival(port("COM7", &other)),
+        RenumerationEvent::Arrival(port("COM4", &app)),
+        RenumerationEvent::Renumerated {
+            old: port("COM4", &app),
+            new: port("COM5", &boot),
+        },
+        RenumerationEvent::Removed("COM7".into()),
+    ];
+    for event in expect {
+        assert_eq!(event, stream.next().await.unwrap().unwrap());
+    }
+
+    // Make sure a removal is reported once the window closes
+    let start = Instant::now();
+    let event = stream.next().await.unwrap().unwrap();
+    assert_eq!(RenumerationEvent::Removed("COM5".into()), event);
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
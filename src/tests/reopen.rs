@@ -0,0 +1,108 @@
+//! reopen
+use super::port::pipe;
+use crate::{
+    hkey::ScanResult,
+    port::{ComPort, PortConfig},
+    prelude::*,
+    reopen::{ReopenEvent, ReopeningPort},
+    PlugEvent, PortMeta,
+};
+use futures::{channel::mpsc, AsyncWriteExt, SinkExt, StreamExt};
+use parking_lot::Mutex;
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A port of an FTDI adapter with a serial number
+fn ftdi(serial: &str) -> PortMeta {
+    let interface = format!(r#"\\?\usb#vid_0403&pid_6001#{serial}#{{86e0d1e0-8089-11d0}}"#);
+    PortMeta::parse_registry(&interface).unwrap()
+}
+
+/// A reopening port which opens a pipe for every arrival. The server ends of the pipes are kept
+/// in the order the ports were opened
+fn reopening(
+    name: &'static str,
+) -> (
+    mpsc::UnboundedSender<ScanResult<PlugEvent>>,
+    ReopeningPort<mpsc::UnboundedReceiver<ScanResult<PlugEvent>>>,
+    Arc<Mutex<Vec<File>>>,
+) {
+    let (tx, rx) = mpsc::unbounded();
+    let servers = Arc::new(Mutex::new(Vec::new()));
+    let opened = AtomicUsize::new(0);
+    let theirs = Arc::clone(&servers);
+    let port = ReopeningPort::new(rx.track(vec![("0403", "6001")]), PortConfig::new()).with_open(
+        move |port| {
+            let n = opened.fetch_add(1, Ordering::SeqCst);
+            let (server, client) = pipe(&format!("{name}-{n}"));
+            theirs.lock().push(server);
+            Ok(ComPort::from_owned_handle(port, client))
+        },
+    );
+    (tx, port, servers)
+}
+
+#[test]
+fn comport_test_reopen_reconnect() {
+    let (mut tx, mut port, servers) = reopening("reopen-reconnect");
+    futures::executor::block_on(async {
+        // Make sure the first arrival is adopted
+        let arrival = PlugEvent::Arrival("COM4".into(), ftdi("a12345"));
+        tx.send(Ok(arrival)).await.unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Connected("COM4".into()), event);
+        port.write_all(b"one").await.unwrap();
+
+        // Make sure the port is reopened when the device arrives again on another port
+        tx.send(Ok(PlugEvent::RemoveComplete("COM4".into())))
+            .await
+            .unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Disconnected("COM4".into()), event);
+        assert!(port.get_ref().is_none());
+        let arrival = PlugEvent::Arrival("COM5".into(), ftdi("a12345"));
+        tx.send(Ok(arrival)).await.unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Reconnected("COM5".into()), event);
+        assert_eq!(Some(&OsString::from("COM5")), port.port());
+        port.write_all(b"two").await.unwrap();
+    });
+    let mut servers = servers.lock();
+    let mut received = [0u8; 3];
+    servers[0].read_exact(&mut received).unwrap();
+    assert_eq!(b"one", &received);
+    servers[1].read_exact(&mut received).unwrap();
+    assert_eq!(b"two", &received);
+}
+
+#[test]
+fn comport_test_reopen_other_serial() {
+    let (mut tx, mut port, servers) = reopening("reopen-other-serial");
+    futures::executor::block_on(async {
+        let arrival = PlugEvent::Arrival("COM4".into(), ftdi("a12345"));
+        tx.send(Ok(arrival)).await.unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Connected("COM4".into()), event);
+        tx.send(Ok(PlugEvent::RemoveComplete("COM4".into())))
+            .await
+            .unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Disconnected("COM4".into()), event);
+
+        // Make sure an identical adapter with another serial number is not adopted
+        let other = PlugEvent::Arrival("COM5".into(), ftdi("b67890"));
+        tx.send(Ok(other)).await.unwrap();
+        let arrival = PlugEvent::Arrival("COM6".into(), ftdi("a12345"));
+        tx.send(Ok(arrival)).await.unwrap();
+        let event = port.next().await.unwrap().unwrap();
+        assert_eq!(ReopenEvent::Reconnected("COM6".into()), event);
+    });
+    assert_eq!(2, servers.lock().len());
+}
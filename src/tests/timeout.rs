@@ -0,0 +1,69 @@
+//! timeout
+
+use crate::{
+    channel::{self, WakeHandle},
+    event::Deadline,
+    timeout::AsyncReadTimeoutExt,
+};
+use bytes::BytesMut;
+use std::{
+    io,
+    os::windows::io::{AsRawHandle, RawHandle},
+    time::{Duration, Instant},
+};
+
+struct MockHandle {}
+impl AsRawHandle for MockHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        0 as _
+    }
+}
+
+impl WakeHandle for MockHandle {}
+
+#[tokio::test]
+async fn comport_test_timeout_deadline() {
+    // Make sure the kernel wait is relative to now
+    let start = Instant::now();
+    Deadline::new(Duration::from_millis(50)).unwrap().await;
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
+
+#[tokio::test]
+async fn comport_test_timeout_read() {
+    let (task, thread) = channel::bounded(MockHandle {}, 4);
+    let mut reader = task.reader();
+    let mut buf = [0u8; 8];
+
+    // Make sure bytes already queued are returned
+    thread.push_ok(BytesMut::from("hi")).unwrap();
+    let n = reader
+        .read_timeout(&mut buf, Duration::from_millis(10))
+        .await
+        .unwrap();
+    assert_eq!(b"hi", &buf[..n]);
+
+    // Make sure a silent device times out
+    let error = reader
+        .read_timeout(&mut buf, Duration::from_millis(10))
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, error.kind());
+
+    // Make sure a partial read times out and keeps the bytes read
+    thread.push_ok(BytesMut::from("abc")).unwrap();
+    let error = reader
+        .read_exact_timeout(&mut buf[..4], Duration::from_millis(10))
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, error.kind());
+    assert_eq!(b"abc", &buf[..3]);
+
+    // Make sure an exact read completes
+    thread.push_ok(BytesMut::from("defg")).unwrap();
+    reader
+        .read_exact_timeout(&mut buf[..4], Duration::from_millis(10))
+        .await
+        .unwrap();
+    assert_eq!(b"defg", &buf[..4]);
+}
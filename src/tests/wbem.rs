@@ -0,0 +1,26 @@
+//! wbem
+use crate::{
+    hkey::PortMeta,
+    wbem::{interface_path, port_name},
+};
+
+#[test]
+fn comport_test_wbem_port_name() {
+    assert_eq!(Some("COM7"), port_name("USB Serial Device (COM7)"));
+    assert_eq!(
+        Some("COM12"),
+        port_name("Silicon Labs CP210x (COM3) Bridge (COM12)")
+    );
+    assert_eq!(None, port_name("ECP Printer Port (LPT1)"));
+    assert_eq!(None, port_name("USB Serial Device"));
+    assert_eq!(None, port_name("USB Serial Device (COM7"));
+}
+
+#[test]
+fn comport_test_wbem_interface_path() {
+    let interface = interface_path(r#"USB\VID_2FE3&PID_0100\123"#);
+    assert_eq!(r#"\\?\usb#vid_2fe3&pid_0100#123"#, interface);
+    let meta = PortMeta::parse_registry(&interface).unwrap();
+    assert_eq!("2fe3", meta.vendor);
+    assert_eq!("0100", meta.product);
+}
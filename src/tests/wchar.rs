@@ -1,4 +1,4 @@
-use crate::wchar::from_wide;
+use crate::wchar::{from_wide, from_wide_len};
 
 #[test]
 fn comport_test_wchar_arr() {
@@ -17,3 +17,34 @@ fn comport_test_wchar() {
     let term = unsafe { from_wide(s.as_ptr() as *const _) };
     assert_eq!("Unicode", term);
 }
+
+#[test]
+fn comport_test_wchar_len() {
+    // Not null terminated, and not necessarily aligned for u16
+    let s: &[u8] = b"\x00\x55\x00\x6E\x00\x69\x00";
+    let term = unsafe { from_wide_len(s[1..].as_ptr() as *const _, 3) };
+    assert_eq!("Uni", term);
+    let s: &[u16] = &[0x0055, 0x0000, 0x006E];
+    let term = unsafe { from_wide_len(s.as_ptr(), s.len()) };
+    assert_eq!("U", term);
+}
+
+#[test]
+fn comport_test_wchar_split() {
+    use crate::wchar::{split_wide, to_wide};
+    // A REG_MULTI_SZ buffer ends with an empty string
+    let mut buffer = to_wide("COM3");
+    buffer.extend(to_wide("COM10"));
+    buffer.push(0);
+    let strings: Vec<String> = split_wide(&buffer).collect();
+    assert_eq!(vec!["COM3", "COM10"], strings);
+    // A single string, and a buffer with slack after the terminator
+    let mut buffer = to_wide("STM32 Virtual ComPort");
+    buffer.extend([0; 4]);
+    assert_eq!(
+        Some("STM32 Virtual ComPort".to_string()),
+        split_wide(&buffer).next()
+    );
+    assert_eq!(None, split_wide(&[0, 0]).next());
+    assert_eq!(None, split_wide(&[]).next());
+}
@@ -0,0 +1,305 @@
+use crate::{guid, Guid, InterfaceKind, Registry, SessionEvent};
+
+#[test]
+fn comport_test_wm_interface_kind() {
+    let kind = |guid| InterfaceKind::from(Guid::from(guid));
+    assert_eq!(InterfaceKind::Serial, kind(Registry::PORTS));
+    assert_eq!(InterfaceKind::Usb, kind(Registry::USBDEVICE));
+    assert_eq!(InterfaceKind::Hid, kind(Registry::HID));
+    assert_eq!(InterfaceKind::MassStorage, kind(Registry::DISK));
+    assert_eq!(InterfaceKind::Volume, kind(Registry::VOLUME));
+    let other = guid!(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+    assert_eq!(InterfaceKind::Other, kind(other));
+}
+
+#[test]
+fn comport_test_wm_session_event() {
+    let connect = SessionEvent::Connect {
+        session: 2,
+        remote: true,
+    };
+    let disconnect = SessionEvent::Disconnect {
+        session: 1,
+        remote: false,
+    };
+    assert_eq!(Some(connect), SessionEvent::new(3, 2));
+    assert_eq!(Some(disconnect), SessionEvent::new(2, 1));
+    // A session lock is not reported
+    assert_eq!(None, SessionEvent::new(7, 1));
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_inject() {
+    use crate::{PlugEvent, PortMeta};
+    use std::time::Duration;
+    let events = Registry::new()
+        .without_initial_scan()
+        .spawn_unique()
+        .unwrap();
+    let meta = PortMeta::from(("0403", "6001"));
+    events.inject(PlugEvent::Arrival("COM9".into(), meta.clone()));
+    match events
+        .recv_timeout(Duration::from_secs(1))
+        .unwrap()
+        .unwrap()
+    {
+        PlugEvent::Arrival(port, ids) => {
+            assert_eq!("COM9", port);
+            assert_eq!(meta, ids);
+        }
+        ev => panic!("unexpected event {ev:?}"),
+    }
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_window_panic() {
+    use crate::PlugEvent;
+    use std::time::Duration;
+    let events = Registry::new()
+        .without_initial_scan()
+        .with_supervision()
+        .spawn_unique()
+        .unwrap();
+    events.inject_panic().unwrap();
+
+    // Make sure the panic did not unwind into the kernel, and the listener restarted
+    match events
+        .recv_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap()
+    {
+        PlugEvent::Restarted => {}
+        ev => panic!("unexpected event {ev:?}"),
+    }
+
+    // Make sure the restarted window still acknowledges commands
+    events.handle().pause().unwrap();
+    events.handle().resume().unwrap();
+}
+
+/// Receive events until the injected marker, and count the arrivals before it
+#[cfg(feature = "inject")]
+fn arrivals_before_marker(events: &crate::WindowEvents) -> usize {
+    use crate::PlugEvent;
+    use std::time::Duration;
+    let mut arrivals = 0;
+    loop {
+        match events
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap()
+        {
+            PlugEvent::Arrival(port, _) if port == "MARKER" => break arrivals,
+            PlugEvent::Arrival(..) => arrivals += 1,
+            ev => panic!("unexpected event {ev:?}"),
+        }
+    }
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_rescan_paused() {
+    use crate::{PausePolicy, PlugEvent, PortMeta};
+    let marker = || PlugEvent::Arrival("MARKER".into(), PortMeta::from(("0000", "0000")));
+    let expected = crate::hkey::scan().map(|ports| ports.len()).unwrap_or(0);
+
+    // Make sure a rescan while paused is dropped
+    let events = Registry::new()
+        .without_initial_scan()
+        .spawn_unique()
+        .unwrap();
+    events.handle().pause().unwrap();
+    let _ = events.rescan();
+    events.handle().resume().unwrap();
+    events.inject(marker());
+    assert_eq!(0, arrivals_before_marker(&events));
+
+    // Make sure a rescan while paused is buffered and reported on resume
+    let events = Registry::new()
+        .without_initial_scan()
+        .with_pause_policy(PausePolicy::Buffer)
+        .spawn_unique()
+        .unwrap();
+    events.handle().pause().unwrap();
+    let _ = events.rescan();
+    events.inject(marker());
+    events.handle().resume().unwrap();
+    assert_eq!(expected, arrivals_before_marker(&events));
+}
+
+/// A DEV_BROADCAST_PORT_W of the port, in a buffer aligned for the header
+#[cfg(feature = "inject")]
+fn port_broadcast(port: &str) -> Vec<u32> {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{DBT_DEVTYP_PORT, DEV_BROADCAST_PORT_W};
+    let name = crate::wchar::to_wide(port);
+    let size = std::mem::size_of::<DEV_BROADCAST_PORT_W>() + name.len() * 2;
+    let mut buffer = vec![0u32; size.div_ceil(4)];
+    unsafe {
+        let header = buffer.as_mut_ptr() as *mut DEV_BROADCAST_PORT_W;
+        (*header).dbcp_size = size as _;
+        (*header).dbcp_devicetype = DBT_DEVTYP_PORT;
+        let dst = std::ptr::addr_of_mut!((*header).dbcp_name) as *mut u16;
+        std::ptr::copy_nonoverlapping(name.as_ptr(), dst, name.len());
+    }
+    buffer
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_service_notifier() {
+    use crate::PlugEvent;
+    use std::time::Duration;
+    use windows_sys::Win32::{
+        Foundation::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR},
+        System::Services::{SERVICE_CONTROL_DEVICEEVENT, SERVICE_CONTROL_STOP},
+        UI::WindowsAndMessaging::{
+            BROADCAST_QUERY_DENY, DBT_DEVICEQUERYREMOVE, DBT_DEVICEREMOVECOMPLETE,
+        },
+    };
+    let events = Registry::new()
+        .without_initial_scan()
+        .spawn_detached_service();
+    let notifier = events.notifier();
+    let mut com9 = port_broadcast("COM9");
+    let data = com9.as_mut_ptr() as *mut std::ffi::c_void;
+    let control = |event_type| unsafe {
+        notifier.device_event(SERVICE_CONTROL_DEVICEEVENT, event_type, data)
+    };
+
+    // Make sure a port broadcast is parsed into an event
+    assert_eq!(NO_ERROR, control(DBT_DEVICEREMOVECOMPLETE));
+    match events
+        .recv_timeout(Duration::from_secs(1))
+        .unwrap()
+        .unwrap()
+    {
+        PlugEvent::RemoveComplete(port) => assert_eq!("COM9", port),
+        ev => panic!("unexpected event {ev:?}"),
+    }
+
+    // Make sure a removal is denied while a veto is held, and allowed once dropped
+    let veto = events.veto_removal("COM9");
+    assert_eq!(BROADCAST_QUERY_DENY, control(DBT_DEVICEQUERYREMOVE));
+    drop(veto);
+    assert_eq!(NO_ERROR, control(DBT_DEVICEQUERYREMOVE));
+
+    // Make sure controls which are not device events are left to the service
+    let stop = unsafe { notifier.device_event(SERVICE_CONTROL_STOP, 0, std::ptr::null_mut()) };
+    assert_eq!(ERROR_CALL_NOT_IMPLEMENTED, stop);
+    let empty = unsafe {
+        notifier.device_event(
+            SERVICE_CONTROL_DEVICEEVENT,
+            DBT_DEVICEREMOVECOMPLETE,
+            std::ptr::null_mut(),
+        )
+    };
+    assert_eq!(ERROR_CALL_NOT_IMPLEMENTED, empty);
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_service_close() {
+    use futures::{stream::FusedStream, StreamExt};
+    let mut events = Registry::new()
+        .without_initial_scan()
+        .spawn_detached_service();
+    assert!(!events.is_terminated());
+
+    // Make sure the stream ends once closed, and keeps returning None
+    events.close();
+    futures::executor::block_on(async {
+        assert!(events.next().await.is_none());
+        assert!(events.is_terminated());
+        assert!(events.next().await.is_none());
+    });
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_command_ack() {
+    use crate::Command;
+    use futures::{stream::FusedStream, StreamExt};
+    let mut events = Registry::new()
+        .without_initial_scan()
+        .spawn_unique()
+        .unwrap();
+    let handle = events.handle();
+
+    // Make sure the window acknowledges every command
+    handle.send(Command::Pause).unwrap();
+    handle.send(Command::Resume).unwrap();
+    let _ = handle.send(Command::Rescan);
+
+    // Make sure the stream ends once closed, and commands to the closed window fail
+    events.close().unwrap();
+    let error = handle.send(Command::Pause).unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotConnected, error.kind());
+    futures::executor::block_on(async {
+        while events.next().await.is_some() {}
+        assert!(events.is_terminated());
+        assert!(events.next().await.is_none());
+    });
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_initial_scan() {
+    use crate::{PlugEvent, PortMeta};
+    let marker = || PlugEvent::Arrival("MARKER".into(), PortMeta::from(("0000", "0000")));
+
+    // Make sure the connected ports are queued before any notification
+    let expected = crate::scan().map(|ports| ports.len()).unwrap_or(0);
+    let events = Registry::new().spawn_unique().unwrap();
+    events.inject(marker());
+    assert_eq!(expected, arrivals_before_marker(&events));
+
+    // Make sure nothing is queued without the initial scan
+    let events = Registry::new()
+        .without_initial_scan()
+        .spawn_unique()
+        .unwrap();
+    events.inject(marker());
+    assert_eq!(0, arrivals_before_marker(&events));
+}
+
+#[cfg(feature = "inject")]
+#[test]
+fn comport_test_wm_duplicate_notifications() {
+    use crate::{PlugEvent, PortMeta};
+    use std::time::Duration;
+    let meta = PortMeta::from(("0403", "6001"));
+    let events = Registry::new()
+        .without_initial_scan()
+        .with_devnodes_changed()
+        .spawn_unique()
+        .unwrap();
+
+    // Make sure a change the known ports already reflect is reported once
+    events.inject(PlugEvent::Arrival("COM_DUPLICATE".into(), meta.clone()));
+    events.inject(PlugEvent::Arrival("COM_DUPLICATE".into(), meta.clone()));
+    events.inject(PlugEvent::RemoveComplete("COM_DUPLICATE".into()));
+    events.inject(PlugEvent::RemoveComplete("COM_DUPLICATE".into()));
+    events.inject(PlugEvent::Arrival("MARKER".into(), meta));
+    let mut received = Vec::new();
+    loop {
+        match events
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .unwrap()
+        {
+            PlugEvent::Arrival(port, _) if port == "MARKER" => break,
+            PlugEvent::Arrival(port, _) => received.push(format!("arrival {port:?}")),
+            PlugEvent::RemoveComplete(port) => received.push(format!("removal {port:?}")),
+            ev => panic!("unexpected event {ev:?}"),
+        }
+    }
+    assert_eq!(
+        vec![
+            "arrival \"COM_DUPLICATE\"".to_string(),
+            "removal \"COM_DUPLICATE\"".to_string()
+        ],
+        received
+    );
+}
@@ -0,0 +1,128 @@
+//! timeout
+//!
+//! Reads which give up after a deadline. Silent devices otherwise leave a read pending forever.
+//! The deadline is a kernel wait from the [`crate::event`] module, so any executor may be used
+
+use crate::event::Deadline;
+use futures::{AsyncRead, Future};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Read helpers which fail with [`io::ErrorKind::TimedOut`] when the deadline elapses first
+pub trait AsyncReadTimeoutExt: AsyncRead {
+    /// Read some bytes into buf, waiting at most the duration for bytes to arrive
+    fn read_timeout<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+        duration: Duration,
+    ) -> ReadTimeout<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadTimeout {
+            reader: self,
+            buf,
+            deadline: ReadDeadline::new(duration),
+        }
+    }
+
+    /// Fill buf, waiting at most the duration for all of the bytes to arrive. Bytes read before
+    /// the deadline are left in buf
+    fn read_exact_timeout<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+        duration: Duration,
+    ) -> ReadExactTimeout<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadExactTimeout {
+            reader: self,
+            buf,
+            filled: 0,
+            deadline: ReadDeadline::new(duration),
+        }
+    }
+}
+
+impl<R: AsyncRead + ?Sized> AsyncReadTimeoutExt for R {}
+
+/// The kernel wait is only started when the read does not complete immediately
+#[derive(Debug)]
+struct ReadDeadline {
+    duration: Duration,
+    deadline: Option<Deadline>,
+}
+
+impl ReadDeadline {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            deadline: None,
+        }
+    }
+
+    /// Resolves with a time out error once the deadline has elapsed
+    fn poll_elapsed(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        let deadline = match self.deadline.as_mut() {
+            Some(deadline) => deadline,
+            None => match Deadline::new(self.duration) {
+                Ok(deadline) => self.deadline.insert(deadline),
+                Err(error) => return Poll::Ready(error),
+            },
+        };
+        Pin::new(deadline)
+            .poll(cx)
+            .map(|_| io::Error::from(io::ErrorKind::TimedOut))
+    }
+}
+
+/// Future for [`AsyncReadTimeoutExt::read_timeout`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadTimeout<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buf: &'a mut [u8],
+    deadline: ReadDeadline,
+}
+
+impl<R: AsyncRead + Unpin + ?Sized> Future for ReadTimeout<'_, R> {
+    type Output = io::Result<usize>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut *this.reader).poll_read(cx, this.buf) {
+            Poll::Ready(result) => Poll::Ready(result),
+            Poll::Pending => this.deadline.poll_elapsed(cx).map(Err),
+        }
+    }
+}
+
+/// Future for [`AsyncReadTimeoutExt::read_exact_timeout`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadExactTimeout<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buf: &'a mut [u8],
+    filled: usize,
+    deadline: ReadDeadline,
+}
+
+impl<R: AsyncRead + Unpin + ?Sized> Future for ReadExactTimeout<'_, R> {
+    type Output = io::Result<()>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while this.filled < this.buf.len() {
+            match Pin::new(&mut *this.reader).poll_read(cx, &mut this.buf[this.filled..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Poll::Ready(Ok(n)) => this.filled += n,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return this.deadline.poll_elapsed(cx).map(Err),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,126 @@
+//! wbem
+//!
+//! Scan the connected COM ports with WMI. Policy can deny a process access to the registry keys
+//! [`crate::scan`] reads, while WMI queries of `Win32_PnPEntity` and `Win32_SerialPort` are still
+//! allowed.
+
+use crate::hkey::{PortMeta, ScanResult};
+use serde::Deserialize;
+use std::{collections::HashMap, ffi::OsString};
+use tracing::{trace, warn};
+use windows_sys::Win32::Foundation::RPC_E_CHANGED_MODE;
+use wmi::{COMLibrary, WMIConnection, WMIError, WMIResult};
+
+/// The devices of the Ports class. IE: COM and LPT ports
+const QUERY: &str = "SELECT Name, Description, PNPDeviceID FROM Win32_PnPEntity \
+                     WHERE ClassGuid = '{4d36e978-e325-11ce-bfc1-08002be10318}'";
+
+/// The serial ports. IE: ports of drivers which do not name the port in the device name
+const SERIAL_QUERY: &str = "SELECT DeviceID, Name, Description, PNPDeviceID FROM Win32_SerialPort";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_PnPEntity")]
+#[serde(rename_all = "PascalCase")]
+struct PnPEntity {
+    name: Option<String>,
+    description: Option<String>,
+    #[serde(rename = "PNPDeviceID")]
+    pnp_device_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename = "Win32_SerialPort")]
+#[serde(rename_all = "PascalCase")]
+struct SerialPort {
+    /// The port name. IE: COM7
+    #[serde(rename = "DeviceID")]
+    device_id: Option<String>,
+    name: Option<String>,
+    description: Option<String>,
+    #[serde(rename = "PNPDeviceID")]
+    pnp_device_id: Option<String>,
+}
+
+/// The port name at the end of the name of a port. IE: COM7 of "USB Serial Device (COM7)"
+pub(crate) fn port_name(name: &str) -> Option<&str> {
+    let (_, port) = name.strip_suffix(')')?.rsplit_once('(')?;
+    port.starts_with("COM").then_some(port)
+}
+
+/// The interface path of a device instance, lowercase as the registry scan reports it. The
+/// instance of a device is its interface path without the prefix and class
+pub(crate) fn interface_path(instance: &str) -> String {
+    format!("\\\\?\\{}", instance.replace('\\', "#")).to_lowercase()
+}
+
+/// Initialize COM on this thread, or use COM as the thread already initialized it with another
+/// apartment model. IE: a UI thread initializes a single threaded apartment
+fn com_library() -> WMIResult<COMLibrary> {
+    match COMLibrary::new() {
+        Err(WMIError::HResultError { hres }) if hres == RPC_E_CHANGED_MODE => {
+            trace!("using the COM apartment of the thread");
+            // Safety: COM is initialized on this thread, only with another apartment model
+            Ok(unsafe { COMLibrary::assume_initialized() })
+        }
+        result => result,
+    }
+}
+
+/// Add a port described by WMI to the ports
+fn insert(
+    ports: &mut HashMap<OsString, PortMeta>,
+    port: OsString,
+    instance: &str,
+    name: Option<String>,
+    description: Option<String>,
+) {
+    match PortMeta::parse_registry(&interface_path(instance)) {
+        Some(meta) => {
+            trace!(?port, instance, "found com port");
+            ports.insert(port, meta.with_names(name, description));
+        }
+        None => warn!(?port, instance, "unable to parse device instance"),
+    }
+}
+
+/// Scan the connected COM ports with WMI
+pub fn scan() -> ScanResult<HashMap<OsString, PortMeta>> {
+    let wmi = WMIConnection::new(com_library()?)?;
+    let entities: Vec<PnPEntity> = wmi.raw_query(QUERY)?;
+    let mut ports = HashMap::new();
+    for entity in entities {
+        let (name, instance) = match (&entity.name, &entity.pnp_device_id) {
+            (Some(name), Some(instance)) => (name, instance),
+            _ => continue,
+        };
+        let port = match port_name(name) {
+            Some(port) => OsString::from(port),
+            None => continue,
+        };
+        insert(
+            &mut ports,
+            port,
+            instance,
+            entity.name.clone(),
+            entity.description.clone(),
+        );
+    }
+    // The serial ports find the ports which are not named by the device name. The ports the
+    // devices already named are kept, because they are described the same way
+    let serial: WMIResult<Vec<SerialPort>> = wmi.raw_query(SERIAL_QUERY);
+    match serial {
+        Ok(serial) => {
+            for serial in serial {
+                let (port, instance) = match (serial.device_id, &serial.pnp_device_id) {
+                    (Some(port), Some(instance)) => (OsString::from(port), instance),
+                    _ => continue,
+                };
+                if !ports.contains_key(&port) {
+                    insert(&mut ports, port, instance, serial.name, serial.description);
+                }
+            }
+        }
+        Err(error) => warn!(?error, "unable to query serial ports"),
+    }
+    Ok(ports)
+}
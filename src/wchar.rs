@@ -21,6 +21,18 @@ pub unsafe fn from_wide(ptr: *const u16) -> OsString {
     OsString::from_wide(std::slice::from_raw_parts(ptr, len))
 }
 
+/// Convert at most len u16 chars into an OsString, stopping early at a null terminator. The
+/// chars are read unaligned, so that byte buffers such as registry data may be converted directly.
+///
+/// Safety: The pointer must be valid for reads of len u16 chars
+pub unsafe fn from_wide_len(ptr: *const u16, len: usize) -> OsString {
+    let wide: Vec<u16> = (0..len)
+        .map(|n| ptr.add(n).read_unaligned())
+        .take_while(|c| *c != 0)
+        .collect();
+    OsString::from_wide(&wide)
+}
+
 #[macro_export]
 macro_rules! get_window_text {
     ($hwnd:expr, $max:expr) => {{
@@ -39,6 +51,15 @@ macro_rules! get_window_text {
     }};
 }
 
+/// The strings of a REG_MULTI_SZ style buffer, which ends with an empty string. A single string
+/// buffer yields the one string
+pub fn split_wide(buffer: &[u16]) -> impl Iterator<Item = String> + '_ {
+    buffer
+        .split(|c| *c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+}
+
 pub fn to_wide<O>(s: O) -> Vec<u16>
 where
     O: Into<OsString>,
@@ -1,33 +1,81 @@
 //! Windows Messaging
 //!
-//! This module uses a windowless window to receive Windows Messages from which to receive device
-//! notifications
+//! This module uses a message-only window to receive Windows Messages from which to receive device
+//! notifications. A message-only window is invisible, but does not receive broadcasts. IE: the
+//! DBT_DEVTYP_PORT broadcast, so every notification is registered instead.
+//!
+//! Windows services have no message pump, so a service registers its status handle instead and
+//! forwards the device events its control handler receives. See [`ServiceEvents`]
 
 use crate::{
     guid,
+    guid::Guid,
     hkey::{self, scan, PortMeta, ScanResult},
+    name::ComPortName,
     wchar::{self, from_wide, to_wide},
 };
 use crossbeam::queue::SegQueue;
-use futures::Stream;
-use parking_lot::Mutex;
+use futures::{stream::FusedStream, Stream};
+use parking_lot::{Condvar, Mutex};
 use std::{
-    cell::OnceCell,
     collections::HashMap,
-    ffi::{c_void, OsString},
+    ffi::{c_void, OsStr, OsString},
     io,
     os::windows::io::{AsRawHandle, RawHandle},
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering},
+        mpsc::{sync_channel, RecvTimeoutError, SyncSender},
+        Arc,
+    },
     task::{Context, Poll, Waker},
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 use windows_sys::{
     core::GUID,
-    Win32::{Foundation::*, System::LibraryLoader::GetModuleHandleW, UI::WindowsAndMessaging::*},
+    Win32::{
+        Foundation::*,
+        Storage::FileSystem::GetLogicalDrives,
+        System::{
+            LibraryLoader::GetModuleHandleW,
+            Power::{
+                RegisterSuspendResumeNotification, UnregisterSuspendResumeNotification,
+                HPOWERNOTIFY,
+            },
+            RemoteDesktop::{
+                WTSRegisterSessionNotification, WTSUnRegisterSessionNotification,
+                NOTIFY_FOR_THIS_SESSION, WTSSESSION_NOTIFICATION,
+            },
+            Services::{
+                SERVICE_CONTROL_DEVICEEVENT, SERVICE_CONTROL_POWEREVENT,
+                SERVICE_CONTROL_SESSIONCHANGE, SERVICE_STATUS_HANDLE,
+            },
+            Threading::{
+                GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
+                THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_IDLE,
+                THREAD_PRIORITY_LOWEST, THREAD_PRIORITY_NORMAL, THREAD_PRIORITY_TIME_CRITICAL,
+            },
+        },
+        UI::WindowsAndMessaging::*,
+    },
 };
 
+/// The priority of the thread which dispatches the messages of a listener window. See
+/// [`Registry::with_thread_priority`]
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPriority {
+    Idle = THREAD_PRIORITY_IDLE,
+    Lowest = THREAD_PRIORITY_LOWEST,
+    BelowNormal = THREAD_PRIORITY_BELOW_NORMAL,
+    Normal = THREAD_PRIORITY_NORMAL,
+    AboveNormal = THREAD_PRIORITY_ABOVE_NORMAL,
+    Highest = THREAD_PRIORITY_HIGHEST,
+    TimeCritical = THREAD_PRIORITY_TIME_CRITICAL,
+}
+
 /// A RAII guard for a window which will destroy the window when dropped
 pub struct Window(HWND);
 impl Drop for Window {
@@ -41,6 +89,14 @@ impl AsRawHandle for Window {
     }
 }
 
+/// A window owned by the application. See [`Registry::attach`]
+struct Borrowed(HWND);
+impl AsRawHandle for Borrowed {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0 as _
+    }
+}
+
 /// Device notification handles returned by
 /// [`windows_sys::Win32::UI::WindowsAndMessaging::RegisterDeviceNotificationW`] must be closed by
 /// calling the [`windows_sys::Win32::UI::WindowsAndMessaging::UnregisterDeviceNotification`]
@@ -54,17 +110,35 @@ impl Drop for RegistrationHandle {
     }
 }
 
+// Safety: a notification handle is not tied to the thread which registered it, and may be
+// unregistered from any thread
+unsafe impl Send for RegistrationHandle {}
+
+/// A RAII guard for the suspend and resume notifications of a window. A message-only window does
+/// not receive the WM_POWERBROADCAST broadcast unless registered
+struct PowerRegistration(HPOWERNOTIFY);
+impl Drop for PowerRegistration {
+    fn drop(&mut self) {
+        let _ = unsafe { UnregisterSuspendResumeNotification(self.0) };
+    }
+}
+
+/// A RAII guard for the session change notifications of a window
+struct SessionRegistration(HWND);
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        let _ = unsafe { WTSUnRegisterSessionNotification(self.0) };
+    }
+}
+
 /// Register device notifications for either a "window" or a "service". See the Flags parameter in:
 /// [https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-registerdevicenotificationw]
 #[repr(u32)]
 pub enum RecepientHandle {
     /// The message recipient parameter is a window handle
     Window(Window) = DEVICE_NOTIFY_WINDOW_HANDLE,
-    #[allow(unused)]
-    /// The message recipient parameter is a service handle
-    /// NOTE this eventually intended to support Service messages (instead of Window messages)
-    ///      when service support added we can remove the #[allow(unused)]
-    Service(isize) = DEVICE_NOTIFY_SERVICE_HANDLE,
+    /// The message recipient parameter is a service status handle
+    Service(SERVICE_STATUS_HANDLE) = DEVICE_NOTIFY_SERVICE_HANDLE,
 }
 impl RecepientHandle {
     fn discriminant(&self) -> u32 {
@@ -92,7 +166,20 @@ impl From<Window> for RecepientHandle {
 /// Register to receive device notifications for DBT_DEVTYP_DEVICE_INTERFACE or DBT_DEVTYP_HANDLE.
 /// We wrap this registration process. To extend support for other kinds of devices, see:
 /// https://learn.microsoft.com/en-us/windows-hardware/drivers/install/system-defined-device-setup-classes-available-to-vendors?redirectedfrom=MSDN
-pub struct Registry(Vec<GUID>);
+#[derive(Clone)]
+pub struct Registry {
+    guids: Vec<GUID>,
+    handles: Vec<(HANDLE, OsString)>,
+    rescan_on_resume: bool,
+    session_changes: bool,
+    devnodes_changed: bool,
+    initial_scan: bool,
+    thread_name: Option<String>,
+    thread_priority: Option<ThreadPriority>,
+    supervised: bool,
+    class_name: Option<OsString>,
+    pause_policy: PausePolicy,
+}
 impl Registry {
     /// Windows CE USB ActiveSync Devices
     pub const WCEUSBS: GUID =
@@ -101,6 +188,18 @@ impl Registry {
         guid!(0x88BAE032, 0x5A81, 0x49f0, 0xBC, 0x3D, 0xA4, 0xFF, 0x13, 0x82, 0x16, 0xD6);
     pub const PORTS: GUID =
         guid!(0x4d36e978, 0xe325, 0x11ce, 0xbf, 0xc1, 0x08, 0x00, 0x2b, 0xe1, 0x03, 0x18);
+    /// GUID_DEVINTERFACE_COMPORT. Reported as the [`PlugEvent`] of the port
+    pub const COMPORT: GUID =
+        guid!(0x86e0d1e0, 0x8089, 0x11d0, 0x9c, 0xe4, 0x08, 0x00, 0x3e, 0x30, 0x1f, 0x73);
+    /// GUID_DEVINTERFACE_HID
+    pub const HID: GUID =
+        guid!(0x4d1e55b2, 0xf16f, 0x11cf, 0x88, 0xcb, 0x00, 0x11, 0x11, 0x00, 0x00, 0x30);
+    /// GUID_DEVINTERFACE_DISK
+    pub const DISK: GUID =
+        guid!(0x53f56307, 0xb6bf, 0x11d0, 0x94, 0xf2, 0x00, 0xa0, 0xc9, 0x1e, 0xfb, 0x8b);
+    /// GUID_DEVINTERFACE_VOLUME
+    pub const VOLUME: GUID =
+        guid!(0x53f5630d, 0xb6bf, 0x11d0, 0x94, 0xf2, 0x00, 0xa0, 0xc9, 0x1e, 0xfb, 0x8b);
 
     /// Create a new registry
     pub fn new() -> Self {
@@ -109,7 +208,19 @@ impl Registry {
 
     /// Create a new registry with fixed capacity
     pub fn with_capacity(capacity: usize) -> Self {
-        Self(Vec::with_capacity(capacity))
+        Self {
+            guids: Vec::with_capacity(capacity),
+            handles: Vec::new(),
+            rescan_on_resume: false,
+            session_changes: false,
+            devnodes_changed: false,
+            initial_scan: true,
+            thread_name: None,
+            thread_priority: None,
+            supervised: false,
+            class_name: None,
+            pause_policy: PausePolicy::Drop,
+        }
     }
 
     /// Helper to add all USB serial port notifications
@@ -117,76 +228,484 @@ impl Registry {
         self.with(Registry::WCEUSBS)
             .with(Registry::USBDEVICE)
             .with(Registry::PORTS)
+            .with(Registry::COMPORT)
+    }
+
+    /// Helper to add HID notifications. Reported as [`InterfaceKind::Hid`]
+    pub fn with_hid(self) -> Self {
+        self.with(Registry::HID)
+    }
+
+    /// Helper to add disk notifications. IE: USB mass storage. Reported as
+    /// [`InterfaceKind::MassStorage`]
+    pub fn with_mass_storage(self) -> Self {
+        self.with(Registry::DISK)
+    }
+
+    /// Helper to add volume notifications. Reported as the drive letters of a
+    /// [`PlugEvent::Volume`], or as [`InterfaceKind::Volume`] if the drive letters did not
+    /// change
+    pub fn with_volume(self) -> Self {
+        self.with(Registry::VOLUME)
     }
 
     /// Add a GUID to the registration
     pub fn with(mut self, guid: GUID) -> Self {
-        self.0.push(guid);
+        self.guids.push(guid);
         self
     }
 
-    pub fn spawn<N>(self, n: N) -> WindowEvents
+    /// Receive the notifications of an open port. IE: a [`crate::ComPort`]. The query to remove
+    /// the port is only delivered to handle registrations, and the handle must be closed for the
+    /// removal to succeed. The notifications of the handle are reported for the port name
+    pub fn with_handle<P, H>(mut self, port: P, handle: &H) -> Self
+    where
+        P: Into<OsString>,
+        H: AsRawHandle,
+    {
+        self.handles
+            .push((handle.as_raw_handle() as _, port.into()));
+        self
+    }
+
+    /// Re-emit the currently connected devices when the machine resumes from suspend. Arrivals
+    /// which happen while the machine sleeps are not always delivered. A service must accept
+    /// SERVICE_ACCEPT_POWEREVENT and forward the SERVICE_CONTROL_POWEREVENT control
+    pub fn with_rescan_on_resume(mut self) -> Self {
+        self.rescan_on_resume = true;
+        self
+    }
+
+    /// Report the sessions which connect and disconnect as a [`PlugEvent::Session`]. The COM ports
+    /// a Remote Desktop session redirects come and go with the session. A service must accept
+    /// SERVICE_ACCEPT_SESSIONCHANGE and forward the SERVICE_CONTROL_SESSIONCHANGE control
+    pub fn with_session_changes(mut self) -> Self {
+        self.session_changes = true;
+        self
+    }
+
+    /// Rescan when a driver broadcasts DBT_DEVNODES_CHANGED, and report the ports which arrived
+    /// or were removed since they were last reported. Some drivers only broadcast
+    /// DBT_DEVNODES_CHANGED. The broadcast is only sent to top level windows, so the listener is a
+    /// hidden top level window instead of a message-only window. Services do not receive it
+    pub fn with_devnodes_changed(mut self) -> Self {
+        self.devnodes_changed = true;
+        self
+    }
+
+    /// Do not emit the currently connected devices when the listener starts. IE: when only future
+    /// notifications matter, or the devices were already scanned
+    pub fn without_initial_scan(mut self) -> Self {
+        self.initial_scan = false;
+        self
+    }
+
+    /// Name the thread which dispatches the messages of the listener window. The name is shown in
+    /// debuggers and in panic messages
+    pub fn with_thread_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    /// Set the priority of the thread which dispatches the messages of the listener window. IE:
+    /// to receive notifications promptly when the process is busy
+    pub fn with_thread_priority(mut self, priority: ThreadPriority) -> Self {
+        self.thread_priority = Some(priority);
+        self
+    }
+
+    /// Recreate the listener window when its message loop fails, instead of ending the stream.
+    /// The notifications are registered again, and a [`PlugEvent::Restarted`] is followed by an
+    /// arrival for every currently connected device
+    pub fn with_supervision(mut self) -> Self {
+        self.supervised = true;
+        self
+    }
+
+    /// Register the listener window with a window class of this name instead of the default
+    /// "DeviceNotifier" class. IE: when another library in the process registered the default class.
+    /// Listeners of a custom class are not found by [`crate::rescan`]
+    pub fn with_class_name<N: Into<OsString>>(mut self, name: N) -> Self {
+        self.class_name = Some(name.into());
+        self
+    }
+
+    /// What to do with the device notifications received while the listener is paused. See
+    /// [`ListenerHandle::pause`]
+    pub fn with_pause_policy(mut self, policy: PausePolicy) -> Self {
+        self.pause_policy = policy;
+        self
+    }
+
+    /// The shared queue of a listener, seeded with the currently connected devices unless
+    /// [`Self::without_initial_scan`]
+    fn queue(&self) -> SharedQueue {
+        let events = match self.initial_scan {
+            true => connected(),
+            false => Vec::new(),
+        };
+        SharedQueue {
+            rescan_on_resume: self.rescan_on_resume,
+            session_changes: self.session_changes,
+            pause_policy: self.pause_policy,
+            ports: self
+                .devnodes_changed
+                .then(|| Mutex::new(scan().unwrap_or_else(|_| HashMap::new()))),
+            handles: self.handles.iter().cloned().collect(),
+            drives: self
+                .guids
+                .iter()
+                .any(|guid| Guid::from(*guid) == Guid::from(Registry::VOLUME))
+                .then(|| Mutex::new(unsafe { GetLogicalDrives() })),
+            ..SharedQueue::with_events(events)
+        }
+    }
+
+    /// Start a thread with a listener window. Returns the error if the window could not be created
+    /// or registered
+    pub fn spawn<N>(self, n: N) -> io::Result<WindowEvents>
     where
         N: Into<OsString> + Send + Sync + 'static,
     {
         let name: OsString = n.into();
         let window = name.clone();
-        let devices = self::scan()
-            .unwrap_or_else(|_| HashMap::new())
-            .into_iter()
-            .map(|(port, meta)| PlugEvent::Arrival(port, meta))
-            .collect();
-        let ours = Arc::new(SharedQueue::with_events(devices));
+        let ours = Arc::new(self.queue());
         let theirs = Arc::clone(&ours);
-        let join_handle = std::thread::spawn(move || unsafe {
-            device_notification_window_dispatcher(name, self, Arc::into_raw(theirs) as _)
-        });
-        WindowEvents {
-            window,
-            context: ours,
-            join_handle: Some(join_handle),
+        let (ready, started) = sync_channel(1);
+        let thread = match &self.thread_name {
+            Some(name) => std::thread::Builder::new().name(name.clone()),
+            None => std::thread::Builder::new(),
+        };
+        let join_handle = thread.spawn(move || unsafe {
+            device_notification_window_dispatcher(name, self, theirs, ready)
+        })?;
+        // The dispatcher publishes the window once registered, or the error if it failed to start
+        match started.recv() {
+            Ok(Ok(())) => Ok(WindowEvents {
+                window,
+                context: ours,
+                join_handle: Some(join_handle),
+            }),
+            Ok(Err(error)) => {
+                let _ = join_handle.join();
+                Err(error)
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "window dispatcher exited before starting",
+            )),
         }
     }
 
-    /// Collect the GUID's and register them for a window handle. NOTE that this method is private
-    /// and not called directly.  The registration is expected to be passed to another thread which
-    /// starts the listener
+    /// Spawn a listener with a name unique to the process and the listener. Listeners which share
+    /// a name, in this process or another, would close and rescan each other. See
+    /// [`WindowEvents::rescan`]
+    pub fn spawn_unique(self) -> io::Result<WindowEvents> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        self.spawn(format!("comport-{}-{n}", std::process::id()))
+    }
+
+    /// Register the device notifications for a service. Unlike [`Self::spawn`] no thread is
+    /// started, the control handler of the service forwards the notifications with
+    /// [`ServiceNotifier::device_event`]
+    ///
+    /// [RegisterServiceCtrlHandlerExW](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/nf-winsvc-registerservicectrlhandlerexw)
+    /// returns the status handle
+    pub fn spawn_service(self, handle: SERVICE_STATUS_HANDLE) -> io::Result<ServiceEvents> {
+        let recipient = RecepientHandle::Service(handle);
+        let context = Arc::new(self.queue());
+        let registrations = self.register(&recipient, recipient.discriminant())?;
+        trace!(handle, "registered service device notifications");
+        Ok(ServiceEvents {
+            context,
+            registrations: Some(registrations),
+        })
+    }
+
+    /// Device notifications of a service without registering for them. IE: to test the control
+    /// handler of a service by forwarding synthetic controls to the [`ServiceNotifier`]
+    #[cfg(feature = "inject")]
+    pub fn spawn_detached_service(self) -> ServiceEvents {
+        ServiceEvents {
+            context: Arc::new(self.queue()),
+            registrations: Some(Vec::new()),
+        }
+    }
+
+    /// Register the device notifications for a window of the application. Unlike [`Self::spawn`]
+    /// no window or thread is created, the window procedure of the application parses the
+    /// notifications with [`AttachedWindow::handle_message`]. Rescans and the initial scan are not
+    /// reported
+    ///
+    /// The [`AttachedWindow`] must be dropped before the window is destroyed
+    pub fn attach(mut self, hwnd: HWND) -> io::Result<AttachedWindow> {
+        self.initial_scan = false;
+        let context = self.queue();
+        let session = match self.session_changes {
+            false => None,
+            true => {
+                match unsafe { WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION) } {
+                    FALSE => return Err(io::Error::last_os_error()),
+                    _ => Some(SessionRegistration(hwnd)),
+                }
+            }
+        };
+        let registrations = self.register(&Borrowed(hwnd), DEVICE_NOTIFY_WINDOW_HANDLE)?;
+        trace!(
+            hwnd,
+            "registered device notifications for an application window"
+        );
+        Ok(AttachedWindow {
+            _registrations: registrations,
+            _session: session,
+            context,
+        })
+    }
+
+    /// Collect the GUID's and handles and register them for a window handle. NOTE that this method
+    /// is private and not called directly.  The registration is expected to be passed to another
+    /// thread which starts the listener
     fn register<H: AsRawHandle>(self, raw: &H, kind: u32) -> io::Result<Vec<RegistrationHandle>> {
         // Safety: We initialize the DEV_BROADCAST_DEVICEINTERFACE_W header correctly before use.
-        self.0
-            .into_iter()
-            .map(|guid| {
-                let handle = unsafe {
-                    let mut iface = std::mem::zeroed::<DEV_BROADCAST_DEVICEINTERFACE_W>();
-                    iface.dbcc_size = std::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as _;
-                    iface.dbcc_classguid = guid;
-                    iface.dbcc_devicetype = DBT_DEVTYP_DEVICEINTERFACE;
-                    RegisterDeviceNotificationW(
-                        raw.as_raw_handle() as _,
-                        &iface as *const _ as _,
-                        kind,
-                    )
-                };
-                match handle.is_null() {
-                    false => Ok(RegistrationHandle(handle)),
-                    true => Err(io::Error::last_os_error()),
-                }
-            })
+        let interfaces = self.guids.into_iter().map(|guid| unsafe {
+            let mut iface = std::mem::zeroed::<DEV_BROADCAST_DEVICEINTERFACE_W>();
+            iface.dbcc_size = std::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as _;
+            iface.dbcc_classguid = guid;
+            iface.dbcc_devicetype = DBT_DEVTYP_DEVICEINTERFACE;
+            register_notification(raw, &iface as *const _ as _, kind)
+        });
+        // Safety: We initialize the DEV_BROADCAST_HANDLE header correctly before use.
+        let handles = self.handles.into_iter().map(|(handle, _)| unsafe {
+            let mut filter = std::mem::zeroed::<DEV_BROADCAST_HANDLE>();
+            filter.dbch_size = std::mem::size_of::<DEV_BROADCAST_HANDLE>() as _;
+            filter.dbch_devicetype = DBT_DEVTYP_HANDLE;
+            filter.dbch_handle = handle;
+            register_notification(raw, &filter as *const _ as _, kind)
+        });
+        interfaces
+            .chain(handles)
             .collect::<io::Result<Vec<RegistrationHandle>>>()
     }
 }
 
+/// Safety: filter must point to an initialized DEV_BROADCAST_DEVICEINTERFACE_W or
+/// DEV_BROADCAST_HANDLE
+unsafe fn register_notification<H: AsRawHandle>(
+    raw: &H,
+    filter: *const c_void,
+    kind: u32,
+) -> io::Result<RegistrationHandle> {
+    let handle = RegisterDeviceNotificationW(raw.as_raw_handle() as _, filter, kind);
+    match handle.is_null() {
+        false => Ok(RegistrationHandle(handle)),
+        true => Err(io::Error::last_os_error()),
+    }
+}
+
 #[derive(Debug)]
 #[repr(u32)]
 pub enum PlugEvent {
     Arrival(OsString, PortMeta) = DBT_DEVICEARRIVAL,
+    /// Windows asks to remove the device. IE: "Safely Remove Hardware". The removal is denied
+    /// while the port has a [`RemovalVeto`]
+    QueryRemove(OsString) = DBT_DEVICEQUERYREMOVE,
+    /// A query to remove the device was denied, and the device remains
+    QueryRemoveFailed(OsString) = DBT_DEVICEQUERYREMOVEFAILED,
     RemoveComplete(OsString) = DBT_DEVICEREMOVECOMPLETE,
+    /// A device interface of a registered class arrived or was removed. IE: a function of a
+    /// composite USB device which is not a COM port
+    Interface(InterfaceEvent) = DBT_DEVTYP_DEVICEINTERFACE,
+    /// The drive letters of a volume arrived or were removed. See [`Registry::with_volume`]
+    Volume(VolumeEvent) = DBT_DEVTYP_VOLUME,
+    /// A session connected or disconnected. See [`Registry::with_session_changes`]
+    Session(SessionEvent) = WM_WTSSESSION_CHANGE,
+    /// The listener window was recreated. The currently connected devices follow. See
+    /// [`Registry::with_supervision`]
+    Restarted,
+}
+
+impl PlugEvent {
+    /// The COM port of an arrival, removal or query to remove. None for other events, or when the
+    /// port is not named COMn
+    pub fn com_port(&self) -> Option<ComPortName> {
+        match self {
+            PlugEvent::Arrival(port, _)
+            | PlugEvent::QueryRemove(port)
+            | PlugEvent::QueryRemoveFailed(port)
+            | PlugEvent::RemoveComplete(port) => ComPortName::try_from(port.as_os_str()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A session connected or disconnected. IE: a Remote Desktop client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The session connected to the console, or remotely when remote is true
+    Connect { session: u32, remote: bool },
+    /// The session disconnected. The COM ports the session redirected are gone
+    Disconnect { session: u32, remote: bool },
+}
+
+impl SessionEvent {
+    /// Parse the WTS_* session change reason. Other reasons, IE: a lock, are not reported
+    pub(crate) fn new(reason: u32, session: u32) -> Option<SessionEvent> {
+        match reason {
+            WTS_CONSOLE_CONNECT => Some(SessionEvent::Connect {
+                session,
+                remote: false,
+            }),
+            WTS_REMOTE_CONNECT => Some(SessionEvent::Connect {
+                session,
+                remote: true,
+            }),
+            WTS_CONSOLE_DISCONNECT => Some(SessionEvent::Disconnect {
+                session,
+                remote: false,
+            }),
+            WTS_REMOTE_DISCONNECT => Some(SessionEvent::Disconnect {
+                session,
+                remote: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum VolumeEvent {
+    Arrival(Vec<char>),
+    RemoveComplete(Vec<char>),
+}
+
+#[derive(Debug, Clone)]
+pub enum InterfaceEvent {
+    Arrival(DeviceInterface),
+    RemoveComplete(DeviceInterface),
+}
+
+impl InterfaceEvent {
+    /// The device interface which arrived or was removed
+    pub fn interface(&self) -> &DeviceInterface {
+        match self {
+            Self::Arrival(interface) | Self::RemoveComplete(interface) => interface,
+        }
+    }
+}
+
+/// A device interface from a DBT_DEVTYP_DEVICEINTERFACE notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInterface {
+    /// The device interface path. IE: \\?\USB#VID_2FE3&PID_0100#...#{a5dcbf10-...}
+    pub path: OsString,
+    /// The device interface class registered with [`Registry::with`]
+    pub class: Guid,
+    /// The kind of device of a preset class
+    pub kind: InterfaceKind,
+}
+
+/// The kind of device of a device interface class. See the presets of the [`Registry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceKind {
+    /// [`Registry::WCEUSBS`], [`Registry::PORTS`] or [`Registry::COMPORT`]
+    Serial,
+    /// [`Registry::USBDEVICE`]
+    Usb,
+    /// [`Registry::HID`]
+    Hid,
+    /// [`Registry::DISK`]
+    MassStorage,
+    /// [`Registry::VOLUME`]
+    Volume,
+    /// A class without a preset
+    Other,
+}
+
+impl From<Guid> for InterfaceKind {
+    fn from(class: Guid) -> Self {
+        [
+            (Registry::WCEUSBS, InterfaceKind::Serial),
+            (Registry::PORTS, InterfaceKind::Serial),
+            (Registry::COMPORT, InterfaceKind::Serial),
+            (Registry::USBDEVICE, InterfaceKind::Usb),
+            (Registry::HID, InterfaceKind::Hid),
+            (Registry::DISK, InterfaceKind::MassStorage),
+            (Registry::VOLUME, InterfaceKind::Volume),
+        ]
+        .into_iter()
+        .find(|(guid, _)| Guid::from(*guid) == class)
+        .map_or(InterfaceKind::Other, |(_, kind)| kind)
+    }
+}
+
+/// Denies requests to remove a port while held. IE: while a transfer is in flight. See
+/// [`WindowEvents::veto_removal`]
+pub struct RemovalVeto {
+    context: Arc<SharedQueue>,
+    port: OsString,
+}
+
+impl RemovalVeto {
+    fn new(context: &Arc<SharedQueue>, port: OsString) -> RemovalVeto {
+        *context.vetoes.lock().entry(port.clone()).or_insert(0) += 1;
+        RemovalVeto {
+            context: Arc::clone(context),
+            port,
+        }
+    }
+}
+
+impl Drop for RemovalVeto {
+    fn drop(&mut self) {
+        let mut vetoes = self.context.vetoes.lock();
+        if let Some(count) = vetoes.get_mut(&self.port) {
+            *count -= 1;
+            if *count == 0 {
+                vetoes.remove(&self.port);
+            }
+        }
+    }
+}
+
+/// Why an event is delivered. A rescan reports every connected port again, so its arrivals are
+/// never dropped as duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Report {
+    Change,
+    Rescan,
 }
 
 #[derive(Default)]
 struct SharedQueue {
     queue: SegQueue<Option<ScanResult<PlugEvent>>>,
     waker: Mutex<Option<Waker>>,
+    /// Signaled with the waker for blocking receivers
+    ready: Condvar,
+    /// The end of the stream was polled
+    terminated: AtomicBool,
+    /// The listener window. Replaced when a supervised dispatcher restarts
+    hwnd: AtomicIsize,
+    /// The listener window was destroyed. See [`ListenerHandle`]
+    destroyed: AtomicBool,
+    /// Device notifications are held back. See [`Command::Pause`]
+    paused: AtomicBool,
+    /// What happens to the notifications while paused. See [`Registry::with_pause_policy`]
+    pause_policy: PausePolicy,
+    /// The notifications buffered while paused
+    held: Mutex<Vec<(ScanResult<PlugEvent>, Report)>>,
+    /// Rescan when the machine resumes from suspend. See [`Registry::with_rescan_on_resume`]
+    rescan_on_resume: bool,
+    /// Report session changes. See [`Registry::with_session_changes`]
+    session_changes: bool,
+    /// The ports last reported. See [`Registry::with_devnodes_changed`]
+    ports: Option<Mutex<HashMap<OsString, PortMeta>>>,
+    /// The number of [`RemovalVeto`] held for each port
+    vetoes: Mutex<HashMap<OsString, usize>>,
+    /// The port names of the handles registered with [`Registry::with_handle`]
+    handles: HashMap<HANDLE, OsString>,
+    /// The logical drives when volumes were last reported. See [`Registry::with_volume`]
+    drives: Option<Mutex<u32>>,
 }
 
 impl SharedQueue {
@@ -198,13 +717,28 @@ impl SharedQueue {
         SharedQueue {
             queue,
             waker: Mutex::new(None),
+            ready: Condvar::new(),
+            terminated: AtomicBool::new(false),
+            hwnd: AtomicIsize::new(0),
+            destroyed: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            pause_policy: PausePolicy::Drop,
+            held: Mutex::new(Vec::new()),
+            rescan_on_resume: false,
+            session_changes: false,
+            ports: None,
+            vetoes: Mutex::new(HashMap::new()),
+            handles: HashMap::new(),
+            drives: None,
         }
     }
 
     fn try_wake(&self) -> &Self {
-        if let Some(waker) = &self.waker.lock().as_ref() {
+        let waker = self.waker.lock();
+        if let Some(waker) = waker.as_ref() {
             waker.wake_by_ref()
         }
+        self.ready.notify_all();
         self
     }
 
@@ -214,7 +748,143 @@ impl SharedQueue {
         self
     }
 
+    /// Queue a parsed device notification. Returns true if the notification is a query to remove
+    /// a port which has a [`RemovalVeto`], and the removal must be denied
+    fn deliver(&self, ev: ScanResult<PlugEvent>) -> bool {
+        self.deliver_as(ev, Report::Change)
+    }
+
+    /// See [`Self::deliver`]
+    fn deliver_as(&self, ev: ScanResult<PlugEvent>, report: Report) -> bool {
+        let deny = match &ev {
+            Ok(PlugEvent::QueryRemove(port)) => self.vetoes.lock().contains_key(port),
+            _ => false,
+        };
+        let mut held = self.held.lock();
+        let paused = self.paused.load(Ordering::Acquire);
+        debug!(msg = ?ev, deny, paused);
+        match (paused, self.pause_policy) {
+            (false, _) => self.publish(ev, report),
+            (true, PausePolicy::Buffer) => held.push((ev, report)),
+            (true, PausePolicy::Drop) => {}
+        }
+        deny
+    }
+
+    /// Pause or resume the device notifications. Buffered notifications are published on resume
+    fn set_paused(&self, paused: bool) {
+        let mut held = self.held.lock();
+        self.paused.store(paused, Ordering::Release);
+        if !paused {
+            for (ev, report) in held.drain(..) {
+                self.publish(ev, report);
+            }
+        }
+    }
+
+    /// Queue a notification, and remember the ports it reports. A change the ports already
+    /// reflect is dropped. IE: the arrival of a port which DBT_DEVNODES_CHANGED already reported
+    fn publish(&self, ev: ScanResult<PlugEvent>, report: Report) {
+        if let Some(ports) = &self.ports {
+            let mut ports = ports.lock();
+            let duplicate = match &ev {
+                Ok(PlugEvent::Arrival(port, meta)) => {
+                    ports.insert(port.clone(), meta.clone()).is_some()
+                }
+                Ok(PlugEvent::RemoveComplete(port)) => ports.remove(port).is_none(),
+                _ => false,
+            };
+            if duplicate && report == Report::Change {
+                trace!(msg = ?ev, "dropping duplicate notification");
+                return;
+            }
+        }
+        self.try_wake_with(Some(ev));
+    }
+
+    /// Report the ports which arrived or were removed since they were last reported
+    fn devnodes_changed(&self) -> ScanResult<()> {
+        let ports = match &self.ports {
+            Some(ports) => ports,
+            None => return Ok(()),
+        };
+        let connected = hkey::scan()?;
+        let changes = {
+            let known = ports.lock();
+            let removed = known
+                .keys()
+                .filter(|port| !connected.contains_key(*port))
+                .map(|port| PlugEvent::RemoveComplete(port.clone()));
+            let arrived = connected
+                .iter()
+                .filter(|(port, _)| !known.contains_key(*port))
+                .map(|(port, meta)| PlugEvent::Arrival(port.clone(), meta.clone()));
+            removed.chain(arrived).collect::<Vec<_>>()
+        };
+        for ev in changes {
+            self.deliver(Ok(ev));
+        }
+        Ok(())
+    }
+
+    /// Report a volume interface as the drive letters which changed since the last volume
+    fn volume(&self, ev: InterfaceEvent) -> PlugEvent {
+        let drives = match &self.drives {
+            Some(drives) if ev.interface().kind == InterfaceKind::Volume => drives,
+            _ => return PlugEvent::Interface(ev),
+        };
+        let mask = unsafe { GetLogicalDrives() };
+        let last = std::mem::replace(&mut *drives.lock(), mask);
+        match ev {
+            InterfaceEvent::Arrival(_) if mask & !last != 0 => {
+                PlugEvent::Volume(VolumeEvent::Arrival(drive_letters(mask & !last)))
+            }
+            InterfaceEvent::RemoveComplete(_) if last & !mask != 0 => {
+                PlugEvent::Volume(VolumeEvent::RemoveComplete(drive_letters(last & !mask)))
+            }
+            ev => PlugEvent::Interface(ev),
+        }
+    }
+
+    /// Re-emit an arrival for every currently connected device, ordered by port number. The
+    /// arrivals are delivered like notifications, so they are held back while paused
+    fn rescan(&self) -> ScanResult<()> {
+        for (port, meta) in hkey::sorted(hkey::scan()?) {
+            self.deliver_as(Ok(PlugEvent::Arrival(port, meta)), Report::Rescan);
+        }
+        Ok(())
+    }
+
+    /// Block until an event arrives. The end of the stream is reported to every later receiver
+    fn recv_timeout(&self, timeout: Duration) -> Result<ScanResult<PlugEvent>, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut guard = self.waker.lock();
+        let mut timed_out = false;
+        loop {
+            if self.is_terminated() {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            match self.queue.pop() {
+                Some(Some(inner)) => break Ok(inner),
+                Some(None) => {
+                    self.queue.push(None);
+                    break Err(RecvTimeoutError::Disconnected);
+                }
+                None if timed_out => break Err(RecvTimeoutError::Timeout),
+                None => match deadline {
+                    Some(deadline) => {
+                        timed_out = self.ready.wait_until(&mut guard, deadline).timed_out()
+                    }
+                    None => self.ready.wait(&mut guard),
+                },
+            }
+        }
+    }
+
     fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Option<ScanResult<PlugEvent>>> {
+        if self.is_terminated() {
+            return Poll::Ready(None);
+        }
         match self.queue.pop() {
             None => {
                 let new_waker = cx.waker();
@@ -232,9 +902,16 @@ impl SharedQueue {
                 Poll::Pending
             }
             Some(Some(inner)) => Poll::Ready(Some(inner)),
-            Some(None) => Poll::Ready(None),
+            Some(None) => {
+                self.terminated.store(true, Ordering::Release);
+                Poll::Ready(None)
+            }
         }
     }
+
+    fn is_terminated(&self) -> bool {
+        self.terminated.load(Ordering::Acquire)
+    }
 }
 
 /// A stream of device notifications
@@ -245,32 +922,76 @@ pub struct WindowEvents {
 }
 
 impl WindowEvents {
+    /// Block until the next device notification arrives, or the timeout elapses
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<ScanResult<PlugEvent>, RecvTimeoutError> {
+        self.context.recv_timeout(timeout)
+    }
+
+    /// Report a synthetic device notification as if the listener received it. IE: to test a
+    /// consumer of the stream on a machine without the hardware
+    #[cfg(feature = "inject")]
+    pub fn inject(&self, event: PlugEvent) {
+        self.context.deliver(Ok(event));
+    }
+
+    /// Panic in the window proceedure of the listener, as if it hit a bug. IE: to test that a
+    /// supervised listener restarts. See [`Registry::with_supervision`]
+    #[cfg(feature = "inject")]
+    pub fn inject_panic(&self) -> io::Result<()> {
+        let hwnd = self.context.hwnd.load(Ordering::Acquire);
+        match unsafe { PostMessageW(hwnd, COMMAND_PANIC, 0, 0) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deny requests to remove the port until the veto is dropped
+    pub fn veto_removal<P: Into<OsString>>(&self, port: P) -> RemovalVeto {
+        RemovalVeto::new(&self.context, port.into())
+    }
+
+    /// The name of the listener window
+    pub fn name(&self) -> &OsStr {
+        &self.window
+    }
+
+    /// A handle to send commands to the listener window from any thread
+    pub fn handle(&self) -> ListenerHandle {
+        ListenerHandle {
+            context: Arc::clone(&self.context),
+        }
+    }
+
+    /// Re-emit the currently connected devices. Unlike [`crate::rescan`] the window is not found
+    /// by name, and the rescan never reaches another listener
+    pub fn rescan(&self) -> io::Result<()> {
+        self.handle().send(Command::Rescan)
+    }
+
+    /// Destroy the listener window and wait for its thread. The listener may be closed again if
+    /// the window did not acknowledge the close
     pub fn close(&mut self) -> io::Result<()> {
-        // Find the window so we can close it
         trace!(window = ?self.window, "closing device notification listener");
-        let wide = to_wide(self.window.clone());
-        let hwnd = unsafe {
-            let result = FindWindowW(WINDOW_CLASS_NAME, wide.as_ptr());
-            match result {
-                0 => Err(io::Error::last_os_error()),
-                hwnd => Ok(hwnd),
-            }
-        }?;
-
-        // Close the window
-        let _close = unsafe {
-            let result = PostMessageW(hwnd, WM_CLOSE, 0, 0);
-            match result {
-                0 => Err(io::Error::last_os_error()),
-                _ => Ok(()),
-            }
-        }?;
-        let jh = self
-            .join_handle
-            .take()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Already closed WindowEvents"))?;
-        jh.join()
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "join error"))?
+        if self.join_handle.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Already closed WindowEvents",
+            ));
+        }
+        match self.handle().send(Command::Close) {
+            // A destroyed window already ended its dispatcher, which is joined below
+            Err(error) if !self.context.destroyed.load(Ordering::Acquire) => return Err(error),
+            _ => {}
+        }
+        match self.join_handle.take() {
+            Some(jh) => jh
+                .join()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "join error"))?,
+            None => Ok(()),
+        }
     }
 }
 
@@ -293,6 +1014,185 @@ impl Stream for WindowEvents {
     }
 }
 
+/// The stream keeps returning `Poll::Ready(None)` once ended
+impl FusedStream for WindowEvents {
+    fn is_terminated(&self) -> bool {
+        self.context.is_terminated()
+    }
+}
+
+/// Device notifications delivered to a Windows service. The stream ends once closed
+pub struct ServiceEvents {
+    context: Arc<SharedQueue>,
+    registrations: Option<Vec<RegistrationHandle>>,
+}
+
+impl ServiceEvents {
+    /// A handle for the control handler of the service to forward device events with
+    pub fn notifier(&self) -> ServiceNotifier {
+        ServiceNotifier(Arc::clone(&self.context))
+    }
+
+    /// Block until the next device notification arrives, or the timeout elapses
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<ScanResult<PlugEvent>, RecvTimeoutError> {
+        self.context.recv_timeout(timeout)
+    }
+
+    /// Report a synthetic device notification as if the listener received it. IE: to test a
+    /// consumer of the stream on a machine without the hardware
+    #[cfg(feature = "inject")]
+    pub fn inject(&self, event: PlugEvent) {
+        self.context.deliver(Ok(event));
+    }
+
+    /// Deny requests to remove the port until the veto is dropped
+    pub fn veto_removal<P: Into<OsString>>(&self, port: P) -> RemovalVeto {
+        RemovalVeto::new(&self.context, port.into())
+    }
+
+    /// Re-emit the currently connected devices. See [`crate::rescan`]
+    pub fn rescan(&self) -> ScanResult<()> {
+        self.context.rescan()
+    }
+
+    /// Stop receiving device notifications and end the stream
+    pub fn close(&mut self) {
+        if let Some(registrations) = self.registrations.take() {
+            trace!("closing service device notifications");
+            drop(registrations);
+            self.context.try_wake_with(None);
+        }
+    }
+}
+
+impl Drop for ServiceEvents {
+    fn drop(&mut self) {
+        self.close()
+    }
+}
+
+impl Stream for ServiceEvents {
+    type Item = ScanResult<PlugEvent>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.context.poll_next(cx)
+    }
+}
+
+impl FusedStream for ServiceEvents {
+    fn is_terminated(&self) -> bool {
+        self.context.is_terminated()
+    }
+}
+
+/// Device notifications registered for a window of the application. See [`Registry::attach`]
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The registrations must be dropped before the
+/// context their notifications are parsed with
+pub struct AttachedWindow {
+    _registrations: Vec<RegistrationHandle>,
+    _session: Option<SessionRegistration>,
+    context: SharedQueue,
+}
+
+impl AttachedWindow {
+    /// Parse a message the window received. Returns the event if the message was a device
+    /// notification, otherwise the window procedure handles the message as usual
+    ///
+    /// Safety: wparam and lparam must be the parameters the window received with the message
+    pub unsafe fn handle_message(
+        &self,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> Option<PlugEvent> {
+        let parsed = match msg {
+            WM_DEVICECHANGE if lparam != 0 => parse_event(&self.context, wparam as _, lparam as _),
+            WM_WTSSESSION_CHANGE if self.context.session_changes => {
+                SessionEvent::new(wparam as _, lparam as _).map(|ev| Ok(PlugEvent::Session(ev)))
+            }
+            _ => None,
+        };
+        match parsed? {
+            Ok(ev) => Some(ev),
+            Err(error) => {
+                error!(?error, "failed to parse device notification");
+                None
+            }
+        }
+    }
+}
+
+/// Forwards the device events a service receives to its [`ServiceEvents`]
+#[derive(Clone)]
+pub struct ServiceNotifier(Arc<SharedQueue>);
+
+impl ServiceNotifier {
+    /// Forward a control from the HandlerEx callback of the service, and return the result for
+    /// the HandlerEx callback to return. IE: BROADCAST_QUERY_DENY to deny the removal of a port
+    /// with a [`RemovalVeto`], or ERROR_CALL_NOT_IMPLEMENTED if the control was not ours
+    ///
+    /// Safety: event_type and event_data must be the dwEventType and lpEventData the control
+    /// handler received with the control
+    pub unsafe fn device_event(
+        &self,
+        control: u32,
+        event_type: u32,
+        event_data: *mut c_void,
+    ) -> u32 {
+        let handled = match control {
+            SERVICE_CONTROL_POWEREVENT => self.0.rescan_on_resume && resume(&self.0, event_type),
+            SERVICE_CONTROL_SESSIONCHANGE if self.0.session_changes && !event_data.is_null() => {
+                let notification = &*(event_data as *const WTSSESSION_NOTIFICATION);
+                match SessionEvent::new(event_type, notification.dwSessionId) {
+                    Some(ev) => {
+                        self.0.deliver(Ok(PlugEvent::Session(ev)));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            SERVICE_CONTROL_DEVICEEVENT if !event_data.is_null() => {
+                match parse_event(&self.0, event_type, event_data) {
+                    Some(msg) if self.0.deliver(msg) => return BROADCAST_QUERY_DENY,
+                    Some(_) => true,
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+        match handled {
+            true => NO_ERROR,
+            false => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+}
+
+/// The currently connected devices ordered by port number, emitted when a listener starts
+fn connected() -> Vec<PlugEvent> {
+    hkey::sorted(self::scan().unwrap_or_else(|_| HashMap::new()))
+        .into_iter()
+        .map(|(port, meta)| PlugEvent::Arrival(port, meta))
+        .collect()
+}
+
+/// Rescan when the power event is a resume from suspend. PBT_APMRESUMEAUTOMATIC is broadcast on
+/// every resume, PBT_APMRESUMESUSPEND only follows it when the user is present
+fn resume(queue: &SharedQueue, event: u32) -> bool {
+    match event {
+        PBT_APMRESUMEAUTOMATIC => {
+            debug!("resumed from suspend, rescanning");
+            if let Err(error) = queue.rescan() {
+                error!(?error, "failed scan");
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
 /// Creating Windows requires the hinstance prop of the WinMain function. To retreive this
 /// parameter use [`windows_sys::Win32::System::LibraryLoader::GetModuleHandleW`];
 fn hinstance() -> isize {
@@ -301,69 +1201,203 @@ fn hinstance() -> isize {
     unsafe { GetModuleHandleW(std::ptr::null()) }
 }
 
-pub(crate) fn rescan<N>(into_name: N) -> io::Result<()>
-where
-    N: Into<OsString>,
-{
-    let name = into_name.into();
+/// Find a listener window by name. FindWindowW does not search message-only windows, and a
+/// listener is a top level window with [`Registry::with_devnodes_changed`]
+fn find_window(name: OsString) -> io::Result<HWND> {
     let wide = to_wide(name);
-    let hwnd = unsafe {
-        let result = FindWindowW(WINDOW_CLASS_NAME, wide.as_ptr());
-        match result {
-            0 => Err(io::Error::last_os_error()),
-            hwnd => Ok(hwnd),
+    let class = to_wide(WINDOW_CLASS_NAME);
+    let result = match unsafe { FindWindowExW(HWND_MESSAGE, 0, class.as_ptr(), wide.as_ptr()) } {
+        0 => unsafe { FindWindowExW(0, 0, class.as_ptr(), wide.as_ptr()) },
+        hwnd => hwnd,
+    };
+    match result {
+        0 => Err(io::Error::last_os_error()),
+        hwnd => Ok(hwnd),
+    }
+}
+
+const COMMAND_RESCAN: u32 = WM_USER;
+const COMMAND_PAUSE: u32 = WM_USER + 1;
+const COMMAND_RESUME: u32 = WM_USER + 2;
+const COMMAND_CLOSE: u32 = WM_USER + 3;
+#[cfg(feature = "inject")]
+const COMMAND_PANIC: u32 = WM_USER + 4;
+
+/// The exit code of the message loop when the window proceedure panicked
+const EXIT_PANICKED: i32 = 101;
+
+/// How long to wait for the listener window to acknowledge a command
+const COMMAND_TIMEOUT_MS: u32 = 5000;
+
+/// A command for a listener window. Every command is a distinct message in the WM_USER range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Command {
+    /// Re-emit the currently connected devices
+    Rescan = COMMAND_RESCAN,
+    /// Hold device notifications back until resumed. Removal vetoes still apply. See
+    /// [`PausePolicy`]
+    Pause = COMMAND_PAUSE,
+    /// Report device notifications again, starting with the buffered notifications
+    Resume = COMMAND_RESUME,
+    /// Destroy the listener window and end the stream
+    Close = COMMAND_CLOSE,
+}
+
+/// What a paused listener does with the device notifications it receives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PausePolicy {
+    /// Drop the notifications. Rescan after resuming to learn the current devices
+    #[default]
+    Drop,
+    /// Buffer the notifications and report them when resumed
+    Buffer,
+}
+
+/// Sends commands to a listener window. See [`WindowEvents::handle`]
+#[derive(Clone)]
+pub struct ListenerHandle {
+    context: Arc<SharedQueue>,
+}
+
+impl ListenerHandle {
+    /// Send a command and wait for the listener window to acknowledge it. Returns an error if the
+    /// window was destroyed, did not respond in time, or failed to complete the command
+    pub fn send(&self, command: Command) -> io::Result<()> {
+        if self.context.destroyed.load(Ordering::Acquire) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "listener window destroyed",
+            ));
         }
-    }?;
-    unsafe {
-        let result = PostMessageW(hwnd, WM_USER, 0, 0);
-        match result {
-            0 => Err(io::Error::last_os_error()),
+        trace!(?command, "sending listener command");
+        let mut result = 0;
+        let sent = unsafe {
+            SendMessageTimeoutW(
+                self.context.hwnd.load(Ordering::Acquire),
+                command as u32,
+                0,
+                0,
+                SMTO_ABORTIFHUNG,
+                COMMAND_TIMEOUT_MS,
+                &mut result,
+            )
+        };
+        match (sent, result) {
+            (0, _) => Err(io::Error::last_os_error()),
+            (_, 0) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("listener failed command {command:?}"),
+            )),
             _ => Ok(()),
         }
     }
+
+    /// Hold device notifications back without closing the listener. IE: during maintenance which
+    /// plugs and unplugs devices. The registrations stay in place. See [`PausePolicy`]
+    pub fn pause(&self) -> io::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    /// Report device notifications again after [`Self::pause`]
+    pub fn resume(&self) -> io::Result<()> {
+        self.send(Command::Resume)
+    }
 }
 
-/// Window proceedure for responding to windows messages and listening for device notifications
+pub(crate) fn rescan<N>(into_name: N) -> io::Result<()>
+where
+    N: Into<OsString>,
+{
+    let hwnd = find_window(into_name.into())?;
+    match unsafe { PostMessageW(hwnd, Command::Rescan as u32, 0, 0) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Window proceedure for responding to windows messages and listening for device notifications.
+/// A panic must not unwind into the kernel, so it ends the message loop instead. A supervised
+/// dispatcher then restarts the window
 unsafe extern "system" fn device_notification_window_proceedure(
     hwnd: HWND,
     msg: u32,
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
+    match std::panic::catch_unwind(|| window_proceedure(hwnd, msg, wparam, lparam)) {
+        Ok(result) => result,
+        Err(_) => {
+            error!(msg, "window proceedure panicked");
+            PostQuitMessage(EXIT_PANICKED);
+            0
+        }
+    }
+}
+
+unsafe fn window_proceedure(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const SharedQueue;
     if !ptr.is_null() {
         match msg {
-            // Safety: lparam is a DEV_BROADCAST_HDR when msg is WM_DEVICECHANGE
-            WM_DEVICECHANGE => match unsafe { parse_event(wparam as _, lparam as _) } {
-                Some(msg) => {
-                    debug!(?msg);
-                    (&*ptr).try_wake_with(Some(msg));
-                    0
+            WM_DEVICECHANGE if wparam as u32 == DBT_DEVNODES_CHANGED => {
+                debug!("received devnodes changed broadcast");
+                if let Err(error) = (&*ptr).devnodes_changed() {
+                    error!(?error, "failed scan");
                 }
-                None => DefWindowProcW(hwnd, msg, wparam, lparam),
-            },
+                TRUE as _
+            }
+            // Safety: lparam is a DEV_BROADCAST_HDR when msg is WM_DEVICECHANGE with event data
+            WM_DEVICECHANGE if lparam != 0 => {
+                match unsafe { parse_event(&*ptr, wparam as _, lparam as _) } {
+                    Some(msg) => match (&*ptr).deliver(msg) {
+                        true => BROADCAST_QUERY_DENY as _,
+                        false => TRUE as _,
+                    },
+                    None => DefWindowProcW(hwnd, msg, wparam, lparam),
+                }
+            }
             WM_DESTROY => {
                 if let Ok(window) = crate::get_window_text!(hwnd, 128) {
                     trace!(?window, "wm_destroy");
                 }
-                // NOTE we only reconstruct our arc on destroy
-                let arc = Arc::from_raw(ptr as *const SharedQueue);
-                arc.try_wake_with(None);
+                // NOTE the dispatcher owns the queue, and outlives the window
+                SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                (&*ptr).destroyed.store(true, Ordering::Release);
+                (&*ptr).try_wake_with(None);
+                PostQuitMessage(0);
                 0
             }
-            WM_USER => {
+            COMMAND_RESCAN => {
                 debug!("received scan request message");
-                match hkey::scan() {
-                    Ok(map) => {
-                        if map.len() > 0 {
-                            map.into_iter()
-                                .map(|(port, meta)| PlugEvent::Arrival(port, meta))
-                                .for_each(|ev| {
-                                    (&*ptr).try_wake_with(Some(Ok(ev)));
-                                });
-                        }
+                match (&*ptr).rescan() {
+                    Ok(_) => TRUE as _,
+                    Err(error) => {
+                        error!(?error, "failed scan");
+                        FALSE as _
                     }
-                    Err(error) => error!(?error, "failed scan"),
+                }
+            }
+            COMMAND_PAUSE | COMMAND_RESUME => {
+                let paused = msg == COMMAND_PAUSE;
+                debug!(paused, "received pause message");
+                (&*ptr).set_paused(paused);
+                TRUE as _
+            }
+            #[cfg(feature = "inject")]
+            COMMAND_PANIC => panic!("injected window proceedure panic"),
+            COMMAND_CLOSE => {
+                debug!("received close message");
+                match DestroyWindow(hwnd) {
+                    FALSE => FALSE as _,
+                    _ => TRUE as _,
+                }
+            }
+            WM_POWERBROADCAST if (&*ptr).rescan_on_resume && resume(&*ptr, wparam as _) => {
+                TRUE as _
+            }
+            WM_WTSSESSION_CHANGE if (&*ptr).session_changes => {
+                if let Some(ev) = SessionEvent::new(wparam as _, lparam as _) {
+                    (&*ptr).deliver(Ok(PlugEvent::Session(ev)));
                 }
                 0
             }
@@ -374,11 +1408,35 @@ unsafe extern "system" fn device_notification_window_proceedure(
     }
 }
 
-unsafe fn parse_event(ty: u32, data: *mut c_void) -> Option<ScanResult<PlugEvent>> {
+unsafe fn parse_event(
+    queue: &SharedQueue,
+    ty: u32,
+    data: *mut c_void,
+) -> Option<ScanResult<PlugEvent>> {
+    let broadcast = &*(data as *const DEV_BROADCAST_HDR);
+    if broadcast.dbch_devicetype == DBT_DEVTYP_DEVICEINTERFACE {
+        let comport = Guid::from(Registry::COMPORT);
+        return match parse_interface(ty, data)? {
+            // The COM port interface is reported instead of the DBT_DEVTYP_PORT broadcast
+            InterfaceEvent::Arrival(iface) if iface.class == comport => {
+                let arrival = hkey::port_name(&iface.path)
+                    .and_then(|port| Ok((hkey::scan_for(&port)?, port)))
+                    .map(|(ids, port)| PlugEvent::Arrival(port, ids));
+                Some(arrival)
+            }
+            InterfaceEvent::RemoveComplete(iface) if iface.class == comport => {
+                Some(hkey::port_name(&iface.path).map(PlugEvent::RemoveComplete))
+            }
+            ev => Some(Ok(queue.volume(ev))),
+        };
+    }
+    let port = || parse_event_data(queue, data);
     match ty {
-        DBT_DEVICEREMOVECOMPLETE => Some(Ok(PlugEvent::RemoveComplete(parse_event_data(data)?))),
+        DBT_DEVICEREMOVECOMPLETE => Some(Ok(PlugEvent::RemoveComplete(port()?))),
+        DBT_DEVICEQUERYREMOVE => Some(Ok(PlugEvent::QueryRemove(port()?))),
+        DBT_DEVICEQUERYREMOVEFAILED => Some(Ok(PlugEvent::QueryRemoveFailed(port()?))),
         DBT_DEVICEARRIVAL => {
-            let port = parse_event_data(data)?;
+            let port = port()?;
             match hkey::scan_for(&port) {
                 Ok(ids) => Some(Ok(PlugEvent::Arrival(port, ids))),
                 Err(e) => Some(Err(e)),
@@ -388,38 +1446,67 @@ unsafe fn parse_event(ty: u32, data: *mut c_void) -> Option<ScanResult<PlugEvent
     }
 }
 
-unsafe fn parse_event_data(data: *mut c_void) -> Option<OsString> {
+unsafe fn parse_interface(ty: u32, data: *mut c_void) -> Option<InterfaceEvent> {
+    let iface = &*(data as *const DEV_BROADCAST_DEVICEINTERFACE_W);
+    let class = Guid::from(iface.dbcc_classguid);
+    let interface = DeviceInterface {
+        path: wchar::from_wide(iface.dbcc_name.as_ptr()),
+        class,
+        kind: InterfaceKind::from(class),
+    };
+    match ty {
+        DBT_DEVICEARRIVAL => Some(InterfaceEvent::Arrival(interface)),
+        DBT_DEVICEREMOVECOMPLETE => Some(InterfaceEvent::RemoveComplete(interface)),
+        _ => None,
+    }
+}
+
+/// Bit 0 of a logical drive mask is drive A, bit 1 is drive B, and so on
+fn drive_letters(mask: u32) -> Vec<char> {
+    (0..26u8)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| char::from(b'A' + bit))
+        .collect()
+}
+
+unsafe fn parse_event_data(queue: &SharedQueue, data: *mut c_void) -> Option<OsString> {
     let broadcast = &mut *(data as *mut DEV_BROADCAST_HDR);
     match broadcast.dbch_devicetype {
         DBT_DEVTYP_PORT => {
             let port = &*(data as *const DEV_BROADCAST_PORT_W);
             Some(wchar::from_wide(port.dbcp_name.as_ptr()))
         }
+        DBT_DEVTYP_HANDLE => {
+            let handle = &*(data as *const DEV_BROADCAST_HANDLE);
+            queue.handles.get(&handle.dbch_handle).cloned()
+        }
         _ => None,
     }
 }
 
 /// Create an instance of a DeviceNotifier window.
 ///
-/// Safety: name must be a null terminated Wide string, and user_data must be a pointer to an
-/// Arc<SharedQueue>;
+/// Safety: name must be a null terminated Wide string, and user_data must be a pointer to a
+/// SharedQueue which outlives the window
 unsafe fn create_device_notification_window(
+    class: u16,
     name: *const u16,
+    parent: HWND,
     user_data: isize,
 ) -> io::Result<RecepientHandle> {
     let handle = CreateWindowExW(
-        WS_EX_APPWINDOW,   // styleEx
-        WINDOW_CLASS_NAME, // class name
-        name,              // window name
-        WS_MINIMIZE,       // style
-        0,                 // x
-        0,                 // y
-        CW_USEDEFAULT,     // width
-        CW_USEDEFAULT,     // hight
-        0,                 // parent
-        0,                 // menu
-        hinstance(),       // instance
-        std::ptr::null(),  // data
+        0,                // styleEx
+        class as _,       // class atom. IE: MAKEINTATOM
+        name,             // window name
+        0,                // style
+        0,                // x
+        0,                // y
+        0,                // width
+        0,                // hight
+        parent,           // parent
+        0,                // menu
+        hinstance(),      // instance
+        std::ptr::null(), // data
     );
     match handle {
         0 => Err(io::Error::last_os_error()),
@@ -444,29 +1531,64 @@ unsafe fn create_device_notification_window(
 
 /// Dispatch window messages
 ///
-/// We receive a "name", a list of GUID registrations, and some "user_data" which is an arc.
-///
-/// Safety: user_data must be a pointer to an Arc<SharedQueue> that was created
-/// by Arc::into_raw...
+/// We receive a "name", a list of GUID registrations, and the queue of the listener. The window is
+/// published to the listener once registered, or the error if the window failed to start.
 ///
-/// This method will rebuild the Arc and pass it to the window procedure...
+/// The queue is passed to the window procedure as a pointer, and outlives the window
 unsafe fn device_notification_window_dispatcher(
     name: OsString,
     registrations: Registry,
-    user_data: isize,
+    context: Arc<SharedQueue>,
+    ready: SyncSender<io::Result<()>>,
 ) -> io::Result<()> {
-    // TODO figure out how to pass atom into class name
-    let _atom = get_window_class();
-    let unsafe_name = to_wide(name.clone());
-    let arc = Arc::from_raw(user_data as *const Arc<SharedQueue>);
     trace!(?name, "starting window dispatcher");
-    let hwnd = create_device_notification_window(unsafe_name.as_ptr(), Arc::as_ptr(&arc) as _)?;
-    // Register the device notifications
-    let _registry = registrations.register(&hwnd, hwnd.discriminant())?;
+    let mut listener = match start_window(&name, registrations.clone(), &context) {
+        Ok(started) => started,
+        Err(error) => {
+            error!(?name, ?error, "window dispatcher failed to start");
+            let _ = ready.send(Err(error));
+            return Ok(());
+        }
+    };
+    let _ = ready.send(Ok(()));
+    loop {
+        let result = dispatch(&name);
+        if !registrations.supervised || context.destroyed.load(Ordering::Acquire) {
+            break result;
+        }
+        // The message loop ended without the window being closed. Detach the queue so the stream
+        // does not end when the window is destroyed, and start over
+        warn!(?name, ?result, "window dispatcher stopped, restarting");
+        SetWindowLongPtrW(listener.hwnd.as_raw_handle() as _, GWLP_USERDATA, 0);
+        drop(listener);
+        listener = match start_window(&name, registrations.clone(), &context) {
+            Ok(started) => started,
+            Err(error) => {
+                error!(?name, ?error, "window dispatcher failed to restart");
+                context.destroyed.store(true, Ordering::Release);
+                context.try_wake_with(None);
+                break Err(error);
+            }
+        };
+        context.deliver(Ok(PlugEvent::Restarted));
+        if let Err(error) = context.rescan() {
+            error!(?error, "failed scan");
+        }
+    }
+}
 
+/// Run the message loop of the thread until the window is destroyed, or the loop fails
+unsafe fn dispatch(name: &OsString) -> io::Result<()> {
     let mut msg: MSG = std::mem::zeroed();
     loop {
         match GetMessageW(&mut msg as *mut _, 0, 0, 0) {
+            0 if msg.wParam == EXIT_PANICKED as WPARAM => {
+                error!(?name, "window dispatcher stopped by a panic");
+                break Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "window proceedure panicked",
+                ));
+            }
             0 => {
                 trace!(?name, "window dispatcher finished");
                 break Ok(());
@@ -490,31 +1612,114 @@ unsafe fn device_notification_window_dispatcher(
     }
 }
 
+/// A listener window and its notification registrations
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The registrations must be dropped before the
+/// window is destroyed
+struct Listener {
+    _registry: Vec<RegistrationHandle>,
+    _session: Option<SessionRegistration>,
+    _power: Option<PowerRegistration>,
+    hwnd: RecepientHandle,
+}
+
+/// Create the listener window and register its notifications
+///
+/// Safety: the queue must outlive the window
+unsafe fn start_window(
+    name: &OsString,
+    registrations: Registry,
+    context: &Arc<SharedQueue>,
+) -> io::Result<Listener> {
+    if let Some(priority) = registrations.thread_priority {
+        if SetThreadPriority(GetCurrentThread(), priority as _) == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let class = match &registrations.class_name {
+        Some(class) => window_class(class)?,
+        None => window_class(OsStr::new(WINDOW_CLASS_NAME))?,
+    };
+    let unsafe_name = to_wide(name.clone());
+    // Broadcasts are not sent to message-only windows
+    let parent = match registrations.devnodes_changed {
+        false => HWND_MESSAGE,
+        true => 0,
+    };
+    let hwnd = create_device_notification_window(
+        class,
+        unsafe_name.as_ptr(),
+        parent,
+        Arc::as_ptr(context) as _,
+    )?;
+    // Register the device notifications
+    let power = match registrations.rescan_on_resume {
+        false => None,
+        true => match RegisterSuspendResumeNotification(
+            hwnd.as_raw_handle() as _,
+            DEVICE_NOTIFY_WINDOW_HANDLE,
+        ) {
+            0 => return Err(io::Error::last_os_error()),
+            handle => Some(PowerRegistration(handle)),
+        },
+    };
+    let session = match registrations.session_changes {
+        false => None,
+        true => {
+            match WTSRegisterSessionNotification(hwnd.as_raw_handle() as _, NOTIFY_FOR_THIS_SESSION)
+            {
+                FALSE => return Err(io::Error::last_os_error()),
+                _ => Some(SessionRegistration(hwnd.as_raw_handle() as _)),
+            }
+        }
+    };
+    let registry = registrations.register(&hwnd, hwnd.discriminant())?;
+    context
+        .hwnd
+        .store(hwnd.as_raw_handle() as _, Ordering::Release);
+    Ok(Listener {
+        _registry: registry,
+        _session: session,
+        _power: power,
+        hwnd,
+    })
+}
+
 /// The name of our window class.
 /// [See also](https://learn.microsoft.com/en-us/windows/win32/winmsg/about-window-classes)
-const WINDOW_CLASS_NAME: *const u16 = windows_sys::w!("DeviceNotifier");
-
-/// We register our class only once
-const WINDOW_CLASS_ATOM: OnceCell<u16> = OnceCell::new();
-fn get_window_class() -> u16 {
-    *WINDOW_CLASS_ATOM.get_or_init(|| {
-        let class = WNDCLASSEXW {
-            style: 0,
-            hIcon: 0,
-            cbSize: std::mem::size_of::<WNDCLASSEXW>() as _,
-            hIconSm: 0,
-            hCursor: 0,
-            cbClsExtra: 0,
-            cbWndExtra: 0,
-            hInstance: hinstance(),
-            lpszMenuName: std::ptr::null(),
-            lpszClassName: WINDOW_CLASS_NAME,
-            lpfnWndProc: Some(device_notification_window_proceedure),
-            hbrBackground: 0,
-        };
-        match unsafe { RegisterClassExW(&class as *const _) } {
-            0 => panic!("{:?}", io::Error::last_os_error()),
-            atom => atom,
+const WINDOW_CLASS_NAME: &str = "DeviceNotifier";
+
+/// The atoms of the window classes registered by this process. A class is registered once, by the
+/// first listener which uses it
+static WINDOW_CLASSES: Mutex<Vec<(OsString, u16)>> = Mutex::new(Vec::new());
+
+/// Get the atom of a window class, and register the class if this process has not yet
+fn window_class(name: &OsStr) -> io::Result<u16> {
+    let mut classes = WINDOW_CLASSES.lock();
+    if let Some((_, atom)) = classes.iter().find(|(class, _)| class == name) {
+        return Ok(*atom);
+    }
+    let wide = to_wide(name);
+    let class = WNDCLASSEXW {
+        style: 0,
+        hIcon: 0,
+        cbSize: std::mem::size_of::<WNDCLASSEXW>() as _,
+        hIconSm: 0,
+        hCursor: 0,
+        cbClsExtra: 0,
+        cbWndExtra: 0,
+        hInstance: hinstance(),
+        lpszMenuName: std::ptr::null(),
+        lpszClassName: wide.as_ptr(),
+        lpfnWndProc: Some(device_notification_window_proceedure),
+        hbrBackground: 0,
+    };
+    match unsafe { RegisterClassExW(&class as *const _) } {
+        0 => Err(io::Error::last_os_error()),
+        atom => {
+            trace!(class = ?name, atom, "registered window class");
+            classes.push((name.to_os_string(), atom));
+            Ok(atom)
         }
-    })
+    }
 }